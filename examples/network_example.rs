@@ -2,7 +2,6 @@ use proof_of_stake::network::{NetworkConfig, NetworkService};
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::{info, Level};
-use tracing_subscriber;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -14,7 +13,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Starting network example");
 
     // Create three nodes for local testing
-    let node_configs = vec![
+    let node_configs = [
         NetworkConfig::local_node(0), // Port 9000
         NetworkConfig::local_node(1), // Port 9001
         NetworkConfig::local_node(2), // Port 9002
@@ -23,7 +22,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut handles = Vec::new();
 
     // Start the first node
-    let (service1, mut handle1) = NetworkService::new(node_configs[0].clone()).unwrap();
+    let (service1, handle1) = NetworkService::new(node_configs[0].clone()).unwrap();
     let handle1_clone = tokio::spawn(async move {
         if let Err(e) = service1.run().await {
             eprintln!("Node 1 error: {}", e);
@@ -0,0 +1,278 @@
+// API events - chain, transaction and validator events for WebSocket
+// subscribers
+
+use super::handlers::{to_hex, transaction_json};
+use super::{parse_address, parse_hash, ApiError};
+use crate::consensus::ConsensusEngine;
+use crate::storage::TransactionLocation;
+use crate::types::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+/// Events a subscriber may fall behind by before it misses some
+pub const EVENT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiEvent {
+    /// The canonical head moved to this block
+    NewHead(Box<Block>),
+    /// The canonical chain switched branches. `depth` is the number of
+    /// blocks that left the canonical chain
+    Reorg {
+        old_head: Hash,
+        new_head: Hash,
+        depth: u64,
+    },
+    FinalizedCheckpoint(Checkpoint),
+    /// A transaction entered the pending set
+    PendingTransaction(Box<Transaction>),
+    /// A transaction was included in a newly canonical block
+    TransactionIncluded {
+        hash: Hash,
+        location: TransactionLocation,
+    },
+    ValidatorStatusChanged {
+        address: Address,
+        previous: Option<ValidatorStatus>,
+        status: ValidatorStatus,
+    },
+}
+
+impl ApiEvent {
+    /// Value of the `type` field of the event message
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiEvent::NewHead(_) => "block",
+            ApiEvent::Reorg { .. } => "reorg",
+            ApiEvent::FinalizedCheckpoint(_) => "finalized_checkpoint",
+            ApiEvent::PendingTransaction(_) => "pending_transaction",
+            ApiEvent::TransactionIncluded { .. } => "transaction_included",
+            ApiEvent::ValidatorStatusChanged { .. } => "validator_status",
+        }
+    }
+
+    pub fn data(&self) -> Value {
+        match self {
+            ApiEvent::NewHead(block) => json!({
+                "hash": to_hex(&block.hash()),
+                "height": block.header.height,
+                "slot": block.header.slot,
+                "epoch": block.header.epoch,
+                "proposer": to_hex(&block.header.proposer.0),
+                "timestamp": block.header.timestamp,
+                "transaction_count": block.transactions.len(),
+            }),
+            ApiEvent::Reorg { old_head, new_head, depth } => json!({
+                "old_head": to_hex(old_head),
+                "new_head": to_hex(new_head),
+                "depth": depth,
+            }),
+            ApiEvent::FinalizedCheckpoint(checkpoint) => json!({
+                "epoch": checkpoint.epoch,
+                "root": to_hex(&checkpoint.root),
+            }),
+            ApiEvent::PendingTransaction(transaction) => transaction_json(transaction, None),
+            ApiEvent::TransactionIncluded { hash, location } => json!({
+                "hash": to_hex(hash),
+                "block_hash": to_hex(&location.block_hash),
+                "block_height": location.block_height,
+                "index": location.index,
+            }),
+            ApiEvent::ValidatorStatusChanged { address, previous, status } => json!({
+                "address": to_hex(&address.0),
+                "previous_status": previous,
+                "status": status,
+            }),
+        }
+    }
+}
+
+/// What a WebSocket subscription delivers
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subscription {
+    Blocks,
+    Reorgs,
+    Finality,
+    /// Pending transactions, optionally only those from or to an address
+    Transactions { address: Option<Address> },
+    /// Inclusion of one transaction
+    TransactionInclusion(Hash),
+    /// Validator status changes, optionally for one validator
+    Validators { address: Option<Address> },
+}
+
+impl Subscription {
+    /// Parse the `params` of a subscribe message
+    pub fn from_params(params: &Value) -> Result<Self, ApiError> {
+        let address = match params["filter"]["address"].as_str() {
+            Some(address) => Some(parse_address(address)?),
+            None => None,
+        };
+        match params["type"].as_str() {
+            Some("blocks") => Ok(Subscription::Blocks),
+            Some("reorgs") => Ok(Subscription::Reorgs),
+            Some("finality") => Ok(Subscription::Finality),
+            Some("transactions") => Ok(Subscription::Transactions { address }),
+            Some("transaction_inclusion") => {
+                let hash = params["hash"]
+                    .as_str()
+                    .ok_or_else(|| ApiError::invalid_parameter("transaction_inclusion needs a hash"))?;
+                Ok(Subscription::TransactionInclusion(parse_hash(hash, "Transaction hash")?))
+            }
+            Some("validators") => Ok(Subscription::Validators { address }),
+            Some(other) => Err(ApiError::invalid_parameter(format!("Unknown subscription type {}", other))),
+            None => Err(ApiError::invalid_parameter("Subscription type is missing")),
+        }
+    }
+
+    pub fn matches(&self, event: &ApiEvent) -> bool {
+        match (self, event) {
+            (Subscription::Blocks, ApiEvent::NewHead(_)) => true,
+            (Subscription::Reorgs, ApiEvent::Reorg { .. }) => true,
+            (Subscription::Finality, ApiEvent::FinalizedCheckpoint(_)) => true,
+            (Subscription::Transactions { address }, ApiEvent::PendingTransaction(transaction)) => {
+                address.is_none_or(|address| transaction.from == address || transaction.to == address)
+            }
+            (Subscription::TransactionInclusion(wanted), ApiEvent::TransactionIncluded { hash, .. }) => wanted == hash,
+            (Subscription::Validators { address: filter }, ApiEvent::ValidatorStatusChanged { address, .. }) => {
+                filter.is_none_or(|filter| filter == *address)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Publishes events to API subscribers, and tracks the finalized
+/// checkpoint and validator statuses to notice when they change
+#[derive(Debug)]
+pub struct EventPublisher {
+    sender: broadcast::Sender<ApiEvent>,
+    finalized: Option<Checkpoint>,
+    validator_statuses: HashMap<Address, ValidatorStatus>,
+}
+
+impl Default for EventPublisher {
+    fn default() -> Self {
+        Self::new(EVENT_BUFFER)
+    }
+}
+
+impl EventPublisher {
+    /// Each subscriber buffers at most `capacity` events
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+            finalized: None,
+            validator_statuses: HashMap::new(),
+        }
+    }
+
+    /// Sender handed to the API server to subscribe connections
+    pub fn sender(&self) -> broadcast::Sender<ApiEvent> {
+        self.sender.clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ApiEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, event: ApiEvent) {
+        // Nobody listening is not an error
+        let _ = self.sender.send(event);
+    }
+
+    /// Take the current finalized checkpoint and validator statuses as
+    /// known, without publishing them
+    pub fn reset(&mut self, consensus: &ConsensusEngine) {
+        self.finalized = Some(consensus.fork_choice.finalized_checkpoint.clone());
+        self.validator_statuses = consensus.validator_set
+            .validators
+            .iter()
+            .map(|(address, validator)| (*address, validator.status.clone()))
+            .collect();
+    }
+
+    /// Publish finality and validator status changes since the last call
+    pub fn observe(&mut self, consensus: &ConsensusEngine) {
+        let finalized = &consensus.fork_choice.finalized_checkpoint;
+        if self.finalized.as_ref() != Some(finalized) {
+            self.finalized = Some(finalized.clone());
+            self.publish(ApiEvent::FinalizedCheckpoint(finalized.clone()));
+        }
+
+        let mut changes: Vec<_> = consensus.validator_set
+            .validators
+            .iter()
+            .filter(|(address, validator)| self.validator_statuses.get(*address) != Some(&validator.status))
+            .map(|(address, validator)| (*address, validator.status.clone()))
+            .collect();
+        changes.sort_by_key(|(address, _)| address.0);
+        for (address, status) in changes {
+            let previous = self.validator_statuses.insert(address, status.clone());
+            self.publish(ApiEvent::ValidatorStatusChanged { address, previous, status });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscriptions_filter_events() {
+        let transaction = Transaction::new(Address([1u8; 32]), Address([2u8; 32]), 10, 21000, 1, 0, Vec::new());
+        let pending = ApiEvent::PendingTransaction(Box::new(transaction.clone()));
+
+        let params = json!({ "type": "transactions", "filter": { "address": to_hex(&[2u8; 32]) } });
+        assert!(Subscription::from_params(&params).unwrap().matches(&pending));
+        let params = json!({ "type": "transactions", "filter": { "address": to_hex(&[3u8; 32]) } });
+        assert!(!Subscription::from_params(&params).unwrap().matches(&pending));
+        assert!(!Subscription::Blocks.matches(&pending));
+
+        let included = ApiEvent::TransactionIncluded {
+            hash: transaction.hash(),
+            location: TransactionLocation { block_hash: [4u8; 32], block_height: 4, index: 0 },
+        };
+        let params = json!({ "type": "transaction_inclusion", "hash": to_hex(&transaction.hash()) });
+        assert!(Subscription::from_params(&params).unwrap().matches(&included));
+        assert!(!Subscription::TransactionInclusion([0u8; 32]).matches(&included));
+
+        assert!(Subscription::from_params(&json!({ "type": "transaction_inclusion" })).is_err());
+        assert!(Subscription::from_params(&json!({ "type": "mempool" })).is_err());
+    }
+
+    #[test]
+    fn test_publisher_reports_finality_and_status_changes() {
+        let validator = Validator::new(
+            Address([5u8; 32]),
+            [5u8; 32],
+            32_000_000_000,
+            500,
+            0,
+            ValidatorMetadata { name: "v".to_string(), website: None, description: None, contact: None },
+        );
+        let mut consensus = ConsensusEngine::new(Default::default(), vec![validator]).unwrap();
+        let mut publisher = EventPublisher::new(8);
+        let mut events = publisher.subscribe();
+
+        publisher.reset(&consensus);
+        publisher.observe(&consensus);
+        assert!(events.try_recv().is_err());
+
+        consensus.validator_set.validators.get_mut(&Address([5u8; 32])).unwrap().status = ValidatorStatus::Jailed;
+        consensus.fork_choice.finalized_checkpoint = Checkpoint { epoch: 2, root: [6u8; 32] };
+        publisher.observe(&consensus);
+        assert_eq!(events.try_recv().unwrap(), ApiEvent::FinalizedCheckpoint(Checkpoint { epoch: 2, root: [6u8; 32] }));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ApiEvent::ValidatorStatusChanged {
+                address: Address([5u8; 32]),
+                previous: Some(ValidatorStatus::Active),
+                status: ValidatorStatus::Jailed,
+            }
+        );
+        publisher.observe(&consensus);
+        assert!(events.try_recv().is_err());
+    }
+}
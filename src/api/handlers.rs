@@ -0,0 +1,341 @@
+// API handlers - answer API requests from the node's storage, consensus
+// state and network
+
+use super::{ApiError, ApiRequest, ErrorCode};
+use crate::network::NetworkHandle;
+use crate::storage::TransactionLocation;
+use crate::types::*;
+use crate::validator::{AdminCommand, KeyInfo};
+use crate::{Node, BLOCK_GAS_LIMIT};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+use tracing::debug;
+
+/// `0x`-prefixed hex, as used for hashes, addresses and keys in responses
+pub fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+pub fn block_json(block: &Block) -> Value {
+    let header = &block.header;
+    json!({
+        "hash": to_hex(&block.hash()),
+        "header": {
+            "height": header.height,
+            "previous_hash": to_hex(&header.previous_hash),
+            "merkle_root": to_hex(&header.merkle_root),
+            "state_root": to_hex(&header.state_root),
+            "timestamp": header.timestamp,
+            "slot": header.slot,
+            "epoch": header.epoch,
+            "proposer": to_hex(&header.proposer.0),
+            "gas_limit": header.gas_limit,
+            "gas_used": header.gas_used,
+        },
+        "transactions": block.transactions
+            .iter()
+            .map(|transaction| transaction_json(transaction, None))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Transaction fields, plus where it was included when `location` is known
+pub fn transaction_json(transaction: &Transaction, location: Option<&TransactionLocation>) -> Value {
+    let mut value = json!({
+        "hash": to_hex(&transaction.hash()),
+        "from": to_hex(&transaction.from.0),
+        "to": to_hex(&transaction.to.0),
+        "amount": transaction.amount.to_string(),
+        "gas_limit": transaction.gas_limit,
+        "gas_price": transaction.gas_price.to_string(),
+        "nonce": transaction.nonce,
+        "timestamp": transaction.timestamp,
+    });
+    if let Some(location) = location {
+        value["block_hash"] = json!(to_hex(&location.block_hash));
+        value["block_height"] = json!(location.block_height);
+        value["index"] = json!(location.index);
+        value["status"] = json!("success");
+    }
+    value
+}
+
+pub fn validator_json(validator: &Validator) -> Value {
+    json!({
+        "address": to_hex(&validator.address.0),
+        "public_key": to_hex(&validator.public_key),
+        "stake": validator.stake.to_string(),
+        "delegated_stake": validator.delegated_stake.to_string(),
+        "commission_rate": validator.commission_rate,
+        "status": validator.status,
+        "registration_epoch": validator.registration_epoch,
+        "last_active_epoch": validator.last_active_epoch,
+        "metadata": validator.metadata,
+        "performance": {
+            "blocks_proposed": validator.performance.blocks_proposed,
+            "blocks_missed": validator.performance.blocks_missed,
+            "attestations_made": validator.performance.attestations_made,
+            "attestations_missed": validator.performance.attestations_missed,
+            "uptime_ratio": validator.uptime_ratio(),
+        },
+    })
+}
+
+fn key_info_json(key: &KeyInfo) -> Value {
+    json!({
+        "address": to_hex(&key.address.0),
+        "public_key": format!("0x{}", key.public_key),
+        "status": key.status,
+        "validator_index": key.validator_index,
+        "keystore": key.keystore.as_ref().map(|path| path.display().to_string()),
+    })
+}
+
+fn checkpoint_json(checkpoint: &Checkpoint) -> Value {
+    json!({
+        "epoch": checkpoint.epoch,
+        "root": to_hex(&checkpoint.root),
+    })
+}
+
+fn storage_error(e: Box<dyn std::error::Error>) -> ApiError {
+    ApiError::internal(format!("Storage error: {}", e))
+}
+
+impl Node {
+    /// Answer an API request from node state
+    pub(crate) async fn handle_api_request(&mut self, network: &NetworkHandle, request: ApiRequest) -> Result<Value, ApiError> {
+        match request {
+            ApiRequest::Head => {
+                let head = self.get_head()
+                    .and_then(|head| self.consensus.fork_choice.get_block(&head))
+                    .ok_or_else(|| ApiError::not_found("No blocks have been imported yet"))?;
+                let header = &head.header;
+                Ok(json!({
+                    "hash": to_hex(&head.hash()),
+                    "height": header.height,
+                    "slot": header.slot,
+                    "epoch": header.epoch,
+                    "proposer": to_hex(&header.proposer.0),
+                    "timestamp": header.timestamp,
+                    "transaction_count": head.transactions.len(),
+                    "gas_used": header.gas_used,
+                    "gas_limit": header.gas_limit,
+                }))
+            }
+            ApiRequest::BlockByHash(hash) => {
+                let block = match self.storage.get_block(&hash).await.map_err(storage_error)? {
+                    Some(block) => Some(block),
+                    None => self.consensus.fork_choice.get_block(&hash).cloned(),
+                };
+                block
+                    .map(|block| block_json(&block))
+                    .ok_or_else(|| ApiError::not_found(format!("Block {} not found", to_hex(&hash))))
+            }
+            ApiRequest::BlockByHeight(height) => self.storage
+                .get_block_by_height(height)
+                .await
+                .map_err(storage_error)?
+                .map(|block| block_json(&block))
+                .ok_or_else(|| ApiError::not_found(format!("No block at height {}", height))),
+            ApiRequest::Transaction(hash) => {
+                if let Some((transaction, location)) = self.storage.get_transaction(&hash).await.map_err(storage_error)? {
+                    return Ok(transaction_json(&transaction, Some(&location)));
+                }
+                let pending = self.pending_transactions
+                    .get(&hash)
+                    .ok_or_else(|| ApiError::not_found(format!("Transaction {} not found", to_hex(&hash))))?;
+                let mut value = transaction_json(&pending.transaction, None);
+                value["status"] = json!("pending");
+                Ok(value)
+            }
+            ApiRequest::SubmitTransaction(transaction) => self.submit_transaction(network, *transaction).await,
+            ApiRequest::Balance(address) => {
+                let account = self.storage.get_account(&address).await.map_err(storage_error)?;
+                let (balance, nonce, is_contract) = account
+                    .map(|account| (account.balance, account.nonce, !account.code.is_empty()))
+                    .unwrap_or_default();
+                Ok(json!({
+                    "address": to_hex(&address.0),
+                    "balance": balance.to_string(),
+                    "nonce": nonce,
+                    "is_contract": is_contract,
+                }))
+            }
+            ApiRequest::AccountProof(address) => {
+                // Stored accounts and the state tree follow the canonical head
+                let height = self.storage.get_latest_height().await.map_err(storage_error)?;
+                let head = self.storage
+                    .get_canonical_hash(height)
+                    .await
+                    .map_err(storage_error)?
+                    .ok_or_else(|| ApiError::not_found("No blocks have been stored yet"))?;
+                let account = self.storage.get_account(&address).await.map_err(storage_error)?;
+                let proof = self.storage.account_proof(&address);
+                Ok(json!({
+                    "address": to_hex(&address.0),
+                    "block_hash": to_hex(&head),
+                    "block_height": height,
+                    "state_root": to_hex(&self.storage.state_root()),
+                    "account": account.map(|account| json!({
+                        "balance": account.balance.to_string(),
+                        "nonce": account.nonce,
+                    })),
+                    "proof": {
+                        "key": to_hex(&proof.key),
+                        "value": proof.value.map(|value| to_hex(&value)),
+                        "bitmap": to_hex(&proof.bitmap),
+                        "siblings": proof.siblings.iter().map(|sibling| to_hex(sibling)).collect::<Vec<_>>(),
+                    },
+                }))
+            }
+            ApiRequest::AccountTransactions { address, limit, offset } => {
+                let (transactions, total) = self.storage
+                    .get_account_transactions(&address, offset, limit)
+                    .await
+                    .map_err(storage_error)?;
+                Ok(json!({
+                    "transactions": transactions
+                        .iter()
+                        .map(|(transaction, location)| transaction_json(transaction, Some(location)))
+                        .collect::<Vec<_>>(),
+                    "total": total,
+                    "limit": limit,
+                    "offset": offset,
+                }))
+            }
+            ApiRequest::Validator(address) => self.consensus.validator_set
+                .validators
+                .get(&address)
+                .map(validator_json)
+                .ok_or_else(|| ApiError::new(ErrorCode::ValidatorNotFound, format!("Validator {} not found", to_hex(&address.0)))),
+            ApiRequest::Validators { status, limit, offset } => {
+                let mut validators: Vec<&Validator> = self.consensus.validator_set
+                    .validators
+                    .values()
+                    .filter(|validator| status.as_ref().is_none_or(|status| validator.status == *status))
+                    .collect();
+                validators.sort_by_key(|validator| validator.address.0);
+                Ok(json!({
+                    "validators": validators
+                        .iter()
+                        .skip(offset)
+                        .take(limit)
+                        .map(|validator| validator_json(validator))
+                        .collect::<Vec<_>>(),
+                    "total": validators.len(),
+                    "limit": limit,
+                    "offset": offset,
+                }))
+            }
+            ApiRequest::Peers => {
+                let peers = network
+                    .get_peers()
+                    .await
+                    .map_err(|e| ApiError::new(ErrorCode::NetworkError, e.to_string()))?;
+                Ok(json!({
+                    "peers": peers
+                        .iter()
+                        .map(|peer| json!({
+                            "id": peer.peer_id.to_string(),
+                            "addresses": peer.addresses.iter().map(|addr| addr.to_string()).collect::<Vec<_>>(),
+                            "status": format!("{:?}", peer.status),
+                            "last_seen": peer.last_seen,
+                            "connection_count": peer.connection_count,
+                            "failed_connections": peer.failed_connections,
+                            "avg_rtt": peer.avg_rtt.map(|rtt| rtt.as_millis() as u64),
+                            "latest_rtt": peer.latest_rtt.map(|rtt| rtt.as_millis() as u64),
+                            "reputation": peer.reputation,
+                            "protocol_version": peer.protocol_version,
+                            "agent_version": peer.agent_version,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "total_peers": peers.len(),
+                    "max_peers": self.config.network.max_connections,
+                }))
+            }
+            ApiRequest::ConsensusState => Ok(json!({
+                "current_epoch": self.consensus.current_epoch,
+                "current_slot": self.consensus.current_slot,
+                "justified_checkpoint": checkpoint_json(&self.consensus.fork_choice.justified_checkpoint),
+                "finalized_checkpoint": checkpoint_json(&self.consensus.fork_choice.finalized_checkpoint),
+                "fork_choice_head": self.get_head().map(|head| to_hex(&head)),
+            })),
+            ApiRequest::AdminListKeys => {
+                let keys = self.admin_command(|response| AdminCommand::ListKeys { response }).await?;
+                Ok(json!({
+                    "keys": keys.iter().map(key_info_json).collect::<Vec<_>>(),
+                    "total": keys.len(),
+                }))
+            }
+            ApiRequest::AdminAddKey { private_key } => self
+                .admin_command(|response| AdminCommand::AddKey { private_key, response })
+                .await?
+                .map(|key| key_info_json(&key))
+                .map_err(ApiError::invalid_parameter),
+            ApiRequest::AdminRemoveKey(address) => self
+                .admin_command(|response| AdminCommand::RemoveKey { address, response })
+                .await?
+                .map(|key| key_info_json(&key))
+                .map_err(|e| ApiError::new(ErrorCode::ValidatorNotFound, e)),
+        }
+    }
+
+    /// Run an admin command against the validator client and wait for its
+    /// reply
+    async fn admin_command<T>(&mut self, command: impl FnOnce(oneshot::Sender<T>) -> AdminCommand) -> Result<T, ApiError> {
+        let (tx, rx) = oneshot::channel();
+        self.validators.handle_admin_command(command(tx), &self.consensus);
+        rx.await.map_err(|_| ApiError::internal("Validator client dropped the admin command"))
+    }
+
+    /// Add a transaction to the pending set and gossip it
+    async fn submit_transaction(&mut self, network: &NetworkHandle, transaction: Transaction) -> Result<Value, ApiError> {
+        if !transaction.is_valid() {
+            return Err(ApiError::invalid_parameter("Transaction failed basic validation"));
+        }
+        if transaction.gas_limit > BLOCK_GAS_LIMIT {
+            return Err(ApiError::invalid_parameter(format!("Gas limit exceeds the block gas limit of {}", BLOCK_GAS_LIMIT)));
+        }
+        let total_cost = transaction
+            .total_cost()
+            .ok_or_else(|| ApiError::invalid_parameter("Amount plus gas_limit * gas_price overflows"))?;
+
+        let account = self.storage.get_account(&transaction.from).await.map_err(storage_error)?;
+        let authorized = match account.as_ref().and_then(|account| account.multisig_policy()) {
+            Some(policy) => transaction.verify_multisig(&policy.signers, policy.threshold),
+            None => transaction.verify_sender(),
+        };
+        authorized.map_err(|e| ApiError::new(ErrorCode::InvalidSignature, e))?;
+
+        // A sender without an account has nothing to pay the fee with
+        let Some(account) = account else {
+            return Err(ApiError::new(ErrorCode::InsufficientBalance, "Sender account does not exist"));
+        };
+        if account.balance < total_cost {
+            return Err(ApiError::new(ErrorCode::InsufficientBalance, "Balance does not cover amount and fee"));
+        }
+        // Later nonces wait in the pending set until the gap is filled
+        if transaction.nonce < account.nonce {
+            return Err(ApiError::invalid_parameter(format!(
+                "Nonce {} already used, the account's next nonce is {}",
+                transaction.nonce, account.nonce
+            )));
+        }
+
+        let hash = transaction.hash();
+        let added = self
+            .add_pending_transaction(transaction.clone())
+            .map_err(|e| ApiError::new(ErrorCode::PoolFull, e))?;
+        if added {
+            // The transaction stays pending even when no peer is reachable yet
+            if let Err(e) = network.broadcast_transaction(transaction).await {
+                debug!("Failed to gossip transaction {}: {}", hex::encode(hash), e);
+            }
+        }
+        Ok(json!({
+            "hash": to_hex(&hash),
+            "status": "pending",
+        }))
+    }
+}
@@ -0,0 +1,503 @@
+// API module - HTTP API server for chain, account, validator, network and
+// consensus queries, and WebSocket subscriptions to chain events
+
+pub mod events;
+pub mod handlers;
+pub mod ws;
+
+pub use events::{ApiEvent, EventPublisher, Subscription};
+
+use crate::config::ApiConfig;
+use crate::types::*;
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Prefix of every API route
+pub const API_BASE_PATH: &str = "/api/v1";
+
+/// Queries waiting for the node before new requests are held back
+pub const API_QUERY_BUFFER: usize = 256;
+
+const DEFAULT_TRANSACTION_LIMIT: usize = 20;
+const MAX_TRANSACTION_LIMIT: usize = 100;
+const DEFAULT_VALIDATOR_LIMIT: usize = 50;
+const MAX_VALIDATOR_LIMIT: usize = 500;
+
+/// Error codes returned in the response envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InvalidParameter,
+    NotFound,
+    InvalidSignature,
+    InsufficientBalance,
+    ValidatorExists,
+    ValidatorNotFound,
+    NetworkError,
+    Unauthorized,
+    /// The pending transaction set is full; retry later
+    PoolFull,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidParameter | ErrorCode::InvalidSignature | ErrorCode::InsufficientBalance => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorCode::NotFound | ErrorCode::ValidatorNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::ValidatorExists => StatusCode::CONFLICT,
+            ErrorCode::NetworkError => StatusCode::BAD_GATEWAY,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::PoolFull => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_parameter(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidParameter, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InternalError, message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl Error for ApiError {}
+
+/// Response envelope shared by every endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiResponse {
+    pub success: bool,
+    pub data: Option<serde_json::Value>,
+    pub error: Option<ApiError>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl ApiResponse {
+    pub fn from_result(result: Result<serde_json::Value, ApiError>) -> Self {
+        let (data, error) = match result {
+            Ok(data) => (Some(data), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            success: error.is_none(),
+            data,
+            error,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+/// A parsed API request, answered by the node
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiRequest {
+    Head,
+    BlockByHash(Hash),
+    BlockByHeight(u64),
+    Transaction(Hash),
+    SubmitTransaction(Box<Transaction>),
+    Balance(Address),
+    /// Merkle proof of an account against the head's state root
+    AccountProof(Address),
+    AccountTransactions {
+        address: Address,
+        limit: usize,
+        offset: usize,
+    },
+    Validator(Address),
+    Validators {
+        status: Option<ValidatorStatus>,
+        limit: usize,
+        offset: usize,
+    },
+    Peers,
+    ConsensusState,
+    /// Validator keys managed by the node; needs the admin token
+    AdminListKeys,
+    /// Add a validator key; needs the admin token
+    AdminAddKey {
+        /// Hex-encoded private key
+        private_key: String,
+    },
+    /// Stop managing a validator key; needs the admin token
+    AdminRemoveKey(Address),
+}
+
+impl ApiRequest {
+    /// Whether the request changes or reveals the node's validator keys
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            ApiRequest::AdminListKeys | ApiRequest::AdminAddKey { .. } | ApiRequest::AdminRemoveKey(_)
+        )
+    }
+}
+
+/// Body of `POST /admin/keys`
+#[derive(Debug, Deserialize)]
+struct AddKeyBody {
+    private_key: String,
+}
+
+/// An API request on its way to the node, with the channel for its answer
+#[derive(Debug)]
+pub struct ApiQuery {
+    pub request: ApiRequest,
+    pub response: oneshot::Sender<Result<serde_json::Value, ApiError>>,
+}
+
+/// Map a method, path, query string and body to an API request
+pub fn route(method: &Method, path: &str, query: Option<&str>, body: &[u8]) -> Result<ApiRequest, ApiError> {
+    let no_route = || ApiError::not_found(format!("No endpoint {} {}", method, path));
+    let route = path.strip_prefix(API_BASE_PATH).ok_or_else(no_route)?;
+    let segments: Vec<&str> = route.trim_matches('/').split('/').collect();
+    let params = parse_query(query);
+
+    let request = match (method, segments.as_slice()) {
+        (&Method::GET, ["chain", "head"]) => ApiRequest::Head,
+        (&Method::GET, ["chain", "block", "height", height]) => ApiRequest::BlockByHeight(
+            height.parse().map_err(|_| ApiError::invalid_parameter("Block height must be an integer"))?,
+        ),
+        (&Method::GET, ["chain", "block", hash]) => ApiRequest::BlockByHash(parse_hash(hash, "Block hash")?),
+        (&Method::GET, ["chain", "transaction", hash]) => ApiRequest::Transaction(parse_hash(hash, "Transaction hash")?),
+        (&Method::POST, ["chain", "transaction"]) => ApiRequest::SubmitTransaction(Box::new(
+            serde_json::from_slice(body).map_err(|e| ApiError::invalid_parameter(format!("Invalid transaction: {}", e)))?,
+        )),
+        (&Method::GET, ["account", address, "balance"]) => ApiRequest::Balance(parse_address(address)?),
+        (&Method::GET, ["account", address, "proof"]) => ApiRequest::AccountProof(parse_address(address)?),
+        (&Method::GET, ["account", address, "transactions"]) => {
+            let (limit, offset) = parse_page(&params, DEFAULT_TRANSACTION_LIMIT, MAX_TRANSACTION_LIMIT)?;
+            ApiRequest::AccountTransactions {
+                address: parse_address(address)?,
+                limit,
+                offset,
+            }
+        }
+        (&Method::GET, ["validator", address]) => ApiRequest::Validator(parse_address(address)?),
+        (&Method::GET, ["validators"]) => {
+            let (limit, offset) = parse_page(&params, DEFAULT_VALIDATOR_LIMIT, MAX_VALIDATOR_LIMIT)?;
+            let status = params.get("status").map(|status| parse_validator_status(status)).transpose()?;
+            ApiRequest::Validators { status, limit, offset }
+        }
+        (&Method::GET, ["network", "peers"]) => ApiRequest::Peers,
+        (&Method::GET, ["consensus", "state"]) => ApiRequest::ConsensusState,
+        (&Method::GET, ["admin", "keys"]) => ApiRequest::AdminListKeys,
+        (&Method::POST, ["admin", "keys"]) => {
+            let body: AddKeyBody = serde_json::from_slice(body)
+                .map_err(|e| ApiError::invalid_parameter(format!("Invalid key: {}", e)))?;
+            ApiRequest::AdminAddKey { private_key: body.private_key }
+        }
+        (&Method::DELETE, ["admin", "keys", address]) => ApiRequest::AdminRemoveKey(parse_address(address)?),
+        _ => return Err(no_route()),
+    };
+    Ok(request)
+}
+
+fn parse_query(query: Option<&str>) -> HashMap<&str, &str> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect()
+}
+
+fn parse_page(params: &HashMap<&str, &str>, default_limit: usize, max_limit: usize) -> Result<(usize, usize), ApiError> {
+    let parse = |name: &str, default: usize| -> Result<usize, ApiError> {
+        params
+            .get(name)
+            .map(|value| value.parse().map_err(|_| ApiError::invalid_parameter(format!("{} must be an integer", name))))
+            .unwrap_or(Ok(default))
+    };
+    let limit = parse("limit", default_limit)?;
+    if limit == 0 || limit > max_limit {
+        return Err(ApiError::invalid_parameter(format!("limit must be between 1 and {}", max_limit)));
+    }
+    Ok((limit, parse("offset", 0)?))
+}
+
+fn parse_hash(value: &str, what: &str) -> Result<Hash, ApiError> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    let bytes = hex::decode(value)
+        .ok()
+        .filter(|bytes| bytes.len() == 32)
+        .ok_or_else(|| ApiError::invalid_parameter(format!("{} must be 64 hex characters", what)))?;
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&bytes);
+    Ok(hash)
+}
+
+fn parse_address(value: &str) -> Result<Address, ApiError> {
+    parse_hash(value, "Address").map(Address)
+}
+
+fn parse_validator_status(value: &str) -> Result<ValidatorStatus, ApiError> {
+    match value.to_ascii_lowercase().as_str() {
+        "active" => Ok(ValidatorStatus::Active),
+        "inactive" => Ok(ValidatorStatus::Inactive),
+        "jailed" => Ok(ValidatorStatus::Jailed),
+        "exiting" => Ok(ValidatorStatus::Exiting),
+        "exited" => Ok(ValidatorStatus::Exited),
+        _ => Err(ApiError::invalid_parameter(format!("Unknown validator status {}", value))),
+    }
+}
+
+struct ServerState {
+    cors_origins: Vec<String>,
+    max_request_size: usize,
+    admin_token: Option<String>,
+    queries: mpsc::Sender<ApiQuery>,
+    events: broadcast::Sender<ApiEvent>,
+    shutdown: watch::Receiver<bool>,
+}
+
+/// HTTP server for the API. Requests are forwarded to the node as
+/// `ApiQuery`s and answered with the response envelope. WebSocket clients
+/// on `ws::WS_PATH` receive the events sent on `events`.
+#[derive(Debug)]
+pub struct ApiServer {
+    local_addr: SocketAddr,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl ApiServer {
+    /// Bind to `config.listen_address` and serve until stopped
+    pub fn start(
+        config: &ApiConfig,
+        queries: mpsc::Sender<ApiQuery>,
+        events: broadcast::Sender<ApiEvent>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let addr: SocketAddr = config.listen_address
+            .parse()
+            .map_err(|e| format!("Invalid API listen address {}: {}", config.listen_address, e))?;
+        let (shutdown, mut shutdown_signal) = watch::channel(false);
+        let state = Arc::new(ServerState {
+            cors_origins: config.cors_origins.clone(),
+            max_request_size: config.max_request_size,
+            admin_token: config.admin_token.clone(),
+            queries,
+            events,
+            shutdown: shutdown_signal.clone(),
+        });
+
+        let make_service = make_service_fn(move |_| {
+            let state = state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| handle_http(state.clone(), request))) }
+        });
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        let local_addr = server.local_addr();
+
+        let task = tokio::spawn(async move {
+            let server = server.with_graceful_shutdown(async move {
+                let _ = shutdown_signal.changed().await;
+            });
+            if let Err(e) = server.await {
+                warn!("API server failed: {}", e);
+            }
+        });
+
+        info!("API listening on http://{}{}", local_addr, API_BASE_PATH);
+        Ok(Self { local_addr, shutdown, task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections, close WebSocket connections and wait
+    /// for open requests to finish
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(true);
+        let _ = self.task.await;
+    }
+}
+
+async fn handle_http(state: Arc<ServerState>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let allowed_origin = allowed_origin(&state.cors_origins, request.headers().get(header::ORIGIN));
+    if request.uri().path() == ws::WS_PATH {
+        // Browsers do not apply CORS to WebSockets, so check the origin here
+        let result = if request.headers().contains_key(header::ORIGIN) && allowed_origin.is_none() {
+            Err(ApiError::invalid_parameter("Origin is not allowed"))
+        } else {
+            ws::upgrade(request, &state.events, state.shutdown.clone(), state.max_request_size)
+        };
+        return Ok(result.unwrap_or_else(error_response));
+    }
+
+    let mut response = if request.method() == Method::OPTIONS {
+        Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST, DELETE, OPTIONS")
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization")
+            .body(Body::empty())
+            .unwrap_or_default()
+    } else {
+        let (status, result) = answer(&state, request).await;
+        json_response(status, result)
+    };
+
+    if let Some(origin) = allowed_origin {
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+    }
+    Ok(response)
+}
+
+fn json_response(status: StatusCode, result: Result<serde_json::Value, ApiError>) -> Response<Body> {
+    let body = serde_json::to_vec(&ApiResponse::from_result(result)).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap_or_default()
+}
+
+fn error_response(error: ApiError) -> Response<Body> {
+    json_response(error.code.status(), Err(error))
+}
+
+/// Value for `Access-Control-Allow-Origin`, if the origin may read responses
+fn allowed_origin(cors_origins: &[String], origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+    if cors_origins.iter().any(|allowed| allowed == "*") {
+        return Some(HeaderValue::from_static("*"));
+    }
+    let origin = origin?;
+    let value = origin.to_str().ok()?;
+    cors_origins.iter().any(|allowed| allowed == value).then(|| origin.clone())
+}
+
+async fn answer(state: &ServerState, request: Request<Body>) -> (StatusCode, Result<serde_json::Value, ApiError>) {
+    let (parts, mut body) = request.into_parts();
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return (StatusCode::BAD_REQUEST, Err(ApiError::invalid_parameter(format!("Unreadable body: {}", e)))),
+        };
+        if bytes.len() + chunk.len() > state.max_request_size {
+            let error = ApiError::invalid_parameter(format!("Request body exceeds {} bytes", state.max_request_size));
+            return (StatusCode::PAYLOAD_TOO_LARGE, Err(error));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let result = match route(&parts.method, parts.uri.path(), parts.uri.query(), &bytes) {
+        Ok(request) if request.is_admin() => match authorize_admin(state.admin_token.as_deref(), &parts.headers) {
+            Ok(()) => query_node(&state.queries, request).await,
+            Err(error) => Err(error),
+        },
+        Ok(request) => query_node(&state.queries, request).await,
+        Err(error) => Err(error),
+    };
+    debug!("{} {} -> {:?}", parts.method, parts.uri, result.as_ref().err());
+    let status = match &result {
+        Ok(_) => StatusCode::OK,
+        Err(error) => error.code.status(),
+    };
+    (status, result)
+}
+
+/// Check the `Authorization: Bearer` header of an admin request. Admin
+/// endpoints do not exist while no token is configured.
+fn authorize_admin(admin_token: Option<&str>, headers: &header::HeaderMap) -> Result<(), ApiError> {
+    let Some(expected) = admin_token else {
+        return Err(ApiError::not_found("Admin endpoints are disabled; set api.admin_token"));
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    // Compare in constant time so the token cannot be guessed byte by byte
+    let matches = given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0;
+    if matches {
+        Ok(())
+    } else {
+        Err(ApiError::new(ErrorCode::Unauthorized, "Missing or wrong admin token"))
+    }
+}
+
+async fn query_node(queries: &mpsc::Sender<ApiQuery>, request: ApiRequest) -> Result<serde_json::Value, ApiError> {
+    let (response, answer) = oneshot::channel();
+    queries
+        .send(ApiQuery { request, response })
+        .await
+        .map_err(|_| ApiError::internal("Node is not running"))?;
+    answer.await.map_err(|_| ApiError::internal("Node dropped the request"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_parses_paths_and_parameters() {
+        let hash = [7u8; 32];
+        assert_eq!(
+            route(&Method::GET, &format!("/api/v1/chain/block/0x{}", hex::encode(hash)), None, b""),
+            Ok(ApiRequest::BlockByHash(hash))
+        );
+        assert_eq!(
+            route(&Method::GET, "/api/v1/chain/block/height/12", None, b""),
+            Ok(ApiRequest::BlockByHeight(12))
+        );
+        assert_eq!(
+            route(&Method::GET, &format!("/api/v1/account/{}/transactions", "ab".repeat(32)), Some("offset=5"), b""),
+            Ok(ApiRequest::AccountTransactions { address: Address([0xab; 32]), limit: 20, offset: 5 })
+        );
+        assert_eq!(
+            route(&Method::GET, &format!("/api/v1/account/{}/proof", "ab".repeat(32)), None, b""),
+            Ok(ApiRequest::AccountProof(Address([0xab; 32])))
+        );
+
+        let error = route(&Method::GET, "/api/v1/chain/block/height/twelve", None, b"").unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParameter);
+        let error = route(&Method::POST, "/api/v1/chain/head", None, b"").unwrap_err();
+        assert_eq!(error.code, ErrorCode::NotFound);
+        let error = route(&Method::POST, "/api/v1/chain/transaction", None, b"{}").unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParameter);
+    }
+}
@@ -0,0 +1,236 @@
+// WebSocket API - subscribe to chain, transaction and validator events
+
+use super::events::{ApiEvent, Subscription};
+use super::{ApiError, ErrorCode};
+use futures::{SinkExt, StreamExt};
+use hyper::header::{self, HeaderValue};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{Message, Role, WebSocketConfig};
+use tokio_tungstenite::WebSocketStream;
+use tracing::debug;
+
+/// Path of the WebSocket endpoint
+pub const WS_PATH: &str = "/ws";
+
+pub const MAX_SUBSCRIPTIONS_PER_CONNECTION: usize = 32;
+
+/// Clients that take longer than this to accept a message are disconnected
+pub const WS_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accept a WebSocket upgrade and serve the connection in the background.
+/// The connection gets its own event receiver, so a slow client only ever
+/// holds `events::EVENT_BUFFER` events.
+pub fn upgrade(
+    request: Request<Body>,
+    events: &broadcast::Sender<ApiEvent>,
+    shutdown: watch::Receiver<bool>,
+    max_message_size: usize,
+) -> Result<Response<Body>, ApiError> {
+    let headers = request.headers();
+    let has_token = |name: header::HeaderName, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    if request.method() != Method::GET || !has_token(header::UPGRADE, "websocket") || !has_token(header::CONNECTION, "upgrade") {
+        return Err(ApiError::invalid_parameter("Expected a WebSocket upgrade request"));
+    }
+    if headers.get(header::SEC_WEBSOCKET_VERSION).map(|version| version.as_bytes()) != Some(b"13") {
+        return Err(ApiError::invalid_parameter("Unsupported WebSocket version"));
+    }
+    let key = headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .ok_or_else(|| ApiError::invalid_parameter("Missing Sec-WebSocket-Key"))?;
+    let accept = HeaderValue::from_str(&derive_accept_key(key.as_bytes()))
+        .map_err(|e| ApiError::internal(e.to_string()))?;
+
+    // Subscribe before answering so no event published after the upgrade
+    // is missed
+    let receiver = events.subscribe();
+    tokio::spawn(async move {
+        match hyper::upgrade::on(request).await {
+            Ok(upgraded) => {
+                let config = WebSocketConfig {
+                    max_message_size: Some(max_message_size),
+                    max_frame_size: Some(max_message_size),
+                    ..Default::default()
+                };
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(config)).await;
+                serve(socket, receiver, shutdown).await;
+            }
+            Err(e) => debug!("WebSocket upgrade failed: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::UPGRADE, "websocket")
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .map_err(|e| ApiError::internal(e.to_string()))
+}
+
+/// Subscriptions of one connection
+#[derive(Debug, Default)]
+struct Subscriptions {
+    active: HashMap<u64, Subscription>,
+    next_id: u64,
+}
+
+impl Subscriptions {
+    /// Answer a subscribe or unsubscribe message
+    fn handle_message(&mut self, text: &str) -> Value {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => return error_reply(Value::Null, ApiError::invalid_parameter(format!("Invalid JSON: {}", e))),
+        };
+        let id = message["id"].clone();
+        let result = match message["method"].as_str() {
+            Some("subscribe") => self.subscribe(&message["params"]),
+            Some("unsubscribe") => self.unsubscribe(&message["params"]),
+            Some(method) => Err(ApiError::invalid_parameter(format!("Unknown method {}", method))),
+            None => Err(ApiError::invalid_parameter("Method is missing")),
+        };
+        match result {
+            Ok(result) => json!({ "id": id, "result": result }),
+            Err(error) => error_reply(id, error),
+        }
+    }
+
+    fn subscribe(&mut self, params: &Value) -> Result<Value, ApiError> {
+        let subscription = Subscription::from_params(params)?;
+        if self.active.len() >= MAX_SUBSCRIPTIONS_PER_CONNECTION {
+            return Err(ApiError::invalid_parameter(format!(
+                "At most {} subscriptions per connection",
+                MAX_SUBSCRIPTIONS_PER_CONNECTION
+            )));
+        }
+        self.next_id += 1;
+        self.active.insert(self.next_id, subscription);
+        Ok(json!({ "subscription": self.next_id }))
+    }
+
+    fn unsubscribe(&mut self, params: &Value) -> Result<Value, ApiError> {
+        let id = params["subscription"]
+            .as_u64()
+            .ok_or_else(|| ApiError::invalid_parameter("Subscription id is missing"))?;
+        self.active
+            .remove(&id)
+            .map(|_| json!({ "unsubscribed": id }))
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("No subscription {}", id)))
+    }
+
+    /// Event messages for every subscription matching `event`
+    fn messages(&self, event: &ApiEvent) -> Vec<Value> {
+        let mut ids: Vec<u64> = self.active
+            .iter()
+            .filter(|(_, subscription)| subscription.matches(event))
+            .map(|(id, _)| *id)
+            .collect();
+        if ids.is_empty() {
+            return Vec::new();
+        }
+        ids.sort_unstable();
+        let data = event.data();
+        ids.into_iter()
+            .map(|id| json!({ "type": event.kind(), "subscription": id, "data": data }))
+            .collect()
+    }
+}
+
+fn error_reply(id: Value, error: ApiError) -> Value {
+    json!({ "id": id, "error": error })
+}
+
+async fn send(socket: &mut WebSocketStream<Upgraded>, message: Value) -> Result<(), String> {
+    match tokio::time::timeout(WS_SEND_TIMEOUT, socket.send(Message::Text(message.to_string()))).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("client is not reading".to_string()),
+    }
+}
+
+async fn serve(
+    mut socket: WebSocketStream<Upgraded>,
+    mut events: broadcast::Receiver<ApiEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut subscriptions = Subscriptions::default();
+    loop {
+        let outgoing = tokio::select! {
+            _ = shutdown.changed() => {
+                let _ = socket.close(None).await;
+                return;
+            }
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => vec![subscriptions.handle_message(&text)],
+                Some(Ok(Message::Binary(_))) => {
+                    vec![error_reply(Value::Null, ApiError::invalid_parameter("Messages must be JSON text"))]
+                }
+                Some(Ok(Message::Close(_))) | None => return,
+                // Pings are answered by the protocol layer
+                Some(Ok(_)) => Vec::new(),
+                Some(Err(e)) => {
+                    debug!("WebSocket connection failed: {}", e);
+                    return;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) => subscriptions.messages(&event),
+                Err(RecvError::Lagged(missed)) => vec![json!({ "type": "lagged", "data": { "missed": missed } })],
+                Err(RecvError::Closed) => return,
+            },
+        };
+
+        for message in outgoing {
+            if let Err(e) = send(&mut socket, message).await {
+                debug!("Dropping WebSocket client: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe_and_unsubscribe_messages() {
+        let mut subscriptions = Subscriptions::default();
+
+        let reply = subscriptions.handle_message(r#"{"id": 1, "method": "subscribe", "params": {"type": "reorgs"}}"#);
+        assert_eq!(reply, json!({ "id": 1, "result": { "subscription": 1 } }));
+        let event = ApiEvent::Reorg { old_head: [1u8; 32], new_head: [2u8; 32], depth: 2 };
+        let messages = subscriptions.messages(&event);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["type"], "reorg");
+        assert_eq!(messages[0]["data"]["depth"], 2);
+        assert!(subscriptions.messages(&ApiEvent::FinalizedCheckpoint(crate::types::Checkpoint { epoch: 1, root: [0u8; 32] })).is_empty());
+
+        let reply = subscriptions.handle_message(r#"{"id": 2, "method": "unsubscribe", "params": {"subscription": 1}}"#);
+        assert_eq!(reply["result"]["unsubscribed"], 1);
+        assert!(subscriptions.messages(&event).is_empty());
+
+        let reply = subscriptions.handle_message(r#"{"id": 3, "method": "unsubscribe", "params": {"subscription": 1}}"#);
+        assert_eq!(reply["error"]["code"], "NOT_FOUND");
+        let reply = subscriptions.handle_message("not json");
+        assert_eq!(reply["error"]["code"], "INVALID_PARAMETER");
+
+        for _ in 0..MAX_SUBSCRIPTIONS_PER_CONNECTION {
+            subscriptions.handle_message(r#"{"method": "subscribe", "params": {"type": "blocks"}}"#);
+        }
+        let reply = subscriptions.handle_message(r#"{"id": 4, "method": "subscribe", "params": {"type": "blocks"}}"#);
+        assert_eq!(reply["error"]["code"], "INVALID_PARAMETER");
+    }
+}
@@ -1,8 +1,16 @@
-use clap::{Arg, Command};
-use proof_of_stake::{config::NodeConfig, Node};
-use std::path::PathBuf;
-use tracing::{info, error};
-use tracing_subscriber;
+use clap::{Arg, ArgMatches, Command};
+use proof_of_stake::{config::NodeConfig, testnet, Node};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::time::Duration;
+use tracing::{info, error, warn};
+
+/// How long `testnet start` waits for nodes to exit after ctrl-c
+const TESTNET_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Environment variable holding the keystore password; `testnet init`
+/// encrypts keystores with it and the started nodes inherit it
+const KEYSTORE_PASSWORD_ENV: &str = "POS_VALIDATOR__KEYSTORE_PASSWORD";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -22,17 +30,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .short('d')
                 .long("data-dir")
                 .value_name("DIR")
-                .help("Data directory path")
-                .default_value("./data"),
+                .help("Data directory path [default: ./data]"),
         )
         .arg(
             Arg::new("network")
                 .short('n')
                 .long("network")
                 .value_name("NETWORK")
-                .help("Network to connect to")
-                .value_parser(["mainnet", "testnet", "devnet"])
-                .default_value("devnet"),
+                .help("Network to connect to [default: devnet]")
+                .value_parser(["mainnet", "testnet", "devnet"]),
+        )
+        .arg(
+            Arg::new("genesis")
+                .short('g')
+                .long("genesis")
+                .value_name("FILE")
+                .help("Genesis file defining the chain"),
         )
         .arg(
             Arg::new("validator")
@@ -45,18 +58,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .short('v')
                 .long("log-level")
                 .value_name("LEVEL")
-                .help("Log level")
-                .value_parser(["trace", "debug", "info", "warn", "error"])
-                .default_value("info"),
+                .help("Log level, overriding logging.level from the config [default: info]")
+                .value_parser(["trace", "debug", "info", "warn", "error"]),
         )
         .arg(
             Arg::new("port")
                 .short('p')
                 .long("port")
                 .value_name("PORT")
-                .help("Network port to listen on")
-                .value_parser(clap::value_parser!(u16))
-                .default_value("9000"),
+                .help("Network port to listen on [default: 9000]")
+                .value_parser(clap::value_parser!(u16)),
         )
         .arg(
             Arg::new("node-id")
@@ -65,27 +76,100 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Local node ID for testing (0-9)")
                 .value_parser(clap::value_parser!(u8)),
         )
+        .subcommand(
+            Command::new("testnet")
+                .about("Generate and run a local multi-node network")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("init")
+                        .about("Generate keystores, a genesis file and node configs")
+                        .arg(
+                            Arg::new("validators")
+                                .long("validators")
+                                .value_name("COUNT")
+                                .help("Number of genesis validators")
+                                .value_parser(clap::value_parser!(usize))
+                                .default_value("4"),
+                        )
+                        .arg(
+                            Arg::new("nodes")
+                                .long("nodes")
+                                .value_name("COUNT")
+                                .help("Number of nodes; validators are spread across them")
+                                .value_parser(clap::value_parser!(usize))
+                                .default_value("3"),
+                        )
+                        .arg(
+                            Arg::new("out")
+                                .short('o')
+                                .long("out")
+                                .value_name("DIR")
+                                .help("Directory to generate the testnet in")
+                                .default_value("./testnet"),
+                        )
+                        .arg(
+                            Arg::new("base-port")
+                                .long("base-port")
+                                .value_name("PORT")
+                                .help("P2P port of the first node")
+                                .value_parser(clap::value_parser!(u16))
+                                .default_value("9000"),
+                        )
+                        .arg(
+                            Arg::new("genesis-delay")
+                                .long("genesis-delay")
+                                .value_name("SECONDS")
+                                .help("Seconds from now until genesis")
+                                .value_parser(clap::value_parser!(u64))
+                                .default_value("30"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("start")
+                        .about("Run every node of a generated testnet until ctrl-c")
+                        .arg(
+                            Arg::new("dir")
+                                .short('d')
+                                .long("dir")
+                                .value_name("DIR")
+                                .help("Testnet directory created by `testnet init`")
+                                .default_value("./testnet"),
+                        ),
+                ),
+        )
         .get_matches();
 
-    // Initialize logging
-    let log_level = matches.get_one::<String>("log-level").unwrap();
-    tracing_subscriber::fmt()
-        .with_env_filter(format!("proof_of_stake={}", log_level))
-        .init();
+    let cli_log_level = matches.get_one::<String>("log-level");
 
-    info!("Starting Production PoS Node v0.1.0");
+    if let Some(("testnet", matches)) = matches.subcommand() {
+        init_logging(cli_log_level.map_or("info", String::as_str));
+        return run_testnet_command(matches, cli_log_level.map(String::as_str)).await;
+    }
 
     // Load configuration
     let config_path = PathBuf::from(matches.get_one::<String>("config").unwrap());
-    let mut config = if config_path.exists() {
-        info!("Loading configuration from: {:?}", config_path);
+    let config_exists = config_path.exists();
+    let mut config = if config_exists {
         NodeConfig::load_from_file(&config_path)?
     } else {
-        info!("Using default configuration");
         NodeConfig::default()
     };
 
-    // Override config with CLI arguments
+    // Environment variables override the file, CLI arguments override both
+    config.apply_env_overrides(std::env::vars())?;
+
+    if let Some(level) = cli_log_level {
+        config.logging.level = level.clone();
+    }
+    init_logging(&config.logging.level);
+
+    info!("Starting Production PoS Node v0.1.0");
+    if config_exists {
+        info!("Loaded configuration from: {:?}", config_path);
+    } else {
+        info!("Using default configuration");
+    }
+
     if let Some(data_dir) = matches.get_one::<String>("data-dir") {
         config.storage.data_dir = PathBuf::from(data_dir);
     }
@@ -99,6 +183,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         };
     }
 
+    if let Some(genesis) = matches.get_one::<String>("genesis") {
+        config.network.genesis_file = Some(PathBuf::from(genesis));
+    }
+
     if matches.get_flag("validator") {
         config.validator.enabled = true;
     }
@@ -122,17 +210,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             9000 // fallback to default base
         };
 
-        config.network.bootstrap_nodes.clear();
+        config.network.bootstrap_peers.clear();
         for i in 0..3u8 {
             if i != *node_id {
                 let bootstrap_port = base_port + (i as u16);
-                config.network.bootstrap_nodes.push(
-                    format!("/ip4/127.0.0.1/tcp/{}", bootstrap_port)
+                config.network.bootstrap_peers.push(
+                    format!("/ip4/127.0.0.1/tcp/{}", bootstrap_port).parse()?
                 );
             }
         }
     }
 
+    config.validate()?;
+
     info!("Configuration: {:?}", config);
 
     // Create and start the node
@@ -140,24 +230,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Ok(mut node) => {
             info!("Node initialized successfully");
 
-            // Handle shutdown gracefully
-            let shutdown_signal = async {
-                tokio::signal::ctrl_c()
-                    .await
-                    .expect("Failed to listen for ctrl-c");
-                info!("Received shutdown signal");
-            };
-
-            tokio::select! {
-                result = node.start() => {
-                    if let Err(e) = result {
-                        error!("Node error: {}", e);
-                        return Err(e.into());
-                    }
-                }
-                _ = shutdown_signal => {
-                    info!("Shutting down gracefully...");
-                }
+            // Runs until ctrl-c, then shuts down and flushes storage
+            if let Err(e) = node.start().await {
+                error!("Node error: {}", e);
+                return Err(e.into());
             }
         }
         Err(e) => {
@@ -168,4 +244,121 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Node stopped");
     Ok(())
-}
\ No newline at end of file
+}
+
+fn init_logging(level: &str) {
+    tracing_subscriber::fmt()
+        .with_env_filter(format!("proof_of_stake={}", level))
+        .init();
+}
+
+async fn run_testnet_command(matches: &ArgMatches, log_level: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    match matches.subcommand() {
+        Some(("init", matches)) => {
+            let options = testnet::TestnetOptions {
+                validators: *matches.get_one::<usize>("validators").unwrap(),
+                nodes: *matches.get_one::<usize>("nodes").unwrap(),
+                base_port: *matches.get_one::<u16>("base-port").unwrap(),
+                genesis_delay: *matches.get_one::<u64>("genesis-delay").unwrap(),
+                keystore_password: std::env::var(KEYSTORE_PASSWORD_ENV).ok().filter(|password| !password.is_empty()),
+                ..testnet::TestnetOptions::default()
+            };
+            let out_dir = PathBuf::from(matches.get_one::<String>("out").unwrap());
+            let testnet = testnet::init(&out_dir, &options)?;
+            if options.keystore_password.is_none() {
+                warn!("{} is not set; validator keys are stored unencrypted, for local development only", KEYSTORE_PASSWORD_ENV);
+            }
+
+            info!("Genesis {} written to {}", hex::encode(testnet.genesis_hash), testnet.genesis_file.display());
+            for node in &testnet.nodes {
+                info!(
+                    "{}: port {}, peer {}, {} validators",
+                    node.config_file.display(),
+                    node.config.network.port,
+                    node.peer_id,
+                    node.validators.len()
+                );
+            }
+            info!("Start the nodes with: node testnet start --dir {}", out_dir.display());
+            Ok(())
+        }
+        Some(("start", matches)) => {
+            let dir = PathBuf::from(matches.get_one::<String>("dir").unwrap());
+            start_testnet(&dir, log_level).await
+        }
+        _ => unreachable!("testnet requires a subcommand"),
+    }
+}
+
+/// Run one node process per config in `dir` and stop them all on ctrl-c.
+/// Nodes log at their configured level unless `log_level` overrides it.
+async fn start_testnet(dir: &Path, log_level: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let executable = std::env::current_exe()?;
+    let mut children: Vec<(PathBuf, Child)> = Vec::new();
+    for config_file in testnet::node_config_files(dir)? {
+        let log_file = std::fs::File::create(config_file.with_file_name("node.log"))?;
+        let mut command = std::process::Command::new(&executable);
+        command.arg("--config").arg(&config_file);
+        if let Some(level) = log_level {
+            command.arg("--log-level").arg(level);
+        }
+        let child = command
+            .stdout(Stdio::from(log_file.try_clone()?))
+            .stderr(Stdio::from(log_file))
+            .spawn();
+        match child {
+            Ok(child) => {
+                info!("Started node {} from {}", child.id(), config_file.display());
+                children.push((config_file, child));
+            }
+            Err(e) => {
+                error!("Failed to start node from {}: {}", config_file.display(), e);
+                stop_nodes(children).await;
+                return Err(e.into());
+            }
+        }
+    }
+
+    info!("Testnet running, logs are in each node directory. Press ctrl-c to stop.");
+    tokio::signal::ctrl_c().await?;
+    stop_nodes(children).await;
+    Ok(())
+}
+
+/// Ask nodes to shut down with SIGINT, the signal they handle like ctrl-c,
+/// and kill any still running after `TESTNET_SHUTDOWN_TIMEOUT`
+async fn stop_nodes(mut children: Vec<(PathBuf, Child)>) {
+    for (config_file, child) in &children {
+        if let Err(e) = interrupt(child) {
+            warn!("Failed to signal node from {}: {}", config_file.display(), e);
+        }
+    }
+
+    let deadline = tokio::time::Instant::now() + TESTNET_SHUTDOWN_TIMEOUT;
+    while !children.is_empty() && tokio::time::Instant::now() < deadline {
+        children.retain_mut(|(_, child)| matches!(child.try_wait(), Ok(None)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    for (config_file, mut child) in children {
+        warn!("Node from {} did not stop, killing it", config_file.display());
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+#[cfg(unix)]
+fn interrupt(child: &Child) -> std::io::Result<()> {
+    let pid = libc::pid_t::try_from(child.id()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: kill only sends a signal to the process we spawned
+    if unsafe { libc::kill(pid, libc::SIGINT) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Without signals nodes are only stopped once the timeout passes
+#[cfg(not(unix))]
+fn interrupt(_child: &Child) -> std::io::Result<()> {
+    Ok(())
+}
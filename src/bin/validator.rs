@@ -1,7 +1,15 @@
 use clap::{Arg, Command};
-use proof_of_stake::{crypto::KeyPair};
+use proof_of_stake::{crypto::KeyPair, validator::keystore};
 use tracing::{info, error};
 
+/// Environment variable holding the keystore password, the same variable
+/// that sets `validator.keystore_password` for the node
+const KEYSTORE_PASSWORD_ENV: &str = "POS_VALIDATOR__KEYSTORE_PASSWORD";
+
+fn keystore_password() -> Option<String> {
+    std::env::var(KEYSTORE_PASSWORD_ENV).ok().filter(|password| !password.is_empty())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("validator")
@@ -102,14 +110,7 @@ async fn generate_validator_keys(output_file: &str) -> Result<(), Box<dyn std::e
 
     let keypair = KeyPair::generate();
 
-    let key_data = serde_json::json!({
-        "private_key": hex::encode(keypair.private_key),
-        "public_key": hex::encode(keypair.public_key),
-        "address": keypair.address.to_string(),
-        "created_at": chrono::Utc::now().to_rfc3339()
-    });
-
-    std::fs::write(output_file, serde_json::to_string_pretty(&key_data)?)?;
+    keystore::write_keystore(std::path::Path::new(output_file), &keypair, keystore_password().as_deref())?;
 
     info!("Validator keypair generated successfully!");
     info!("Private key saved to: {}", output_file);
@@ -125,14 +126,7 @@ async fn generate_validator_keys(output_file: &str) -> Result<(), Box<dyn std::e
 async fn show_validator_address(keyfile: &str) -> Result<(), Box<dyn std::error::Error>> {
     info!("Reading validator key from: {}", keyfile);
 
-    let key_content = std::fs::read_to_string(keyfile)?;
-    let key_data: serde_json::Value = serde_json::from_str(&key_content)?;
-
-    let private_key_hex = key_data["private_key"]
-        .as_str()
-        .ok_or("Invalid key file format")?;
-
-    let keypair = KeyPair::from_hex(private_key_hex)?;
+    let keypair = keystore::read_keystore(std::path::Path::new(keyfile), keystore_password().as_deref())?;
 
     info!("Validator Information:");
     info!("Address: {}", keypair.address);
@@ -149,14 +143,7 @@ async fn register_validator(
 ) -> Result<(), Box<dyn std::error::Error>> {
     info!("Registering validator...");
 
-    let key_content = std::fs::read_to_string(keyfile)?;
-    let key_data: serde_json::Value = serde_json::from_str(&key_content)?;
-
-    let private_key_hex = key_data["private_key"]
-        .as_str()
-        .ok_or("Invalid key file format")?;
-
-    let keypair = KeyPair::from_hex(private_key_hex)?;
+    let keypair = keystore::read_keystore(std::path::Path::new(keyfile), keystore_password().as_deref())?;
     let stake_amount: u64 = stake.parse()?;
     let commission_rate: u16 = commission.parse()?;
 
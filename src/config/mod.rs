@@ -1,8 +1,21 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use crate::types::NetworkId;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+pub use crate::network::NetworkConfig;
+
+/// Prefix of environment variables overriding config values, e.g.
+/// `POS_NETWORK__PORT=9001` or `POS_API__ENABLED=false`
+pub const ENV_PREFIX: &str = "POS_";
+
+/// Separates nested keys in environment variable names
+pub const ENV_SEPARATOR: &str = "__";
+
+const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct NodeConfig {
     pub network: NetworkConfig,
     pub storage: StorageConfig,
@@ -13,16 +26,7 @@ pub struct NodeConfig {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct NetworkConfig {
-    pub network_id: NetworkId,
-    pub listen_address: String,
-    pub port: u16,
-    pub max_peers: usize,
-    pub bootstrap_nodes: Vec<String>,
-    pub enable_mdns: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct StorageConfig {
     pub data_dir: PathBuf,
     pub db_url: Option<String>,
@@ -30,24 +34,34 @@ pub struct StorageConfig {
     pub sync_mode: SyncMode,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ValidatorConfig {
     pub enabled: bool,
+    /// Single key file
     pub keystore_path: Option<PathBuf>,
+    /// Directory of key files, one per validator; keys added at runtime are
+    /// written here
+    pub keystore_dir: Option<PathBuf>,
     pub keystore_password: Option<String>,
     pub graffiti: Option<String>,
     pub fee_recipient: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ApiConfig {
     pub enabled: bool,
     pub listen_address: String,
     pub cors_origins: Vec<String>,
     pub max_request_size: usize,
+    /// Bearer token for the validator key admin endpoints, which are
+    /// disabled when unset
+    pub admin_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct MetricsConfig {
     pub enabled: bool,
     pub listen_address: String,
@@ -55,6 +69,7 @@ pub struct MetricsConfig {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct LoggingConfig {
     pub level: String,
     pub format: LogFormat,
@@ -78,7 +93,7 @@ pub enum LogFormat {
 impl Default for NodeConfig {
     fn default() -> Self {
         NodeConfig {
-            network: NetworkConfig::default(),
+            network: NetworkConfig::with_port(crate::network::DEFAULT_PORT),
             storage: StorageConfig::default(),
             validator: ValidatorConfig::default(),
             api: ApiConfig::default(),
@@ -88,19 +103,6 @@ impl Default for NodeConfig {
     }
 }
 
-impl Default for NetworkConfig {
-    fn default() -> Self {
-        NetworkConfig {
-            network_id: NetworkId::Devnet,
-            listen_address: "0.0.0.0".to_string(),
-            port: 9000,
-            max_peers: 50,
-            bootstrap_nodes: Vec::new(),
-            enable_mdns: true,
-        }
-    }
-}
-
 impl Default for StorageConfig {
     fn default() -> Self {
         StorageConfig {
@@ -112,18 +114,6 @@ impl Default for StorageConfig {
     }
 }
 
-impl Default for ValidatorConfig {
-    fn default() -> Self {
-        ValidatorConfig {
-            enabled: false,
-            keystore_path: None,
-            keystore_password: None,
-            graffiti: None,
-            fee_recipient: None,
-        }
-    }
-}
-
 impl Default for ApiConfig {
     fn default() -> Self {
         ApiConfig {
@@ -131,6 +121,7 @@ impl Default for ApiConfig {
             listen_address: "127.0.0.1:8080".to_string(),
             cors_origins: vec!["*".to_string()],
             max_request_size: 1024 * 1024, // 1MB
+            admin_token: None,
         }
     }
 }
@@ -156,15 +147,178 @@ impl Default for LoggingConfig {
 }
 
 impl NodeConfig {
-    pub fn load_from_file(path: &PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = std::fs::read_to_string(path)?;
-        let config: NodeConfig = serde_json::from_str(&content)?;
-        Ok(config)
+    /// Read a config file, as TOML or JSON depending on its extension
+    pub fn load_from_file(path: &PathBuf) -> Result<Self, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+        let config = match ConfigFormat::from_path(path)? {
+            ConfigFormat::Toml => toml::from_str(&content).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(&content).map_err(|e| e.to_string()),
+        };
+        config.map_err(|e| format!("Invalid config file {}: {}", path.display(), e).into())
     }
 
-    pub fn save_to_file(&self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-        let content = serde_json::to_string_pretty(self)?;
+    pub fn save_to_file(&self, path: &PathBuf) -> Result<(), Box<dyn Error>> {
+        let content = match ConfigFormat::from_path(path)? {
+            ConfigFormat::Toml => toml::to_string_pretty(self)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+        };
         std::fs::write(path, content)?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Override values from `POS_*` variables in `vars`. Nested keys are
+    /// joined with `__`: `POS_NETWORK__LOCAL_NETWORK__BASE_PORT=9100`.
+    /// Values are parsed as JSON unless the key holds a string, and lists
+    /// may also be given comma separated.
+    pub fn apply_env_overrides(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<(), Box<dyn Error>> {
+        let mut config = serde_json::to_value(&*self)?;
+        let mut overridden = false;
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let path: Vec<String> = key.split(ENV_SEPARATOR).map(|part| part.to_lowercase()).collect();
+            let field = path
+                .iter()
+                .try_fold(&mut config, |value, part| value.get_mut(part.as_str()))
+                .ok_or_else(|| format!("Unknown config key {} ({})", name, path.join(".")))?;
+            *field = parse_env_value(field, &value);
+            overridden = true;
+        }
+
+        if overridden {
+            *self = serde_json::from_value(config).map_err(|e| format!("Invalid config from environment: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Check that the config can run a node, reporting every problem found
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut problems = Vec::new();
+
+        let network = &self.network;
+        if let Err(e) = network.listen_multiaddr() {
+            problems.push(format!("network.listen_address: {}", e));
+        }
+        if network.heartbeat_interval.is_zero() {
+            problems.push("network.heartbeat_interval must be positive".to_string());
+        }
+        if network.max_connections == 0 {
+            problems.push("network.max_connections must be positive".to_string());
+        }
+        if network.wire_formats.is_empty() {
+            problems.push("network.wire_formats must list at least one format".to_string());
+        }
+        let local = &network.local_network;
+        if local.enabled && local.base_port as u32 + local.max_local_nodes as u32 > u16::MAX as u32 + 1 {
+            problems.push("network.local_network ports run past 65535".to_string());
+        }
+        if let Some(path) = &network.genesis_file {
+            if !path.is_file() {
+                problems.push(format!("network.genesis_file {} does not exist", path.display()));
+            }
+        }
+        if let Some(hash) = &network.genesis_hash {
+            if hex::decode(hash.trim_start_matches("0x")).map(|bytes| bytes.len()) != Ok(32) {
+                problems.push("network.genesis_hash must be 32 hex encoded bytes".to_string());
+            }
+        }
+
+        if self.storage.data_dir.as_os_str().is_empty() {
+            problems.push("storage.data_dir is empty".to_string());
+        } else if self.storage.data_dir.is_file() {
+            problems.push(format!("storage.data_dir {} is a file", self.storage.data_dir.display()));
+        }
+
+        if self.api.admin_token.as_ref().is_some_and(|token| token.len() < 16) {
+            problems.push("api.admin_token must be at least 16 characters".to_string());
+        }
+
+        let validator = &self.validator;
+        if validator.enabled && validator.keystore_dir.is_none() && validator.keystore_path.is_none() {
+            problems.push("Validator mode requires validator.keystore_dir or validator.keystore_path".to_string());
+        }
+        if let Some(path) = &validator.keystore_path {
+            if !path.is_file() {
+                problems.push(format!("validator.keystore_path {} does not exist", path.display()));
+            }
+        }
+        if let Some(dir) = &validator.keystore_dir {
+            if !dir.is_dir() {
+                problems.push(format!("validator.keystore_dir {} is not a directory", dir.display()));
+            }
+        }
+
+        let mut ports = vec![("network.port", network.port)];
+        for (name, enabled, address) in [
+            ("api.listen_address", self.api.enabled, &self.api.listen_address),
+            ("metrics.listen_address", self.metrics.enabled, &self.metrics.listen_address),
+        ] {
+            match address.parse::<SocketAddr>() {
+                Ok(address) if enabled => ports.push((name, address.port())),
+                Ok(_) => {}
+                Err(e) => problems.push(format!("{} {}: {}", name, address, e)),
+            }
+        }
+        // Port 0 lets the OS choose, so it never clashes
+        for (i, (name, port)) in ports.iter().enumerate() {
+            if let Some((other, _)) = ports[..i].iter().find(|(_, other)| *port != 0 && other == port) {
+                problems.push(format!("{} uses port {}, like {}", name, port, other));
+            }
+        }
+
+        let namespace = &self.metrics.namespace;
+        let valid_namespace = namespace.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && namespace.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_namespace {
+            problems.push(format!("metrics.namespace {:?} is not a valid metric name prefix", namespace));
+        }
+
+        if !LOG_LEVELS.contains(&self.logging.level.as_str()) {
+            problems.push(format!("logging.level must be one of {}", LOG_LEVELS.join(", ")));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Invalid configuration:\n  {}", problems.join("\n  ")).into())
+        }
+    }
+}
+
+enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => Err(format!("Config file {} must end in .toml or .json", path.display()).into()),
+        }
+    }
+}
+
+/// Value of an environment override for a field currently holding `current`
+fn parse_env_value(current: &serde_json::Value, value: &str) -> serde_json::Value {
+    use serde_json::Value;
+
+    match current {
+        // Strings and unset optional values (paths, hashes, passwords)
+        Value::String(_) | Value::Null => Value::String(value.to_string()),
+        Value::Array(_) => serde_json::from_str(value).unwrap_or_else(|_| {
+            Value::Array(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| serde_json::from_str(item).unwrap_or_else(|_| Value::String(item.to_string())))
+                    .collect(),
+            )
+        }),
+        _ => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+    }
+}
@@ -0,0 +1,303 @@
+use crate::types::*;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// Source of wall-clock time, in milliseconds since the unix epoch
+pub trait Clock: Debug + Send + Sync {
+    fn now_ms(&self) -> u64;
+
+    /// Resolve once the clock reads at least `time_ms`
+    fn sleep_until(&self, time_ms: u64) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        chrono::Utc::now().timestamp_millis().max(0) as u64
+    }
+
+    fn sleep_until(&self, time_ms: u64) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let wait = Duration::from_millis(time_ms.saturating_sub(self.now_ms()));
+        Box::pin(tokio::time::sleep(wait))
+    }
+}
+
+/// Clock that only moves when told to, for tests. Clones share the time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now_ms: Arc<watch::Sender<u64>>,
+}
+
+impl ManualClock {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            now_ms: Arc::new(watch::Sender::new(now_ms)),
+        }
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms.send_replace(now_ms);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now_ms.send_modify(|now| *now += duration.as_millis() as u64);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        *self.now_ms.borrow()
+    }
+
+    fn sleep_until(&self, time_ms: u64) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let mut receiver = self.now_ms.subscribe();
+        Box::pin(async move {
+            // The sender lives as long as any clone of the clock
+            let _ = receiver.wait_for(|now| *now >= time_ms).await;
+        })
+    }
+}
+
+/// Maps wall-clock time to slots and epochs
+#[derive(Debug, Clone)]
+pub struct SlotClock {
+    /// Unix seconds at the start of slot 0
+    pub genesis_time: u64,
+    pub seconds_per_slot: u64,
+    pub slots_per_epoch: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl SlotClock {
+    pub fn new(genesis_time: u64, seconds_per_slot: u64, slots_per_epoch: u64) -> Self {
+        Self {
+            genesis_time,
+            seconds_per_slot: seconds_per_slot.max(1),
+            slots_per_epoch: slots_per_epoch.max(1),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Slot clock for the chain described by `state`
+    pub fn from_state(state: &BeaconState, config: &ConsensusConfig) -> Self {
+        Self::new(state.genesis_time, config.seconds_per_slot, config.slots_per_epoch)
+    }
+
+    /// Read time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    pub fn slot_duration(&self) -> Duration {
+        Duration::from_secs(self.seconds_per_slot)
+    }
+
+    /// Current slot, or None before genesis
+    pub fn current_slot(&self) -> Option<Slot> {
+        self.slot_at(self.now_ms())
+    }
+
+    pub fn slot_at(&self, time_ms: u64) -> Option<Slot> {
+        let genesis_ms = self.genesis_time * 1000;
+        if time_ms < genesis_ms {
+            return None;
+        }
+        Some((time_ms - genesis_ms) / (self.seconds_per_slot * 1000))
+    }
+
+    pub fn current_epoch(&self) -> Option<Epoch> {
+        self.current_slot().map(|slot| slot / self.slots_per_epoch)
+    }
+
+    pub fn slot_start_ms(&self, slot: Slot) -> u64 {
+        (self.genesis_time + slot * self.seconds_per_slot) * 1000
+    }
+
+    /// Attestations are published a third of the way into the slot
+    pub fn attestation_deadline_ms(&self, slot: Slot) -> u64 {
+        self.slot_start_ms(slot) + self.seconds_per_slot * 1000 / 3
+    }
+
+    /// Aggregates are published two thirds of the way into the slot
+    pub fn aggregation_deadline_ms(&self, slot: Slot) -> u64 {
+        self.slot_start_ms(slot) + self.seconds_per_slot * 2000 / 3
+    }
+
+    pub fn is_epoch_start(&self, slot: Slot) -> bool {
+        slot.is_multiple_of(self.slots_per_epoch)
+    }
+
+    /// Time at which `event` is due
+    pub fn event_time_ms(&self, event: SlotEvent) -> u64 {
+        match event {
+            SlotEvent::SlotStart(slot) => self.slot_start_ms(slot),
+            SlotEvent::AttestationDeadline(slot) => self.attestation_deadline_ms(slot),
+            SlotEvent::AggregationDeadline(slot) => self.aggregation_deadline_ms(slot),
+        }
+    }
+
+    /// First event due at or after `time_ms`
+    pub fn next_event_at(&self, time_ms: u64) -> SlotEvent {
+        let Some(slot) = self.slot_at(time_ms) else {
+            return SlotEvent::SlotStart(0);
+        };
+        [SlotEvent::SlotStart(slot), SlotEvent::AttestationDeadline(slot), SlotEvent::AggregationDeadline(slot)]
+            .into_iter()
+            .find(|event| self.event_time_ms(*event) >= time_ms)
+            .unwrap_or(SlotEvent::SlotStart(slot + 1))
+    }
+
+    fn sleep_until(&self, time_ms: u64) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.clock.sleep_until(time_ms)
+    }
+}
+
+/// Points in a slot at which duties are due
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SlotEvent {
+    /// Blocks are proposed
+    SlotStart(Slot),
+    /// Attestations for the slot are published
+    AttestationDeadline(Slot),
+    /// Aggregated attestations for the slot are published
+    AggregationDeadline(Slot),
+}
+
+impl SlotEvent {
+    pub fn slot(&self) -> Slot {
+        match self {
+            SlotEvent::SlotStart(slot)
+            | SlotEvent::AttestationDeadline(slot)
+            | SlotEvent::AggregationDeadline(slot) => *slot,
+        }
+    }
+
+    fn next(self) -> SlotEvent {
+        match self {
+            SlotEvent::SlotStart(slot) => SlotEvent::AttestationDeadline(slot),
+            SlotEvent::AttestationDeadline(slot) => SlotEvent::AggregationDeadline(slot),
+            SlotEvent::AggregationDeadline(slot) => SlotEvent::SlotStart(slot + 1),
+        }
+    }
+}
+
+/// Yields slot events in order as the clock reaches them. Events are
+/// never skipped: if the clock jumps ahead, the missed ones are returned
+/// immediately one after another.
+#[derive(Debug)]
+pub struct SlotTicker {
+    clock: SlotClock,
+    next: SlotEvent,
+}
+
+impl SlotTicker {
+    /// Ticker starting with the first event at or after the current time
+    pub fn new(clock: SlotClock) -> Self {
+        let next = clock.next_event_at(clock.now_ms());
+        Self { clock, next }
+    }
+
+    pub fn clock(&self) -> &SlotClock {
+        &self.clock
+    }
+
+    /// Wait for the next event. Cancel safe: an event is only consumed
+    /// once it is returned.
+    pub async fn tick(&mut self) -> SlotEvent {
+        self.clock.sleep_until(self.clock.event_time_ms(self.next)).await;
+        let event = self.next;
+        self.next = event.next();
+        event
+    }
+
+    /// Run the ticker as a task sending each event to `sender`, until the
+    /// receiver is dropped
+    pub fn spawn(mut self, sender: mpsc::Sender<SlotEvent>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let event = self.tick().await;
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    const GENESIS: u64 = 1_000;
+
+    fn manual_slot_clock() -> (ManualClock, SlotClock) {
+        let clock = ManualClock::new(GENESIS * 1000);
+        let slot_clock = SlotClock::new(GENESIS, 12, 4).with_clock(Arc::new(clock.clone()));
+        (clock, slot_clock)
+    }
+
+    #[test]
+    fn test_slot_arithmetic() {
+        let (clock, slot_clock) = manual_slot_clock();
+        assert_eq!(slot_clock.current_slot(), Some(0));
+        assert_eq!(slot_clock.slot_at(GENESIS * 1000 - 1), None);
+
+        clock.advance(Duration::from_secs(12 * 5 + 1));
+        assert_eq!(slot_clock.current_slot(), Some(5));
+        assert_eq!(slot_clock.current_epoch(), Some(1));
+        assert_eq!(slot_clock.attestation_deadline_ms(5), (GENESIS + 60 + 4) * 1000);
+        assert_eq!(slot_clock.aggregation_deadline_ms(5), (GENESIS + 60 + 8) * 1000);
+        assert_eq!(slot_clock.next_event_at(slot_clock.now_ms()), SlotEvent::AttestationDeadline(5));
+        assert!(slot_clock.is_epoch_start(8));
+    }
+
+    #[tokio::test]
+    async fn test_ticker_fires_events_in_order() {
+        let (clock, slot_clock) = manual_slot_clock();
+        let mut ticker = SlotTicker::new(slot_clock);
+
+        assert_eq!(ticker.tick().await, SlotEvent::SlotStart(0));
+        assert!(ticker.tick().now_or_never().is_none());
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(ticker.tick().await, SlotEvent::AttestationDeadline(0));
+
+        // Events the clock jumped past are delivered without waiting
+        clock.advance(Duration::from_secs(15));
+        assert_eq!(ticker.tick().await, SlotEvent::AggregationDeadline(0));
+        assert_eq!(ticker.tick().await, SlotEvent::SlotStart(1));
+        assert_eq!(ticker.tick().await, SlotEvent::AttestationDeadline(1));
+        assert!(ticker.tick().now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_spawned_ticker_sends_events() {
+        let (clock, slot_clock) = manual_slot_clock();
+        let (sender, mut events) = mpsc::channel(4);
+        let task = SlotTicker::new(slot_clock).spawn(sender);
+
+        assert_eq!(events.recv().await, Some(SlotEvent::SlotStart(0)));
+        clock.advance(Duration::from_secs(12));
+        assert_eq!(events.recv().await, Some(SlotEvent::AttestationDeadline(0)));
+        assert_eq!(events.recv().await, Some(SlotEvent::AggregationDeadline(0)));
+        assert_eq!(events.recv().await, Some(SlotEvent::SlotStart(1)));
+
+        drop(events);
+        clock.advance(Duration::from_secs(12));
+        task.await.unwrap();
+    }
+}
@@ -1,31 +1,103 @@
-// Consensus engine implementation - placeholder for the main consensus coordinator
-// This is referenced in mod.rs but was not created
+// Consensus engine core - drives ConsensusEngine from the slot clock
 
 use crate::types::*;
+use super::{ConsensusEngine, SlotClock, SlotEvent};
 use anyhow::Result;
+use tracing::{debug, info};
 
 pub struct ConsensusEngineCore {
-    // Core consensus state
+    pub slot_clock: SlotClock,
+    /// Last slot passed to `process_slot`
+    last_slot: Option<Slot>,
 }
 
 impl ConsensusEngineCore {
-    pub fn new() -> Self {
-        ConsensusEngineCore {}
+    pub fn new(slot_clock: SlotClock) -> Self {
+        ConsensusEngineCore {
+            slot_clock,
+            last_slot: None,
+        }
     }
 
-    pub async fn initialize(&mut self) -> Result<()> {
-        // Initialize consensus engine
+    /// Start from the current slot so that epochs which ended before the
+    /// node started are not processed
+    pub async fn initialize(&mut self, engine: &mut ConsensusEngine) -> Result<()> {
+        if let Some(slot) = self.slot_clock.current_slot() {
+            self.process_slot(engine, slot).await?;
+        }
         Ok(())
     }
 
-    pub async fn process_slot(&mut self, _slot: Slot) -> Result<()> {
-        // Process a new slot
+    pub fn last_slot(&self) -> Option<Slot> {
+        self.last_slot
+    }
+
+    /// Handle a slot clock event. Only slot starts change consensus state.
+    pub async fn on_event(&mut self, engine: &mut ConsensusEngine, event: SlotEvent) -> Result<()> {
+        match event {
+            SlotEvent::SlotStart(slot) => self.process_slot(engine, slot).await,
+            SlotEvent::AttestationDeadline(_) | SlotEvent::AggregationDeadline(_) => Ok(()),
+        }
+    }
+
+    /// Advance consensus to `slot`: move the current slot and epoch forward,
+    /// drop the previous slot's proposer boost and run `finalize_epoch` for
+    /// every epoch that ended since the last processed slot
+    pub async fn process_slot(&mut self, engine: &mut ConsensusEngine, slot: Slot) -> Result<()> {
+        if self.last_slot.is_some_and(|last| slot <= last) {
+            return Ok(());
+        }
+        debug!("Processing slot {}", slot);
+
+        engine.current_slot = engine.current_slot.max(slot);
+        engine.current_epoch = engine.slot_to_epoch(engine.current_slot);
+        engine.fork_choice.clear_proposer_boost();
+
+        if let Some(last) = self.last_slot {
+            for epoch in engine.slot_to_epoch(last)..engine.slot_to_epoch(slot) {
+                info!("Processing end of epoch {}", epoch);
+                engine.finalize_epoch(epoch)?;
+            }
+        }
+
+        self.last_slot = Some(slot);
         Ok(())
     }
 }
 
-impl Default for ConsensusEngineCore {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::test_utils::engine_with_validators;
+
+    fn engine() -> ConsensusEngine {
+        let keys: Vec<KeyPair> = (0..3).map(|_| KeyPair::generate()).collect();
+        engine_with_validators(&keys)
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_process_slot_runs_epoch_processing_once() {
+        let mut engine = engine();
+        let mut core = ConsensusEngineCore::new(SlotClock::new(0, 12, 4));
+        engine.fork_choice.proposer_boost_root = Some([1u8; 32]);
+
+        core.process_slot(&mut engine, 2).await.unwrap();
+        assert_eq!(engine.current_slot, 2);
+        assert_eq!(engine.fork_choice.proposer_boost_root, None);
+        assert_eq!(engine.validator_set.epoch, 0);
+
+        // Skipping ahead finalizes every epoch that ended in between
+        core.process_slot(&mut engine, 9).await.unwrap();
+        assert_eq!(engine.current_epoch, 2);
+        assert_eq!(engine.validator_set.epoch, 1);
+
+        // Replayed slots are ignored
+        engine.validator_set.epoch = 0;
+        core.process_slot(&mut engine, 9).await.unwrap();
+        core.process_slot(&mut engine, 3).await.unwrap();
+        assert_eq!(engine.validator_set.epoch, 0);
+        assert_eq!(core.last_slot(), Some(9));
+        assert_eq!(engine.validator_set.get_active_validators().len(), 3);
+    }
+}
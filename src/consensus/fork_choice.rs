@@ -30,6 +30,20 @@ impl ForkChoice {
         }
     }
 
+    /// Fork choice rooted at a genesis block, which is justified and
+    /// finalized at epoch 0
+    pub fn from_anchor(anchor: Block) -> Self {
+        let checkpoint = Checkpoint {
+            epoch: 0,
+            root: anchor.hash(),
+        };
+        let mut fork_choice = Self::new();
+        fork_choice.blocks.insert(checkpoint.root, anchor);
+        fork_choice.justified_checkpoint = checkpoint.clone();
+        fork_choice.finalized_checkpoint = checkpoint;
+        fork_choice
+    }
+
     pub fn add_block(&mut self, block: Block) {
         let block_hash = block.hash();
         self.blocks.insert(block_hash, block);
@@ -74,10 +88,11 @@ impl ForkChoice {
                 break;
             }
 
-            // Select child with highest weight
+            // Select child with highest weight, breaking ties by root so
+            // every node picks the same head
             let best_child = children
                 .into_iter()
-                .max_by_key(|&child_root| self.get_weight(child_root))
+                .max_by_key(|&child_root| (self.get_weight(child_root), child_root))
                 .unwrap();
 
             current_root = best_child;
@@ -1,17 +1,22 @@
+pub mod clock;
 pub mod engine;
 pub mod fork_choice;
 pub mod proposer_selection;
 pub mod attestation;
 pub mod slashing;
 
+pub use clock::*;
 pub use engine::*;
 pub use fork_choice::*;
 pub use proposer_selection::*;
 pub use attestation::*;
 pub use slashing::*;
 
+use crate::crypto::Vrf;
 use crate::types::*;
 use anyhow::Result;
+use std::collections::HashMap;
+use tracing::info;
 
 #[derive(Debug, Clone)]
 pub struct ConsensusEngine {
@@ -21,6 +26,9 @@ pub struct ConsensusEngine {
     pub current_epoch: Epoch,
     pub current_slot: Slot,
     pub proposer_selector: ProposerSelector,
+    /// Randomness after each block in fork choice, seeding VRF leader
+    /// election on the chains built on it
+    pub randomness: HashMap<Hash, ChainRandomness>,
 }
 
 impl ConsensusEngine {
@@ -31,6 +39,10 @@ impl ConsensusEngine {
             0,    // genesis epoch
         );
 
+        // Genesis validators are indexed in address order, whatever order
+        // they are listed in
+        let mut genesis_validators = genesis_validators;
+        genesis_validators.sort_by_key(|validator| validator.address.0);
         for validator in genesis_validators {
             validator_set.add_validator(validator).map_err(|e| anyhow::anyhow!(e))?;
         }
@@ -45,19 +57,45 @@ impl ConsensusEngine {
             current_epoch: 0,
             current_slot: 0,
             proposer_selector,
+            randomness: HashMap::new(),
         })
     }
 
+    /// Engine starting from a genesis block and its validator set
+    pub fn from_genesis(genesis: &crate::genesis::Genesis) -> Self {
+        let proposer_selector = ProposerSelector::new(genesis.config.clone());
+
+        ConsensusEngine {
+            config: genesis.config.clone(),
+            fork_choice: ForkChoice::from_anchor(genesis.block.clone()),
+            validator_set: genesis.validator_set.clone(),
+            current_epoch: 0,
+            current_slot: 0,
+            proposer_selector,
+            randomness: HashMap::new(),
+        }
+    }
+
     pub fn process_block(&mut self, block: &Block) -> Result<()> {
         // Validate block
         self.validate_block(block)?;
 
+        // Mix the block's VRF output into its chain's randomness
+        let vrf_output = block.header.vrf_proof.as_ref().map(Vrf::proof_to_hash).transpose()?;
+        let randomness = self
+            .chain_randomness(&block.header.previous_hash)
+            .next(block.header.epoch, vrf_output.as_ref());
+        self.randomness.insert(block.hash(), randomness);
+
         // Update fork choice
         self.fork_choice.add_block(block.clone());
 
-        // Update current slot/epoch
-        self.current_slot = block.header.slot;
-        self.current_epoch = block.header.epoch;
+        // Update current slot/epoch; a block imported on an older fork does
+        // not move the clock backwards
+        if block.header.slot > self.current_slot {
+            self.current_slot = block.header.slot;
+            self.current_epoch = block.header.epoch;
+        }
 
         // Process validator updates
         self.process_validator_updates(block)?;
@@ -72,9 +110,25 @@ impl ConsensusEngine {
         }
 
         // Check proposer
-        let expected_proposer = self.get_proposer_for_slot(block.header.slot)?;
-        if block.header.proposer != expected_proposer {
-            return Err(anyhow::anyhow!("Invalid proposer"));
+        match self.config.leader_election {
+            LeaderElection::Deterministic => {
+                let expected_proposer = self.get_proposer_for_slot(block.header.slot)?;
+                if block.header.proposer != expected_proposer {
+                    return Err(anyhow::anyhow!("Invalid proposer"));
+                }
+            }
+            LeaderElection::Vrf { .. } => {
+                let vrf_proof = block.header.vrf_proof
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Missing VRF proof"))?;
+                self.proposer_selector.verify_leadership(
+                    block.header.slot,
+                    &self.seed_mix(&block.header.previous_hash, block.header.epoch),
+                    &block.header.proposer,
+                    vrf_proof,
+                    &self.validator_set,
+                )?;
+            }
         }
 
         // Verify proposer signature
@@ -84,9 +138,18 @@ impl ConsensusEngine {
 
         block.verify_signature(&validator.public_key)?;
 
-        // Check slot is valid
-        if block.header.slot <= self.current_slot {
-            return Err(anyhow::anyhow!("Block slot is not in the future"));
+        // Check slot is valid. A block only has to be newer than its parent
+        // so that blocks from competing forks can still be imported.
+        let parent_slot = if block.header.previous_hash == [0u8; 32] {
+            0
+        } else {
+            match self.fork_choice.get_block(&block.header.previous_hash) {
+                Some(parent) => parent.header.slot,
+                None => self.current_slot,
+            }
+        };
+        if block.header.slot <= parent_slot {
+            return Err(anyhow::anyhow!("Block slot is not after its parent"));
         }
 
         // Validate epoch
@@ -98,7 +161,22 @@ impl ConsensusEngine {
         Ok(())
     }
 
+    /// Randomness after a block; blocks not in fork choice, such as the
+    /// parent of the first block, have the initial randomness
+    pub fn chain_randomness(&self, hash: &Hash) -> ChainRandomness {
+        self.randomness.get(hash).copied().unwrap_or_default()
+    }
+
+    /// Randomness mix seeding VRF leader election in `epoch` for a block
+    /// built on `parent`
+    pub fn seed_mix(&self, parent: &Hash, epoch: Epoch) -> Hash {
+        self.chain_randomness(parent).seed_mix(epoch)
+    }
+
     pub fn get_proposer_for_slot(&self, slot: Slot) -> Result<Address> {
+        if let LeaderElection::Vrf { .. } = self.config.leader_election {
+            return Err(anyhow::anyhow!("Proposers are private under VRF leader election"));
+        }
         self.proposer_selector.select_proposer(slot, &self.validator_set)
     }
 
@@ -111,7 +189,7 @@ impl ConsensusEngine {
     }
 
     pub fn is_epoch_boundary(&self, slot: Slot) -> bool {
-        slot % self.config.slots_per_epoch == 0
+        slot.is_multiple_of(self.config.slots_per_epoch)
     }
 
     pub fn get_head(&self) -> Option<Hash> {
@@ -130,14 +208,23 @@ impl ConsensusEngine {
 
     pub fn validate_attestation(&self, attestation: &Attestation) -> Result<()> {
         // Check if validator exists and is active
-        let validator_index = attestation.validator_index;
-        if validator_index as usize >= self.validator_set.validators.len() {
-            return Err(anyhow::anyhow!("Invalid validator index"));
+        let validator = self.validator_set
+            .validator_by_index(attestation.validator_index)
+            .filter(|validator| validator.is_eligible(self.validator_set.min_stake))
+            .ok_or_else(|| anyhow::anyhow!("Invalid validator index"))?;
+
+        if attestation.source_epoch > attestation.target_epoch {
+            return Err(anyhow::anyhow!("Attestation source is newer than its target"));
         }
 
+        crate::crypto::CryptoProvider::verify(
+            &validator.public_key,
+            &attestation.signing_root(),
+            &attestation.signature,
+        )
+        .map_err(|_| anyhow::anyhow!("Invalid attestation signature"))?;
+
         // Additional attestation validation logic would go here
-        // - Check attestation data
-        // - Verify signature
         // - Check slashing conditions
 
         Ok(())
@@ -161,10 +248,12 @@ impl ConsensusEngine {
 
     pub fn finalize_epoch(&mut self, epoch: Epoch) -> Result<()> {
         // Process epoch finalization
+        // - Justify and finalize the epoch's checkpoint
         // - Calculate rewards
         // - Process slashings
         // - Update validator set
 
+        self.process_justification(epoch)?;
         self.calculate_rewards(epoch)?;
         self.process_slashings(epoch)?;
         self.update_validator_set(epoch)?;
@@ -172,6 +261,45 @@ impl ConsensusEngine {
         Ok(())
     }
 
+    /// Checkpoint at the start of `epoch` on the head chain, if a
+    /// two-thirds supermajority of active validators' latest votes are for
+    /// blocks of that epoch or later descending from it
+    pub fn supermajority_checkpoint(&self, epoch: Epoch) -> Option<Checkpoint> {
+        let fork_choice = &self.fork_choice;
+        let epoch_start = self.epoch_to_slot(epoch);
+        let root = fork_choice.get_ancestor(self.get_head()?, epoch_start)?;
+
+        let active = self.validator_set.active_indices();
+        let supporting = active
+            .iter()
+            .filter_map(|index| fork_choice.latest_messages.get(index))
+            .filter(|vote| {
+                fork_choice.get_block(vote).is_some_and(|block| block.header.slot >= epoch_start)
+                    && fork_choice.is_descendant(root, **vote)
+            })
+            .count();
+
+        (!active.is_empty() && supporting * 3 >= active.len() * 2).then_some(Checkpoint { epoch, root })
+    }
+
+    /// Justify and finalize the checkpoint of `epoch` at once when it has a
+    /// supermajority
+    fn process_justification(&mut self, epoch: Epoch) -> Result<()> {
+        let Some(checkpoint) = self.supermajority_checkpoint(epoch) else {
+            return Ok(());
+        };
+        if self.fork_choice.justified_checkpoint.epoch < epoch {
+            self.fork_choice.update_justified_checkpoint(checkpoint.clone())?;
+        }
+        if self.fork_choice.finalized_checkpoint.epoch < epoch {
+            info!("Finalized epoch {} at {}", epoch, hex::encode(checkpoint.root));
+            self.fork_choice.update_finalized_checkpoint(checkpoint)?;
+            let fork_choice = &self.fork_choice;
+            self.randomness.retain(|hash, _| fork_choice.has_block(hash));
+        }
+        Ok(())
+    }
+
     fn calculate_rewards(&mut self, epoch: Epoch) -> Result<()> {
         // Calculate and distribute rewards for the epoch
         let total_rewards = self.calculate_total_rewards(epoch);
@@ -225,10 +353,10 @@ impl ConsensusEngine {
             }
 
             // Process exiting validators
-            if matches!(validator.status, ValidatorStatus::Exiting) {
-                if epoch >= validator.last_active_epoch + self.config.min_validator_withdrawability_delay {
-                    validators_to_remove.push(*address);
-                }
+            if matches!(validator.status, ValidatorStatus::Exiting)
+                && epoch >= validator.last_active_epoch + self.config.min_validator_withdrawability_delay
+            {
+                validators_to_remove.push(*address);
             }
         }
 
@@ -1,15 +1,58 @@
 use crate::types::*;
-use crate::crypto::Hasher;
+use crate::crypto::{Hasher, KeyPair, Vrf, VrfOutput};
 use anyhow::Result;
 
+/// Chain randomness after a block: an accumulator of the VRF outputs of the
+/// block and its ancestors, and its value at the end of the two epochs
+/// before the block's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainRandomness {
+    pub epoch: Epoch,
+    pub mix: Hash,
+    pub previous_epoch_mix: Hash,
+    pub two_epochs_ago_mix: Hash,
+}
+
+impl ChainRandomness {
+    /// Randomness after a child block in `epoch`, mixing in its VRF output.
+    /// Blocks without a VRF proof leave the mix unchanged.
+    pub fn next(&self, epoch: Epoch, vrf_output: Option<&VrfOutput>) -> Self {
+        let mix = match vrf_output {
+            Some(output) => Hasher::hash_with_domain(b"RANDAO_MIX", &[&self.mix[..], &output[..]].concat()),
+            None => self.mix,
+        };
+        let (previous_epoch_mix, two_epochs_ago_mix) = match epoch.saturating_sub(self.epoch) {
+            0 => (self.previous_epoch_mix, self.two_epochs_ago_mix),
+            1 => (self.mix, self.previous_epoch_mix),
+            _ => (self.mix, self.mix),
+        };
+        ChainRandomness { epoch, mix, previous_epoch_mix, two_epochs_ago_mix }
+    }
+
+    /// The mix at the end of epoch `epoch - 2` on this chain, which seeds
+    /// `epoch`. It is fixed before anyone can propose in `epoch - 1`, so the
+    /// last proposers before an epoch cannot grind its seed.
+    pub fn seed_mix(&self, epoch: Epoch) -> Hash {
+        match epoch.saturating_sub(self.epoch) {
+            0 => self.two_epochs_ago_mix,
+            1 => self.previous_epoch_mix,
+            _ => self.mix,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProposerSelector {
-    _config: ConsensusConfig,
+    config: ConsensusConfig,
 }
 
 impl ProposerSelector {
     pub fn new(config: ConsensusConfig) -> Self {
-        ProposerSelector { _config: config }
+        ProposerSelector { config }
+    }
+
+    pub fn leader_election(&self) -> LeaderElection {
+        self.config.leader_election
     }
 
     pub fn select_proposer(&self, slot: Slot, validator_set: &ValidatorSet) -> Result<Address> {
@@ -43,6 +86,101 @@ impl ProposerSelector {
         Hasher::hash(&slot.to_le_bytes())
     }
 
+    /// Seed of `epoch` from the chain's randomness mix, see
+    /// `ChainRandomness::seed_mix`
+    pub fn get_epoch_seed(&self, epoch: Epoch, mix: &Hash) -> Hash {
+        Hasher::hash_with_domain(b"EPOCH_SEED", &[&epoch.to_le_bytes()[..], &mix[..]].concat())
+    }
+
+    /// VRF input for a slot: the epoch seed followed by the slot number
+    pub fn vrf_input(&self, slot: Slot, mix: &Hash) -> Vec<u8> {
+        let epoch = slot / self.config.slots_per_epoch;
+        let mut input = self.get_epoch_seed(epoch, mix).to_vec();
+        input.extend_from_slice(&slot.to_le_bytes());
+        input
+    }
+
+    /// Evaluate the VRF for `keypair` at `slot`, seeded by the parent
+    /// chain's `mix`. Returns the proof to include in the block header if the
+    /// validator is eligible to propose.
+    pub fn prove_leadership(
+        &self,
+        slot: Slot,
+        mix: &Hash,
+        keypair: &KeyPair,
+        validator_set: &ValidatorSet,
+    ) -> Result<Option<VrfProof>> {
+        let coefficient = self.active_slot_coefficient()?;
+        let stake = self.eligible_stake(&keypair.address, validator_set)?;
+
+        let (proof, output) = Vrf::prove(&keypair.private_key, &self.vrf_input(slot, mix))?;
+        if self.is_below_threshold(&output, stake, validator_set, coefficient) {
+            Ok(Some(proof))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Check that `proof` shows `proposer` was eligible to propose at `slot`
+    /// on a chain with randomness `mix`
+    pub fn verify_leadership(
+        &self,
+        slot: Slot,
+        mix: &Hash,
+        proposer: &Address,
+        proof: &VrfProof,
+        validator_set: &ValidatorSet,
+    ) -> Result<()> {
+        let coefficient = self.active_slot_coefficient()?;
+        let stake = self.eligible_stake(proposer, validator_set)?;
+        let validator = &validator_set.validators[proposer];
+
+        let output = Vrf::verify(&validator.public_key, &self.vrf_input(slot, mix), proof)?;
+        if !self.is_below_threshold(&output, stake, validator_set, coefficient) {
+            return Err(anyhow::anyhow!("VRF output above proposer threshold"));
+        }
+
+        Ok(())
+    }
+
+    fn active_slot_coefficient(&self) -> Result<u16> {
+        match self.config.leader_election {
+            LeaderElection::Vrf { active_slot_coefficient } => Ok(active_slot_coefficient),
+            LeaderElection::Deterministic => Err(anyhow::anyhow!("VRF leader election is not enabled")),
+        }
+    }
+
+    fn eligible_stake(&self, address: &Address, validator_set: &ValidatorSet) -> Result<Amount> {
+        validator_set.validators
+            .get(address)
+            .filter(|v| v.is_eligible(validator_set.min_stake))
+            .map(|v| v.total_stake())
+            .ok_or_else(|| anyhow::anyhow!("Proposer is not an active validator"))
+    }
+
+    fn is_below_threshold(
+        &self,
+        output: &VrfOutput,
+        stake: Amount,
+        validator_set: &ValidatorSet,
+        coefficient: u16,
+    ) -> bool {
+        let total_stake: u128 = validator_set
+            .get_active_validators()
+            .iter()
+            .map(|v| v.total_stake() as u128)
+            .sum();
+        if total_stake == 0 {
+            return false;
+        }
+
+        // Eligible with probability coefficient * stake / total_stake, using
+        // 32 bits of the VRF output
+        let value = u32::from_le_bytes([output[0], output[1], output[2], output[3]]) as u128;
+        let threshold = ((stake as u128 * coefficient as u128) << 32) / (total_stake * 10_000);
+        value < threshold
+    }
+
     fn bytes_to_u128(&self, bytes: &Hash) -> u128 {
         u128::from_le_bytes([
             bytes[0], bytes[1], bytes[2], bytes[3],
@@ -52,22 +190,43 @@ impl ProposerSelector {
         ])
     }
 
+    /// Registry indices of the validators attesting at `slot`
     pub fn get_committee(&self, slot: Slot, committee_index: u64, validator_set: &ValidatorSet) -> Vec<u64> {
-        let active_validators = validator_set.get_active_validators();
-        if active_validators.is_empty() {
+        let mut indices = validator_set.active_indices();
+        if indices.is_empty() {
             return Vec::new();
         }
 
-        let committee_size = active_validators.len().min(128); // Max committee size
+        let committee_size = indices.len().min(128); // Max committee size
         let seed = self.get_committee_seed(slot, committee_index);
 
         // Shuffle validators deterministically
-        let mut indices: Vec<u64> = (0..active_validators.len() as u64).collect();
         self.shuffle(&mut indices, &seed);
 
         indices.into_iter().take(committee_size).collect()
     }
 
+    /// Committees in `epoch` that include the given validator, one
+    /// committee per slot
+    pub fn get_committee_assignments(
+        &self,
+        epoch: Epoch,
+        validator_index: u64,
+        validator_set: &ValidatorSet,
+    ) -> Vec<CommitteeAssignment> {
+        let start_slot = epoch * self.config.slots_per_epoch;
+        (start_slot..start_slot + self.config.slots_per_epoch)
+            .filter_map(|slot| {
+                let validators = self.get_committee(slot, 0, validator_set);
+                validators.contains(&validator_index).then_some(CommitteeAssignment {
+                    slot,
+                    committee_index: 0,
+                    validators,
+                })
+            })
+            .collect()
+    }
+
     fn get_committee_seed(&self, slot: Slot, committee_index: u64) -> Hash {
         let mut data = Vec::new();
         data.extend_from_slice(&slot.to_le_bytes());
@@ -153,6 +312,56 @@ mod tests {
         assert!(committee.len() <= 10);
     }
 
+    #[test]
+    fn test_vrf_leader_election() {
+        let config = ConsensusConfig {
+            leader_election: LeaderElection::Vrf { active_slot_coefficient: 10_000 },
+            ..ConsensusConfig::default()
+        };
+        let selector = ProposerSelector::new(config);
+
+        let keypair = KeyPair::generate();
+        let mut validator = create_test_validator(keypair.address, 5000);
+        validator.public_key = keypair.public_key;
+
+        let mut validator_set = ValidatorSet::new(1000, 100, 0);
+        validator_set.add_validator(validator).unwrap();
+
+        // A sole validator with a coefficient of 1.0 is always eligible
+        let mix = [3u8; 32];
+        let proof = selector.prove_leadership(7, &mix, &keypair, &validator_set).unwrap().unwrap();
+        assert!(selector.verify_leadership(7, &mix, &keypair.address, &proof, &validator_set).is_ok());
+        assert!(selector.verify_leadership(8, &mix, &keypair.address, &proof, &validator_set).is_err());
+        assert!(selector.verify_leadership(7, &[4u8; 32], &keypair.address, &proof, &validator_set).is_err());
+    }
+
+    #[test]
+    fn test_epoch_seed_uses_mix_from_two_epochs_earlier() {
+        let output = [9u8; 64];
+        let genesis = ChainRandomness::default();
+
+        // Outputs mixed in during epoch 1 only seed epoch 3 onwards
+        let first = genesis.next(1, Some(&output));
+        let second = first.next(1, Some(&output));
+        assert_ne!(second.mix, genesis.mix);
+        assert_eq!(second.seed_mix(1), genesis.mix);
+        assert_eq!(second.seed_mix(2), genesis.mix);
+        assert_eq!(second.seed_mix(3), second.mix);
+
+        // Crossing into epoch 2 and 3 carries the end of epoch 1 along
+        let third = second.next(2, Some(&output));
+        assert_eq!(third.seed_mix(3), second.mix);
+        let fourth = third.next(3, None);
+        assert_eq!(fourth.mix, third.mix);
+        assert_eq!(fourth.seed_mix(3), second.mix);
+        assert_eq!(fourth.seed_mix(4), third.mix);
+
+        // Skipped epochs seed from the last block before them
+        let skipped = second.next(5, None);
+        assert_eq!(skipped.seed_mix(5), second.mix);
+        assert_eq!(skipped.seed_mix(6), second.mix);
+    }
+
     #[test]
     fn test_deterministic_selection() {
         let config = ConsensusConfig::default();
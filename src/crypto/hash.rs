@@ -107,6 +107,18 @@ pub fn compute_domain(domain_type: &[u8; 4], fork_version: &[u8; 4], genesis_val
     domain.finalize()
 }
 
+/// Short identifier of a fork, used to namespace gossip topics
+pub fn compute_fork_digest(fork_version: &[u8; 4], genesis_validators_root: &Hash) -> [u8; 4] {
+    let mut fork_data_root = HashBuilder::new();
+    fork_data_root
+        .update(fork_version)
+        .update(genesis_validators_root);
+
+    let mut digest = [0u8; 4];
+    digest.copy_from_slice(&fork_data_root.finalize()[..4]);
+    digest
+}
+
 pub fn compute_signing_root(object_root: &Hash, domain: &Hash) -> Hash {
     let mut signing_root = HashBuilder::new();
     signing_root
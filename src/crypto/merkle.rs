@@ -62,7 +62,7 @@ impl MerkleTree {
         let mut current_index = index;
 
         for level in &self.levels[..self.levels.len() - 1] {
-            let sibling_index = if current_index % 2 == 0 {
+            let sibling_index = if current_index.is_multiple_of(2) {
                 current_index + 1
             } else {
                 current_index - 1
@@ -71,7 +71,7 @@ impl MerkleTree {
             if sibling_index < level.len() {
                 proof.push(MerkleProofElement {
                     hash: level[sibling_index],
-                    is_left: current_index % 2 != 0,
+                    is_left: !current_index.is_multiple_of(2),
                 });
             }
 
@@ -146,95 +146,6 @@ impl MerkleProof {
     }
 }
 
-pub struct SparseMerkleTree {
-    pub root: Hash,
-    pub depth: usize,
-    pub nodes: std::collections::HashMap<(usize, u64), Hash>,
-    pub default_hashes: Vec<Hash>,
-}
-
-impl SparseMerkleTree {
-    pub fn new(depth: usize) -> Self {
-        let mut default_hashes = vec![[0u8; 32]; depth + 1];
-
-        // Calculate default hashes bottom-up
-        for i in (0..depth).rev() {
-            default_hashes[i] = Hasher::hash_two(&default_hashes[i + 1], &default_hashes[i + 1]);
-        }
-
-        SparseMerkleTree {
-            root: default_hashes[0],
-            depth,
-            nodes: std::collections::HashMap::new(),
-            default_hashes,
-        }
-    }
-
-    pub fn update(&mut self, index: u64, value: Hash) {
-        self.update_recursive(0, 0, 1u64 << self.depth, index, value);
-    }
-
-    fn update_recursive(&mut self, level: usize, start: u64, size: u64, index: u64, value: Hash) {
-        if size == 1 {
-            self.nodes.insert((level, start), value);
-            return;
-        }
-
-        let mid = start + size / 2;
-        if index < mid {
-            self.update_recursive(level + 1, start, size / 2, index, value);
-        } else {
-            self.update_recursive(level + 1, mid, size / 2, index, value);
-        }
-
-        let left = self.get_node(level + 1, start);
-        let right = self.get_node(level + 1, mid);
-        let new_hash = Hasher::hash_two(&left, &right);
-
-        self.nodes.insert((level, start), new_hash);
-
-        if level == 0 {
-            self.root = new_hash;
-        }
-    }
-
-    fn get_node(&self, level: usize, index: u64) -> Hash {
-        self.nodes
-            .get(&(level, index))
-            .copied()
-            .unwrap_or(self.default_hashes[level])
-    }
-
-    pub fn get_proof(&self, index: u64) -> Vec<Hash> {
-        let mut proof = Vec::new();
-        let mut current_index = index;
-
-        for level in (1..=self.depth).rev() {
-            let sibling_index = current_index ^ 1;
-            proof.push(self.get_node(level, sibling_index));
-            current_index /= 2;
-        }
-
-        proof
-    }
-
-    pub fn verify_proof(&self, index: u64, value: Hash, proof: &[Hash]) -> bool {
-        let mut current_hash = value;
-        let mut current_index = index;
-
-        for &sibling_hash in proof {
-            current_hash = if current_index % 2 == 0 {
-                Hasher::hash_two(&current_hash, &sibling_hash)
-            } else {
-                Hasher::hash_two(&sibling_hash, &current_hash)
-            };
-            current_index /= 2;
-        }
-
-        current_hash == self.root
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,13 +171,14 @@ mod tests {
 
     #[test]
     fn test_sparse_merkle_tree() {
-        let mut smt = SparseMerkleTree::new(8);
+        let mut smt = crate::crypto::SparseMerkleTree::new();
+        let key = Hasher::hash(b"5");
         let value = Hasher::hash(b"test");
 
-        smt.update(5, value);
-        let proof = smt.get_proof(5);
+        smt.update(key, value);
+        let proof = smt.get_proof(&key);
 
-        assert!(smt.verify_proof(5, value, &proof));
+        assert!(proof.verify_membership(&smt.root(), &key, &value));
     }
 
     #[test]
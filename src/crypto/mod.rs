@@ -2,11 +2,15 @@ pub mod keys;
 pub mod signatures;
 pub mod hash;
 pub mod merkle;
+pub mod sparse_merkle;
+pub mod vrf;
 
 pub use keys::*;
 pub use signatures::*;
 pub use hash::*;
 pub use merkle::*;
+pub use sparse_merkle::*;
+pub use vrf::*;
 
 use crate::types::{Hash, Signature, PublicKey, PrivateKey};
 use anyhow::Result;
@@ -1,6 +1,7 @@
 use crate::types::{Signature, PublicKey, Hash};
 use anyhow::{Result, anyhow};
 use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
 
 pub struct SignatureUtils;
 
@@ -76,7 +77,7 @@ impl SignatureUtils {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MultiSignature {
     pub signatures: Vec<Signature>,
     pub signers: Vec<PublicKey>,
@@ -119,6 +120,33 @@ impl MultiSignature {
 
         Ok(())
     }
+
+    /// Verify against a declared signer set and threshold rather than the
+    /// threshold carried in the signature itself
+    pub fn verify_with_signers(&self, message: &[u8], allowed: &[PublicKey], threshold: usize) -> Result<()> {
+        if self.signatures.len() != self.signers.len() {
+            return Err(anyhow!("Mismatched signatures and signers"));
+        }
+
+        for (i, signer) in self.signers.iter().enumerate() {
+            if !allowed.contains(signer) {
+                return Err(anyhow!("Signer {} is not a member of the signer set", hex::encode(signer)));
+            }
+            if self.signers[..i].contains(signer) {
+                return Err(anyhow!("Duplicate signer"));
+            }
+        }
+
+        if self.signers.len() < threshold {
+            return Err(anyhow!("Insufficient signatures: {} of {} required", self.signers.len(), threshold));
+        }
+
+        for (signature, signer) in self.signatures.iter().zip(self.signers.iter()) {
+            SignatureUtils::verify(signer, message, signature)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -165,4 +193,21 @@ mod tests {
         assert!(multi_sig.is_valid());
         assert!(multi_sig.verify(message).is_ok());
     }
+
+    #[test]
+    fn test_multi_signature_signer_set() {
+        let keypair1 = KeyPair::generate();
+        let keypair2 = KeyPair::generate();
+        let outsider = KeyPair::generate();
+        let message = b"test message";
+        let allowed = [keypair1.public_key, keypair2.public_key];
+
+        let mut multi_sig = MultiSignature::new(1);
+        multi_sig.add_signature(SignatureUtils::sign(&keypair1.signing_key(), message), keypair1.public_key).unwrap();
+        assert!(multi_sig.verify_with_signers(message, &allowed, 1).is_ok());
+        assert!(multi_sig.verify_with_signers(message, &allowed, 2).is_err());
+
+        multi_sig.add_signature(SignatureUtils::sign(&outsider.signing_key(), message), outsider.public_key).unwrap();
+        assert!(multi_sig.verify_with_signers(message, &allowed, 1).is_err());
+    }
 }
\ No newline at end of file
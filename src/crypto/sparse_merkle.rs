@@ -0,0 +1,447 @@
+use crate::types::Hash;
+use crate::crypto::Hasher;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Depth of the tree: one level per bit of a 256-bit key
+pub const SMT_DEPTH: usize = 256;
+
+/// Leaf value used for keys that are not present in the tree
+pub const EMPTY_LEAF: Hash = [0u8; 32];
+
+/// Default (empty subtree) hash for every depth, where index 0 is the root
+/// and index `SMT_DEPTH` is a leaf.
+pub fn default_hashes() -> &'static [Hash; SMT_DEPTH + 1] {
+    static DEFAULTS: OnceLock<[Hash; SMT_DEPTH + 1]> = OnceLock::new();
+    DEFAULTS.get_or_init(|| {
+        let mut defaults = [EMPTY_LEAF; SMT_DEPTH + 1];
+        for depth in (0..SMT_DEPTH).rev() {
+            defaults[depth] = Hasher::hash_two(&defaults[depth + 1], &defaults[depth + 1]);
+        }
+        defaults
+    })
+}
+
+/// Position of a node in the tree: its depth and the key prefix leading to it.
+/// Bits of `path` beyond `depth` are always zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NodeKey {
+    pub depth: u16,
+    pub path: Hash,
+}
+
+impl NodeKey {
+    pub fn new(depth: usize, key: &Hash) -> Self {
+        NodeKey {
+            depth: depth as u16,
+            path: truncate_path(key, depth),
+        }
+    }
+
+    pub fn root() -> Self {
+        NodeKey {
+            depth: 0,
+            path: [0u8; 32],
+        }
+    }
+}
+
+/// Backing store for sparse Merkle tree nodes. Only non-default nodes are
+/// stored, so an empty store represents an empty tree.
+pub trait NodeStore {
+    fn get_node(&self, key: &NodeKey) -> Option<Hash>;
+    fn put_node(&mut self, key: NodeKey, hash: Hash);
+    fn remove_node(&mut self, key: &NodeKey);
+}
+
+/// In-memory node store
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNodeStore {
+    pub nodes: HashMap<NodeKey, Hash>,
+}
+
+impl MemoryNodeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl NodeStore for MemoryNodeStore {
+    fn get_node(&self, key: &NodeKey) -> Option<Hash> {
+        self.nodes.get(key).copied()
+    }
+
+    fn put_node(&mut self, key: NodeKey, hash: Hash) {
+        self.nodes.insert(key, hash);
+    }
+
+    fn remove_node(&mut self, key: &NodeKey) {
+        self.nodes.remove(key);
+    }
+}
+
+/// Node store that reads through to a base store and keeps its own
+/// writes, so updates can be tried without changing the base
+pub struct OverlayNodeStore<'a, S: NodeStore> {
+    base: &'a S,
+    changes: HashMap<NodeKey, Option<Hash>>,
+}
+
+impl<'a, S: NodeStore> OverlayNodeStore<'a, S> {
+    pub fn new(base: &'a S) -> Self {
+        OverlayNodeStore {
+            base,
+            changes: HashMap::new(),
+        }
+    }
+}
+
+impl<S: NodeStore> NodeStore for OverlayNodeStore<'_, S> {
+    fn get_node(&self, key: &NodeKey) -> Option<Hash> {
+        match self.changes.get(key) {
+            Some(change) => *change,
+            None => self.base.get_node(key),
+        }
+    }
+
+    fn put_node(&mut self, key: NodeKey, hash: Hash) {
+        self.changes.insert(key, Some(hash));
+    }
+
+    fn remove_node(&mut self, key: &NodeKey) {
+        self.changes.insert(*key, None);
+    }
+}
+
+/// Sparse Merkle tree over 256-bit keys. Every key maps to a leaf at depth
+/// 256; absent keys hold `EMPTY_LEAF`, so setting a value to `EMPTY_LEAF`
+/// deletes it.
+#[derive(Debug, Clone, Default)]
+pub struct SparseMerkleTree<S: NodeStore = MemoryNodeStore> {
+    pub store: S,
+}
+
+impl SparseMerkleTree<MemoryNodeStore> {
+    pub fn new() -> Self {
+        SparseMerkleTree {
+            store: MemoryNodeStore::new(),
+        }
+    }
+}
+
+impl<S: NodeStore> SparseMerkleTree<S> {
+    /// Open a tree backed by an existing store. The root is read from the
+    /// store, so a previously persisted tree is restored as-is.
+    pub fn with_store(store: S) -> Self {
+        SparseMerkleTree { store }
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    pub fn root(&self) -> Hash {
+        self.get_node(0, &[0u8; 32])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root() == default_hashes()[0]
+    }
+
+    pub fn get(&self, key: &Hash) -> Option<Hash> {
+        let leaf = self.get_node(SMT_DEPTH, key);
+        if leaf == EMPTY_LEAF {
+            None
+        } else {
+            Some(leaf)
+        }
+    }
+
+    pub fn contains(&self, key: &Hash) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn update(&mut self, key: Hash, value: Hash) -> Hash {
+        self.update_batch(&[(key, value)])
+    }
+
+    pub fn remove(&mut self, key: &Hash) -> Hash {
+        self.update(*key, EMPTY_LEAF)
+    }
+
+    /// Apply several updates at once, hashing each affected internal node a
+    /// single time. If a key appears more than once the last value wins.
+    /// Returns the new root.
+    pub fn update_batch(&mut self, entries: &[(Hash, Hash)]) -> Hash {
+        if entries.is_empty() {
+            return self.root();
+        }
+
+        let mut sorted: Vec<(Hash, Hash)> = entries.to_vec();
+        // Stable sort keeps insertion order among equal keys
+        sorted.sort_by_key(|(key, _)| *key);
+        let mut deduped: Vec<(Hash, Hash)> = Vec::with_capacity(sorted.len());
+        for (key, value) in sorted {
+            match deduped.last_mut() {
+                Some(last) if last.0 == key => last.1 = value,
+                _ => deduped.push((key, value)),
+            }
+        }
+
+        self.update_subtree(0, &deduped)
+    }
+
+    fn update_subtree(&mut self, depth: usize, entries: &[(Hash, Hash)]) -> Hash {
+        let path = entries[0].0;
+
+        let hash = if depth == SMT_DEPTH {
+            entries[0].1
+        } else {
+            let split = entries.partition_point(|(key, _)| !get_bit(key, depth));
+            let (left_entries, right_entries) = entries.split_at(split);
+
+            let left = if left_entries.is_empty() {
+                self.get_node(depth + 1, &with_bit(&path, depth, false))
+            } else {
+                self.update_subtree(depth + 1, left_entries)
+            };
+            let right = if right_entries.is_empty() {
+                self.get_node(depth + 1, &with_bit(&path, depth, true))
+            } else {
+                self.update_subtree(depth + 1, right_entries)
+            };
+
+            Hasher::hash_two(&left, &right)
+        };
+
+        let node_key = NodeKey::new(depth, &path);
+        if hash == default_hashes()[depth] {
+            self.store.remove_node(&node_key);
+        } else {
+            self.store.put_node(node_key, hash);
+        }
+
+        hash
+    }
+
+    fn get_node(&self, depth: usize, key: &Hash) -> Hash {
+        self.store
+            .get_node(&NodeKey::new(depth, key))
+            .unwrap_or(default_hashes()[depth])
+    }
+
+    /// Root the tree would have after `update_batch(entries)`, leaving the
+    /// tree unchanged
+    pub fn root_after(&self, entries: &[(Hash, Hash)]) -> Hash {
+        SparseMerkleTree::with_store(OverlayNodeStore::new(&self.store)).update_batch(entries)
+    }
+
+    /// Build a proof for `key`. The proof shows membership when the key is
+    /// present and non-membership otherwise.
+    pub fn get_proof(&self, key: &Hash) -> SparseMerkleProof {
+        let defaults = default_hashes();
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::new();
+
+        for depth in (1..=SMT_DEPTH).rev() {
+            let sibling_path = with_bit(key, depth - 1, !get_bit(key, depth - 1));
+            let sibling = self.get_node(depth, &sibling_path);
+            if sibling != defaults[depth] {
+                set_bit(&mut bitmap, depth - 1);
+                siblings.push(sibling);
+            }
+        }
+
+        SparseMerkleProof {
+            key: *key,
+            value: self.get(key),
+            bitmap,
+            siblings,
+        }
+    }
+}
+
+/// Compact sparse Merkle proof. Siblings equal to the default hash for their
+/// depth are omitted and flagged as absent in `bitmap`; bit `d - 1` is set
+/// when the sibling at depth `d` is present. `siblings` is ordered from the
+/// leaf upward.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SparseMerkleProof {
+    pub key: Hash,
+    pub value: Option<Hash>,
+    pub bitmap: [u8; 32],
+    pub siblings: Vec<Hash>,
+}
+
+impl SparseMerkleProof {
+    pub fn is_membership(&self) -> bool {
+        self.value.is_some()
+    }
+
+    /// Recompute the root implied by this proof
+    pub fn compute_root(&self) -> Option<Hash> {
+        let defaults = default_hashes();
+        let mut siblings = self.siblings.iter();
+        let mut current = self.value.unwrap_or(EMPTY_LEAF);
+
+        for depth in (1..=SMT_DEPTH).rev() {
+            let sibling = if get_bit(&self.bitmap, depth - 1) {
+                *siblings.next()?
+            } else {
+                defaults[depth]
+            };
+
+            current = if get_bit(&self.key, depth - 1) {
+                Hasher::hash_two(&sibling, &current)
+            } else {
+                Hasher::hash_two(&current, &sibling)
+            };
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+
+        Some(current)
+    }
+
+    pub fn verify(&self, root: &Hash) -> bool {
+        self.compute_root().is_some_and(|computed| computed == *root)
+    }
+
+    pub fn verify_membership(&self, root: &Hash, key: &Hash, value: &Hash) -> bool {
+        self.key == *key && self.value == Some(*value) && *value != EMPTY_LEAF && self.verify(root)
+    }
+
+    pub fn verify_non_membership(&self, root: &Hash, key: &Hash) -> bool {
+        self.key == *key && self.value.is_none() && self.verify(root)
+    }
+}
+
+fn get_bit(bytes: &Hash, index: usize) -> bool {
+    (bytes[index / 8] >> (7 - index % 8)) & 1 == 1
+}
+
+fn set_bit(bytes: &mut Hash, index: usize) {
+    bytes[index / 8] |= 1 << (7 - index % 8);
+}
+
+fn with_bit(bytes: &Hash, index: usize, value: bool) -> Hash {
+    let mut result = *bytes;
+    if value {
+        result[index / 8] |= 1 << (7 - index % 8);
+    } else {
+        result[index / 8] &= !(1 << (7 - index % 8));
+    }
+    result
+}
+
+fn truncate_path(key: &Hash, depth: usize) -> Hash {
+    let mut path = *key;
+    for (i, byte) in path.iter_mut().enumerate() {
+        let bit_start = i * 8;
+        if bit_start >= depth {
+            *byte = 0;
+        } else if bit_start + 8 > depth {
+            *byte &= 0xffu8 << (bit_start + 8 - depth);
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_merkle_tree() {
+        let mut smt = SparseMerkleTree::new();
+        let key = Hasher::hash(b"key");
+        let value = Hasher::hash(b"test");
+
+        let root = smt.update(key, value);
+        assert_eq!(root, smt.root());
+
+        let proof = smt.get_proof(&key);
+        assert!(proof.is_membership());
+        assert!(proof.verify_membership(&root, &key, &value));
+        assert!(!proof.verify_membership(&root, &key, &Hasher::hash(b"other")));
+    }
+
+    #[test]
+    fn test_non_membership_proof() {
+        let mut smt = SparseMerkleTree::new();
+        smt.update(Hasher::hash(b"a"), Hasher::hash(b"1"));
+        smt.update(Hasher::hash(b"b"), Hasher::hash(b"2"));
+        let root = smt.root();
+
+        let missing = Hasher::hash(b"c");
+        let proof = smt.get_proof(&missing);
+        assert!(!proof.is_membership());
+        assert!(proof.verify_non_membership(&root, &missing));
+        assert!(!proof.verify_non_membership(&root, &Hasher::hash(b"a")));
+    }
+
+    #[test]
+    fn test_compact_proof_omits_default_siblings() {
+        let mut smt = SparseMerkleTree::new();
+        let key = Hasher::hash(b"only");
+        smt.update(key, Hasher::hash(b"value"));
+
+        let proof = smt.get_proof(&key);
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(&smt.root()));
+    }
+
+    #[test]
+    fn test_batch_update_matches_sequential() {
+        let entries: Vec<(Hash, Hash)> = (0u8..20)
+            .map(|i| (Hasher::hash(&[i]), Hasher::hash(&[i, i])))
+            .collect();
+
+        let mut sequential = SparseMerkleTree::new();
+        for (key, value) in &entries {
+            sequential.update(*key, *value);
+        }
+
+        let mut batched = SparseMerkleTree::new();
+        let root = batched.update_batch(&entries);
+
+        assert_eq!(root, sequential.root());
+        assert_eq!(batched.store.len(), sequential.store.len());
+    }
+
+    #[test]
+    fn test_remove_restores_empty_root() {
+        let mut smt = SparseMerkleTree::new();
+        let key = Hasher::hash(b"key");
+        smt.update(key, Hasher::hash(b"value"));
+        assert!(!smt.is_empty());
+
+        smt.remove(&key);
+        assert!(smt.is_empty());
+        assert!(smt.store.is_empty());
+        assert_eq!(smt.root(), default_hashes()[0]);
+    }
+
+    #[test]
+    fn test_reopen_from_store() {
+        let mut smt = SparseMerkleTree::new();
+        let key = Hasher::hash(b"key");
+        let value = Hasher::hash(b"value");
+        let root = smt.update(key, value);
+
+        let reopened = SparseMerkleTree::with_store(smt.into_store());
+        assert_eq!(reopened.root(), root);
+        assert_eq!(reopened.get(&key), Some(value));
+    }
+}
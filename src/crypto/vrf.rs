@@ -0,0 +1,183 @@
+// ECVRF-EDWARDS25519-SHA512-TAI (RFC 9381) over ed25519 keys
+
+use crate::types::{PrivateKey, PublicKey, VrfProof};
+use anyhow::{Result, anyhow};
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::{clamp_integer, Scalar};
+use sha2::{Digest, Sha512};
+
+/// Suite string for ECVRF-EDWARDS25519-SHA512-TAI
+const SUITE: u8 = 0x03;
+
+/// VRF output (proof-to-hash) length in bytes
+pub const VRF_OUTPUT_LEN: usize = 64;
+
+pub type VrfOutput = [u8; VRF_OUTPUT_LEN];
+
+pub struct Vrf;
+
+impl Vrf {
+    /// Evaluate the VRF on `alpha`, returning the proof and its output
+    pub fn prove(private_key: &PrivateKey, alpha: &[u8]) -> Result<(VrfProof, VrfOutput)> {
+        let expanded: [u8; 64] = Sha512::digest(private_key).into();
+        let mut scalar_bytes = [0u8; 32];
+        scalar_bytes.copy_from_slice(&expanded[..32]);
+        let x = Scalar::from_bytes_mod_order(clamp_integer(scalar_bytes));
+        let public_key = EdwardsPoint::mul_base(&x).compress();
+
+        let h = Self::encode_to_curve(public_key.as_bytes(), alpha)?;
+        let h_bytes = h.compress();
+        let gamma = x * h;
+
+        let mut nonce_hasher = Sha512::new();
+        nonce_hasher.update(&expanded[32..]);
+        nonce_hasher.update(h_bytes.as_bytes());
+        let k = Scalar::from_bytes_mod_order_wide(&nonce_hasher.finalize().into());
+
+        let c = Self::challenge(
+            public_key.as_bytes(),
+            &h,
+            &gamma,
+            &EdwardsPoint::mul_base(&k),
+            &(k * h),
+        );
+        let s = k + c * x;
+
+        let mut proof = [0u8; 80];
+        proof[..32].copy_from_slice(gamma.compress().as_bytes());
+        proof[32..48].copy_from_slice(&c.as_bytes()[..16]);
+        proof[48..].copy_from_slice(s.as_bytes());
+
+        Ok((VrfProof(proof), Self::gamma_to_hash(&gamma)))
+    }
+
+    /// Verify `proof` for `alpha` under `public_key`, returning the output
+    pub fn verify(public_key: &PublicKey, alpha: &[u8], proof: &VrfProof) -> Result<VrfOutput> {
+        let y = CompressedEdwardsY(*public_key)
+            .decompress()
+            .ok_or_else(|| anyhow!("Invalid VRF public key"))?;
+        if y.is_small_order() {
+            return Err(anyhow!("Invalid VRF public key"));
+        }
+
+        let (gamma, c, s) = Self::decode_proof(proof)?;
+        let h = Self::encode_to_curve(public_key, alpha)?;
+
+        // U = s*B - c*Y, V = s*H - c*Gamma
+        let u = EdwardsPoint::vartime_double_scalar_mul_basepoint(&(-c), &y, &s);
+        let v = s * h - c * gamma;
+
+        if Self::challenge(public_key, &h, &gamma, &u, &v) != c {
+            return Err(anyhow!("VRF proof verification failed"));
+        }
+
+        Ok(Self::gamma_to_hash(&gamma))
+    }
+
+    /// Output of a proof, without verifying it
+    pub fn proof_to_hash(proof: &VrfProof) -> Result<VrfOutput> {
+        let (gamma, _, _) = Self::decode_proof(proof)?;
+        Ok(Self::gamma_to_hash(&gamma))
+    }
+
+    fn encode_to_curve(public_key: &[u8; 32], alpha: &[u8]) -> Result<EdwardsPoint> {
+        for ctr in 0..=u8::MAX {
+            let mut hasher = Sha512::new();
+            hasher.update([SUITE, 0x01]);
+            hasher.update(public_key);
+            hasher.update(alpha);
+            hasher.update([ctr, 0x00]);
+            let hash = hasher.finalize();
+
+            let mut candidate = [0u8; 32];
+            candidate.copy_from_slice(&hash[..32]);
+            if let Some(point) = CompressedEdwardsY(candidate).decompress() {
+                return Ok(point.mul_by_cofactor());
+            }
+        }
+
+        Err(anyhow!("Failed to encode VRF input to curve"))
+    }
+
+    fn challenge(
+        public_key: &[u8; 32],
+        h: &EdwardsPoint,
+        gamma: &EdwardsPoint,
+        u: &EdwardsPoint,
+        v: &EdwardsPoint,
+    ) -> Scalar {
+        let mut hasher = Sha512::new();
+        hasher.update([SUITE, 0x02]);
+        hasher.update(public_key);
+        for point in [h, gamma, u, v] {
+            hasher.update(point.compress().as_bytes());
+        }
+        hasher.update([0x00]);
+        let hash = hasher.finalize();
+
+        let mut c = [0u8; 32];
+        c[..16].copy_from_slice(&hash[..16]);
+        Scalar::from_bytes_mod_order(c)
+    }
+
+    fn decode_proof(proof: &VrfProof) -> Result<(EdwardsPoint, Scalar, Scalar)> {
+        let mut gamma_bytes = [0u8; 32];
+        gamma_bytes.copy_from_slice(&proof.0[..32]);
+        let gamma = CompressedEdwardsY(gamma_bytes)
+            .decompress()
+            .ok_or_else(|| anyhow!("Invalid VRF proof point"))?;
+
+        let mut c_bytes = [0u8; 32];
+        c_bytes[..16].copy_from_slice(&proof.0[32..48]);
+        let c = Scalar::from_bytes_mod_order(c_bytes);
+
+        let mut s_bytes = [0u8; 32];
+        s_bytes.copy_from_slice(&proof.0[48..]);
+        let s = Option::<Scalar>::from(Scalar::from_canonical_bytes(s_bytes))
+            .ok_or_else(|| anyhow!("Invalid VRF proof scalar"))?;
+
+        Ok((gamma, c, s))
+    }
+
+    fn gamma_to_hash(gamma: &EdwardsPoint) -> VrfOutput {
+        let mut hasher = Sha512::new();
+        hasher.update([SUITE, 0x03]);
+        hasher.update(gamma.mul_by_cofactor().compress().as_bytes());
+        hasher.update([0x00]);
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_rfc9381_vector() {
+        // RFC 9381 Appendix B.3, Example 16
+        let keypair = KeyPair::from_hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        let (proof, output) = Vrf::prove(&keypair.private_key, b"").unwrap();
+
+        assert_eq!(
+            hex::encode(proof.0),
+            "8657106690b5526245a92b003bb079ccd1a92130477671f6fc01ad16f26f723f26f8a57ccaed74ee1b190bed1f479d9727d2d0f9b005a6e456a35d4fb0daab1268a1b0db10836d9826a528ca76567805"
+        );
+        assert_eq!(
+            hex::encode(output),
+            "90cf1df3b703cce59e2a35b925d411164068269d7b2d29f3301c03dd757876ff66b71dda49d2de59d03450451af026798e8f81cd2e333de5cdf4f3e140fdd8ae"
+        );
+        assert_eq!(Vrf::verify(&keypair.public_key, b"", &proof).unwrap(), output);
+    }
+
+    #[test]
+    fn test_vrf_rejects_wrong_input_or_key() {
+        let keypair = KeyPair::generate();
+        let other = KeyPair::generate();
+        let (proof, _) = Vrf::prove(&keypair.private_key, b"slot-1").unwrap();
+
+        assert!(Vrf::verify(&keypair.public_key, b"slot-1", &proof).is_ok());
+        assert!(Vrf::verify(&keypair.public_key, b"slot-2", &proof).is_err());
+        assert!(Vrf::verify(&other.public_key, b"slot-1", &proof).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+// Genesis module - the genesis file format and the builder that turns it
+// into the first block, account state and validator set
+
+use crate::crypto::Hasher;
+use crate::types::*;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Maximum number of validators in the genesis set
+pub const MAX_GENESIS_VALIDATORS: usize = 1000;
+
+/// Contents of a genesis file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenesisSpec {
+    pub network_id: NetworkId,
+    /// Unix seconds at which slot 0 starts
+    pub genesis_time: u64,
+    /// `ConsensusConfig` fields that differ from the defaults
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub consensus: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    pub accounts: Vec<GenesisAccount>,
+    pub validators: Vec<GenesisValidator>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    /// Hex encoded address
+    pub address: String,
+    pub balance: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    /// Hex encoded ed25519 public key; the address is derived from it
+    pub public_key: String,
+    pub stake: Amount,
+    #[serde(default)]
+    pub commission_rate: u16,
+    #[serde(default)]
+    pub name: String,
+}
+
+/// The chain state at genesis, identical on every node built from the same
+/// spec
+#[derive(Debug, Clone)]
+pub struct Genesis {
+    pub network_id: NetworkId,
+    pub genesis_time: u64,
+    pub config: ConsensusConfig,
+    pub block: Block,
+    pub accounts: AccountState,
+    pub validator_set: ValidatorSet,
+    pub genesis_validators_root: Hash,
+}
+
+impl GenesisSpec {
+    pub fn new(network_id: NetworkId, genesis_time: u64) -> Self {
+        GenesisSpec {
+            network_id,
+            genesis_time,
+            consensus: serde_json::Map::new(),
+            accounts: Vec::new(),
+            validators: Vec::new(),
+        }
+    }
+
+    pub fn add_account(&mut self, address: Address, balance: Amount) {
+        self.accounts.push(GenesisAccount {
+            address: address.to_string(),
+            balance,
+        });
+    }
+
+    pub fn add_validator(&mut self, public_key: PublicKey, stake: Amount, name: &str) {
+        self.validators.push(GenesisValidator {
+            public_key: hex::encode(public_key),
+            stake,
+            commission_rate: 0,
+            name: name.to_string(),
+        });
+    }
+
+    /// Override a `ConsensusConfig` field, e.g. `slots_per_epoch`
+    pub fn set_consensus(&mut self, field: &str, value: serde_json::Value) {
+        self.consensus.insert(field.to_string(), value);
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read genesis file {}: {}", path.display(), e))?;
+        serde_json::from_str(&content).map_err(|e| anyhow!("Invalid genesis file {}: {}", path.display(), e))
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Default consensus parameters with the spec's overrides applied
+    pub fn consensus_config(&self) -> Result<ConsensusConfig> {
+        let mut config = serde_json::to_value(ConsensusConfig::default())?;
+        let fields = config.as_object_mut().expect("ConsensusConfig serializes to an object");
+        for (field, value) in &self.consensus {
+            if !fields.contains_key(field) {
+                return Err(anyhow!("Unknown consensus parameter {}", field));
+            }
+            fields.insert(field.clone(), value.clone());
+        }
+        let config: ConsensusConfig =
+            serde_json::from_value(config).map_err(|e| anyhow!("Invalid consensus parameters: {}", e))?;
+        if config.seconds_per_slot == 0 || config.slots_per_epoch == 0 {
+            return Err(anyhow!("seconds_per_slot and slots_per_epoch must be positive"));
+        }
+        Ok(config)
+    }
+
+    /// Build the genesis block and state
+    pub fn build(&self) -> Result<Genesis> {
+        let config = self.consensus_config()?;
+        if self.validators.is_empty() {
+            return Err(anyhow!("Genesis needs at least one validator"));
+        }
+
+        let mut accounts = AccountState::new();
+        for account in &self.accounts {
+            let address = Address(parse_hex(&account.address, "account address")?);
+            if accounts.get_account(&address).is_some() {
+                return Err(anyhow!("Duplicate genesis account {}", address));
+            }
+            accounts.create_account(address, account.balance);
+        }
+
+        let mut validator_set = ValidatorSet::new(config.min_deposit_amount, MAX_GENESIS_VALIDATORS, 0);
+        for validator in &self.validators {
+            let public_key = parse_hex(&validator.public_key, "validator public key")?;
+            ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+                .map_err(|_| anyhow!("Invalid validator public key {}", validator.public_key))?;
+            let address = Address::from(public_key);
+            if validator_set.validators.contains_key(&address) {
+                return Err(anyhow!("Duplicate genesis validator {}", validator.public_key));
+            }
+            let metadata = ValidatorMetadata {
+                name: validator.name.clone(),
+                website: None,
+                description: None,
+                contact: None,
+            };
+            validator_set
+                .add_validator(Validator::new(address, public_key, validator.stake, validator.commission_rate, 0, metadata))
+                .map_err(|e| anyhow!("Genesis validator {}: {}", address, e))?;
+        }
+        // Index validators in address order, like the validators root, so
+        // listing them in another order gives the same genesis
+        validator_set.registry.sort_by_key(|address| address.0);
+
+        let genesis_validators_root = compute_genesis_validators_root(&validator_set);
+        let timestamp = DateTime::from_timestamp(self.genesis_time as i64, 0)
+            .ok_or_else(|| anyhow!("Genesis time {} is out of range", self.genesis_time))?;
+
+        // The state root commits to the accounts, the validators and the
+        // consensus parameters, so the block hash identifies the whole
+        // genesis
+        let config_hash = Hasher::hash_serializable(&config)?;
+        let state_root = Hasher::hash_multiple(&[&accounts.state_root()[..], &genesis_validators_root, &config_hash]);
+
+        let mut block = Block::default();
+        block.header.state_root = state_root;
+        block.header.timestamp = timestamp;
+        block.header.gas_limit = crate::BLOCK_GAS_LIMIT;
+
+        Ok(Genesis {
+            network_id: self.network_id,
+            genesis_time: self.genesis_time,
+            config,
+            block,
+            accounts,
+            validator_set,
+            genesis_validators_root,
+        })
+    }
+}
+
+impl Genesis {
+    /// Hash of the genesis block
+    pub fn hash(&self) -> Hash {
+        self.block.hash()
+    }
+
+    /// Check the genesis against a hex encoded hash published for the network
+    pub fn verify_hash(&self, expected: &str) -> Result<()> {
+        let expected = parse_hex(expected, "genesis hash")?;
+        if self.hash() != expected {
+            return Err(anyhow!(
+                "Genesis hash {} does not match expected {}",
+                hex::encode(self.hash()),
+                hex::encode(expected)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Root of the genesis validators, in address order: their addresses,
+/// public keys and stakes
+pub fn compute_genesis_validators_root(validator_set: &ValidatorSet) -> Hash {
+    let mut validators: Vec<&Validator> = validator_set.validators.values().collect();
+    validators.sort_by_key(|validator| validator.address.0);
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"GENESIS_VALIDATORS");
+    for validator in validators {
+        hasher.update(validator.address.0);
+        hasher.update(validator.public_key);
+        hasher.update(validator.total_stake().to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+fn parse_hex(value: &str, what: &str) -> Result<Hash> {
+    let bytes = hex::decode(value.trim_start_matches("0x")).map_err(|e| anyhow!("Invalid {} {}: {}", what, value, e))?;
+    bytes.try_into().map_err(|_| anyhow!("Invalid {} {}: expected 32 bytes", what, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    fn test_spec() -> GenesisSpec {
+        let mut spec = GenesisSpec::new(NetworkId::Devnet, 1_700_000_000);
+        for i in 0..3u8 {
+            let keypair = KeyPair::from_private_key([i + 1; 32]).unwrap();
+            spec.add_validator(keypair.public_key, 32_000_000_000, &format!("validator-{}", i));
+            spec.add_account(keypair.address, 1_000_000);
+        }
+        spec.set_consensus("slots_per_epoch", serde_json::json!(8));
+        spec
+    }
+
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let spec = test_spec();
+        let json = serde_json::to_string(&spec).unwrap();
+        let genesis = spec.build().unwrap();
+        let reloaded = serde_json::from_str::<GenesisSpec>(&json).unwrap().build().unwrap();
+
+        assert_eq!(genesis.hash(), reloaded.hash());
+        assert_eq!(genesis.genesis_validators_root, reloaded.genesis_validators_root);
+        assert_eq!(genesis.config.slots_per_epoch, 8);
+        assert_eq!(genesis.validator_set.validators.len(), 3);
+        assert_eq!(genesis.accounts.total_supply, 3_000_000);
+        assert_eq!(genesis.block.header.height, 0);
+        assert_eq!(genesis.block.header.timestamp.timestamp(), 1_700_000_000);
+        assert!(genesis.verify_hash(&hex::encode(genesis.hash())).is_ok());
+        assert!(genesis.verify_hash(&hex::encode([0u8; 32])).is_err());
+
+        // Any change to the state changes the genesis hash
+        let mut changed = test_spec();
+        changed.accounts[0].balance += 1;
+        assert_ne!(changed.build().unwrap().hash(), genesis.hash());
+        let mut changed = test_spec();
+        changed.set_consensus("seconds_per_slot", serde_json::json!(6));
+        assert_ne!(changed.build().unwrap().hash(), genesis.hash());
+    }
+
+    #[test]
+    fn test_invalid_genesis_is_rejected() {
+        let mut spec = test_spec();
+        spec.set_consensus("slots_per_era", serde_json::json!(8));
+        assert!(spec.build().is_err());
+
+        let mut spec = test_spec();
+        spec.validators[0].stake = 1;
+        assert!(spec.build().is_err());
+
+        let mut spec = test_spec();
+        spec.validators.push(spec.validators[0].clone());
+        assert!(spec.build().is_err());
+
+        let mut spec = test_spec();
+        spec.accounts[0].address = "abcd".to_string();
+        assert!(spec.build().is_err());
+
+        let mut spec = test_spec();
+        spec.validators.clear();
+        assert!(spec.build().is_err());
+    }
+}
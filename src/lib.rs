@@ -1,48 +1,276 @@
 pub mod types;
+pub mod api;
 pub mod crypto;
 pub mod consensus;
 pub mod network;
 pub mod storage;
 pub mod validator;
 pub mod config;
+pub mod sync;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
+pub mod metrics;
+pub mod genesis;
+pub mod testnet;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
 
 pub use types::*;
 pub use crypto::*;
 pub use consensus::*;
 
 use anyhow::Result;
+use network::{NetworkEvent, NetworkHandle, NetworkService};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Gas limit of blocks proposed by this node
+pub const BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+/// Upper bound on transactions held while waiting for inclusion
+pub const MAX_PENDING_TRANSACTIONS: usize = 10_000;
+
+/// How long a transaction stays pending before it is dropped
+pub const PENDING_TRANSACTION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Synced blocks waiting to be imported by the node before a background
+/// sync stops downloading more
+pub const SYNC_IMPORT_BUFFER: usize = 64;
+
+/// How often storage is flushed to disk while running
+pub const STORAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
 
-#[derive(Debug, Clone)]
 pub struct Node {
     pub config: config::NodeConfig,
     pub consensus: consensus::ConsensusEngine,
-    // Network and storage components would be added here
+    pub storage: storage::StorageService,
+    /// Transactions received from the network and not yet in a block
+    pub pending_transactions: HashMap<Hash, PendingTransaction>,
+    /// Drives slot and epoch processing and validator duties
+    pub slot_clock: SlotClock,
+    /// Validator keys run by this node and their duties
+    pub validators: validator::ValidatorClient,
+    /// Chain and transaction events for API subscribers
+    pub events: api::EventPublisher,
+    pub metrics: metrics::Metrics,
+    /// Genesis loaded from `network.genesis_file`
+    pub genesis: Option<genesis::Genesis>,
+    admin: validator::AdminHandle,
+    admin_commands: tokio::sync::mpsc::UnboundedReceiver<validator::AdminCommand>,
+    /// Active validator keys last passed to the network for gossip
+    /// validation
+    gossip_keys: Vec<(u64, Address, PublicKey)>,
+    /// Range sync running in the background, if any
+    sync_task: Option<tokio::task::JoinHandle<()>>,
+    synced_sender: tokio::sync::mpsc::Sender<Block>,
+    synced_blocks: tokio::sync::mpsc::Receiver<Block>,
+}
+
+/// A transaction waiting for inclusion
+#[derive(Debug, Clone)]
+pub struct PendingTransaction {
+    pub transaction: Transaction,
+    pub received: std::time::Instant,
 }
 
 impl Node {
     pub async fn new(config: config::NodeConfig) -> Result<Self> {
-        let consensus_config = ConsensusConfig::default();
-        let genesis_validators = Vec::new(); // Would load from genesis
-
-        let consensus = ConsensusEngine::new(consensus_config, genesis_validators)?;
+        let genesis = match &config.network.genesis_file {
+            Some(path) => Some(load_genesis(path, &config.network)?),
+            None => None,
+        };
+        let consensus = match &genesis {
+            Some(genesis) => ConsensusEngine::from_genesis(genesis),
+            None => ConsensusEngine::new(ConsensusConfig::default(), Vec::new())?,
+        };
+        let genesis_time = genesis.as_ref().map_or(0, |genesis| genesis.genesis_time);
+        let slot_clock = SlotClock::new(genesis_time, consensus.config.seconds_per_slot, consensus.config.slots_per_epoch);
+        let (admin, admin_commands) = validator::AdminHandle::channel();
+        let metrics = metrics::Metrics::new(&config.metrics.namespace);
+        let (synced_sender, synced_blocks) = tokio::sync::mpsc::channel(SYNC_IMPORT_BUFFER);
 
         Ok(Node {
             config,
             consensus,
+            storage: storage::StorageService::new(),
+            pending_transactions: HashMap::new(),
+            slot_clock,
+            validators: validator::ValidatorClient::default(),
+            events: api::EventPublisher::default(),
+            metrics,
+            genesis,
+            admin,
+            admin_commands,
+            gossip_keys: Vec::new(),
+            sync_task: None,
+            synced_sender,
+            synced_blocks,
         })
     }
 
+    /// Handle for adding and removing validator keys while the node runs
+    pub fn admin_handle(&self) -> validator::AdminHandle {
+        self.admin.clone()
+    }
+
+    /// Run the node until ctrl-c
     pub async fn start(&mut self) -> Result<()> {
+        self.run_until(async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                warn!("Failed to listen for ctrl-c: {}", e);
+                std::future::pending::<()>().await;
+            }
+            info!("Received shutdown signal");
+        })
+        .await
+    }
+
+    /// Open storage, restore the chain, start networking and process events
+    /// and validator duties until `shutdown` completes. Storage is flushed
+    /// before returning.
+    pub async fn run_until(&mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
         tracing::info!("Starting node with config: {:?}", self.config);
+        self.config.validate().map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let data_dir = self.config.storage.data_dir.clone();
+        self.storage = storage::StorageService::open(&data_dir).map_err(storage_error)?;
+        self.store_genesis().await?;
+        self.restore_head().await?;
+        self.events.reset(&self.consensus);
 
-        // Initialize network
-        // Initialize storage
-        // Start consensus engine
-        // Start API server
+        let mut p2p_config = self.config.network.clone();
+        p2p_config.data_dir = Some(data_dir.join("network"));
+        p2p_config.genesis_time = self.slot_clock.genesis_time;
+        if let Some(genesis) = &self.genesis {
+            p2p_config.genesis_root = genesis.genesis_validators_root;
+        }
+        p2p_config.seconds_per_slot = self.slot_clock.seconds_per_slot;
 
+        self.load_validators()?;
+
+        let (service, mut network) = NetworkService::new(p2p_config.clone()).map_err(network_error)?;
+        let network_task = tokio::spawn(service.with_metrics(self.metrics.clone()).run());
+
+        self.refresh_gossip_keys(&network).await;
+        self.announce_head(&network).await;
+
+        let (api_sender, mut api_queries) = tokio::sync::mpsc::channel(api::API_QUERY_BUFFER);
+        let api_server = if self.config.api.enabled {
+            Some(api::ApiServer::start(&self.config.api, api_sender, self.events.sender()).map_err(|e| anyhow::anyhow!("Failed to start API: {}", e))?)
+        } else {
+            None
+        };
+
+        let metrics_server = if self.config.metrics.enabled {
+            Some(
+                metrics::MetricsServer::start(&self.config.metrics, self.metrics.clone())
+                    .map_err(|e| anyhow::anyhow!("Failed to start metrics: {}", e))?,
+            )
+        } else {
+            None
+        };
+
+        let mut core = ConsensusEngineCore::new(self.slot_clock.clone());
+        core.initialize(&mut self.consensus).await?;
+        let (slot_sender, mut slot_events) = tokio::sync::mpsc::channel(16);
+        let ticker_task = SlotTicker::new(self.slot_clock.clone()).spawn(slot_sender);
+
+        let mut flush_timer = tokio::time::interval(STORAGE_FLUSH_INTERVAL);
+        tokio::pin!(shutdown);
+        self.update_metrics();
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                event = network.next_event() => match event {
+                    Some(event) => self.handle_network_event(&network, &p2p_config, event).await,
+                    None => {
+                        warn!("Network service stopped");
+                        break;
+                    }
+                },
+                Some(event) = slot_events.recv() => {
+                    self.expire_pending_transactions();
+                    if let Err(e) = core.on_event(&mut self.consensus, event).await {
+                        warn!("Failed to process {:?}: {}", event, e);
+                    }
+                    // Epoch processing activates and ejects validators
+                    self.refresh_gossip_keys(&network).await;
+                    if !self.validators.is_empty() {
+                        self.perform_duties(&network, &p2p_config, event).await;
+                    }
+                }
+                Some(block) = self.synced_blocks.recv() => {
+                    if let Err(e) = self.import_block(&network, &block).await {
+                        debug!("Failed to import synced block {}: {}", hex::encode(block.hash()), e);
+                    }
+                }
+                Some(command) = self.admin_commands.recv() => {
+                    self.validators.handle_admin_command(command, &self.consensus);
+                }
+                Some(query) = api_queries.recv() => {
+                    let result = self.handle_api_request(&network, query.request).await;
+                    let _ = query.response.send(result);
+                }
+                _ = flush_timer.tick() => {
+                    if let Err(e) = self.flush_storage() {
+                        warn!("Failed to flush storage: {}", e);
+                    }
+                }
+            }
+            self.events.observe(&self.consensus);
+            self.update_metrics();
+        }
+
+        info!("Shutting down node");
+        ticker_task.abort();
+        if let Some(sync_task) = self.sync_task.take() {
+            sync_task.abort();
+        }
+        // Dropping the queue answers waiting API requests with an error
+        drop(api_queries);
+        if let Some(api_server) = api_server {
+            api_server.stop().await;
+        }
+        if let Some(metrics_server) = metrics_server {
+            metrics_server.stop().await;
+        }
+        if let Err(e) = network.shutdown().await {
+            warn!("Network service did not shut down cleanly: {}", e);
+        }
+        match network_task.await {
+            Ok(Err(e)) => warn!("Network service failed: {}", e),
+            Err(e) => warn!("Network task panicked: {}", e),
+            Ok(Ok(())) => {}
+        }
+        self.flush_storage()
+    }
+
+    /// Write storage changes to disk, recording how long it took and the
+    /// size on disk
+    fn flush_storage(&mut self) -> Result<()> {
+        if self.storage.is_dirty() {
+            let started = std::time::Instant::now();
+            self.storage.flush().map_err(storage_error)?;
+            self.metrics.storage_write.observe(started.elapsed().as_secs_f64());
+        }
+        self.metrics.storage_size.set(self.storage.disk_size() as i64);
         Ok(())
     }
 
+    fn update_metrics(&self) {
+        let head_slot = self.get_head()
+            .and_then(|head| self.consensus.fork_choice.get_block(&head))
+            .map(|block| block.header.slot)
+            .unwrap_or(0);
+        self.metrics.head_slot.set(head_slot as i64);
+        self.metrics.finalized_epoch.set(self.consensus.fork_choice.finalized_checkpoint.epoch as i64);
+        self.metrics.pending_transactions.set(self.pending_transactions.len() as i64);
+    }
+
     pub fn process_block(&mut self, block: Block) -> Result<()> {
         self.consensus.process_block(&block)
     }
@@ -50,4 +278,720 @@ impl Node {
     pub fn get_head(&self) -> Option<Hash> {
         self.consensus.get_head()
     }
-}
\ No newline at end of file
+
+    /// Store the genesis block and state in a new data directory, or check
+    /// that an existing one was built from the same genesis
+    async fn store_genesis(&mut self) -> Result<()> {
+        let Some(genesis) = &self.genesis else {
+            return Ok(());
+        };
+        let hash = genesis.hash();
+        match self.storage.get_canonical_hash(0).await.map_err(storage_error)? {
+            Some(stored) if stored == hash => return Ok(()),
+            Some(stored) => {
+                return Err(anyhow::anyhow!(
+                    "Data directory holds genesis {}, but the genesis file produces {}",
+                    hex::encode(stored),
+                    hex::encode(hash)
+                ));
+            }
+            None if self.storage.get_latest_height().await.map_err(storage_error)? > 0 => {
+                return Err(anyhow::anyhow!("Data directory holds a chain without a genesis block"));
+            }
+            None => {}
+        }
+
+        self.storage.store_block(genesis.block.clone()).await.map_err(storage_error)?;
+        let accounts = genesis.accounts.accounts.values().cloned().collect();
+        self.storage.store_accounts(accounts).await.map_err(storage_error)?;
+        for validator in genesis.validator_set.validators.values() {
+            self.storage.store_validator(validator.clone()).await.map_err(storage_error)?;
+        }
+        info!("Initialized chain from genesis {}", hex::encode(hash));
+        Ok(())
+    }
+
+    /// Replay the stored canonical chain into consensus
+    async fn restore_head(&mut self) -> Result<()> {
+        let latest_height = self.storage.get_latest_height().await.map_err(storage_error)?;
+        for height in 1..=latest_height {
+            let Some(block) = self.storage.get_block_by_height(height).await.map_err(storage_error)? else {
+                warn!("Stored chain is missing block {}", height);
+                break;
+            };
+            if let Err(e) = self.consensus.process_block(&block) {
+                warn!("Stopped restoring chain at height {}: {}", height, e);
+                break;
+            }
+        }
+
+        if let Some(head) = self.get_head() {
+            info!("Restored head {} at height {}", hex::encode(head), self.head_height());
+        }
+        Ok(())
+    }
+
+    fn head_height(&self) -> u64 {
+        self.get_head()
+            .and_then(|head| self.consensus.fork_choice.get_block(&head))
+            .map(|block| block.header.height)
+            .unwrap_or(0)
+    }
+
+    /// Load the validator keys from `validator.keystore_dir` and
+    /// `validator.keystore_path`
+    fn load_validators(&mut self) -> Result<()> {
+        let config = &self.config.validator;
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let mut validators = match &config.keystore_dir {
+            Some(dir) => validator::ValidatorClient::load(dir, config.keystore_password.clone())
+                .map_err(|e| anyhow::anyhow!("Failed to load keystores from {}: {}", dir.display(), e))?,
+            None => validator::ValidatorClient::new(None, config.keystore_password.clone()),
+        };
+        if let Some(path) = &config.keystore_path {
+            let keypair = validator::keystore::read_keystore(path, config.keystore_password.as_deref())
+                .map_err(|e| anyhow::anyhow!("Failed to load {}: {}", path.display(), e))?;
+            validators.insert(keypair, Some(path.clone())).map_err(|e| anyhow::anyhow!("{}", e))?;
+        }
+
+        validators.update_statuses(&self.consensus.validator_set);
+        for key in validators.keys(&self.consensus.validator_set) {
+            info!("Validating as {} ({:?})", key.address, key.status);
+        }
+        self.validators = validators;
+        Ok(())
+    }
+
+    async fn handle_network_event(&mut self, network: &NetworkHandle, p2p_config: &network::NetworkConfig, event: NetworkEvent) {
+        match event {
+            NetworkEvent::BlockReceived { block, from } => {
+                if let Err(e) = self.import_block(network, &block).await {
+                    debug!("Rejected block {} from {}: {}", hex::encode(block.hash()), from, e);
+                }
+            }
+            NetworkEvent::TransactionReceived { transaction, .. } => {
+                let hash = transaction.hash();
+                if let Err(e) = self.add_pending_transaction(transaction) {
+                    debug!("Dropping transaction {}: {}", hex::encode(hash), e);
+                }
+            }
+            NetworkEvent::AttestationReceived { attestation, .. } => {
+                match self.consensus.process_attestation(&attestation) {
+                    Ok(()) => self.validators.observe_attestation(&attestation),
+                    Err(e) => debug!("Rejected attestation: {}", e),
+                }
+            }
+            NetworkEvent::PeerStatusReceived { peer_id, status } => {
+                if status.head_height > self.head_height() {
+                    self.start_sync(network, p2p_config, peer_id, status);
+                }
+            }
+            NetworkEvent::SyncRequestReceived { request_id, request, .. } => {
+                let response = sync::handle_sync_request(request, &self.storage, &self.consensus, p2p_config).await;
+                if let Err(e) = network.send_sync_response(request_id, response).await {
+                    debug!("Failed to answer sync request: {}", e);
+                }
+            }
+            event => debug!("{}", event.description()),
+        }
+    }
+
+    /// Sync from `peer_id` in a background task so the node keeps handling
+    /// slots, gossip and queries meanwhile. The task checks blocks against
+    /// a copy of consensus and sends them back to be imported. Only one
+    /// sync runs at a time.
+    fn start_sync(
+        &mut self,
+        network: &NetworkHandle,
+        p2p_config: &network::NetworkConfig,
+        peer_id: libp2p::PeerId,
+        status: network::StatusMessage,
+    ) {
+        if self.sync_task.as_ref().is_some_and(|task| !task.is_finished()) {
+            return;
+        }
+        let mut manager = sync::SyncManager::new(p2p_config.network_id, network::MAX_BLOCKS_PER_REQUEST);
+        if !manager.add_peer(peer_id, status, self.slot_clock.current_slot().unwrap_or(0)) {
+            return;
+        }
+        manager.start(self.head_height(), self.get_head().unwrap_or([0u8; 32]));
+
+        let client = network.sync_client();
+        let mut consensus = self.consensus.clone();
+        let synced_sender = self.synced_sender.clone();
+        self.sync_task = Some(tokio::spawn(async move {
+            match manager.sync(&client, &mut consensus, &synced_sender).await {
+                Ok(imported) => info!("Synced {} blocks from {}", imported, peer_id),
+                Err(e) => warn!("Sync with {} failed: {}", peer_id, e),
+            }
+        }));
+    }
+
+    /// Add a transaction to the pending set and publish it to API
+    /// subscribers. Returns false if it was already pending.
+    pub(crate) fn add_pending_transaction(&mut self, transaction: Transaction) -> Result<bool, String> {
+        if transaction.gas_limit > BLOCK_GAS_LIMIT {
+            return Err(format!("Gas limit {} exceeds the block gas limit {}", transaction.gas_limit, BLOCK_GAS_LIMIT));
+        }
+        let hash = transaction.hash();
+        if self.pending_transactions.contains_key(&hash) {
+            return Ok(false);
+        }
+        if self.pending_transactions.len() >= MAX_PENDING_TRANSACTIONS {
+            return Err("Pending transaction set is full".to_string());
+        }
+
+        self.pending_transactions.insert(
+            hash,
+            PendingTransaction {
+                transaction: transaction.clone(),
+                received: std::time::Instant::now(),
+            },
+        );
+        self.events.publish(api::ApiEvent::PendingTransaction(Box::new(transaction)));
+        Ok(true)
+    }
+
+    /// Drop transactions pending for longer than `PENDING_TRANSACTION_TTL`
+    /// or that have become too old to be valid
+    fn expire_pending_transactions(&mut self) {
+        self.pending_transactions.retain(|_, pending| {
+            pending.received.elapsed() < PENDING_TRANSACTION_TTL && pending.transaction.is_valid()
+        });
+    }
+
+    /// Import a block through consensus, drop its transactions from the
+    /// pending set and persist the new canonical chain. Blocks whose
+    /// transactions do not apply to their parent's accounts, or that commit
+    /// to a different state root, are refused.
+    async fn import_block(&mut self, network: &NetworkHandle, block: &Block) -> Result<()> {
+        let (changes, state_root) = self.execute_block(block).await?;
+        if state_root != block.header.state_root {
+            return Err(anyhow::anyhow!(
+                "Block {} commits to state root {}, but its transactions produce {}",
+                hex::encode(block.hash()),
+                hex::encode(block.header.state_root),
+                hex::encode(state_root)
+            ));
+        }
+
+        let started = std::time::Instant::now();
+        let result = self.consensus.process_block(block);
+        self.metrics.observe_block_processing(result.is_ok(), started.elapsed());
+        result?;
+        self.storage.store_block_changes(block.hash(), changes).await.map_err(storage_error)?;
+        for transaction in &block.transactions {
+            self.pending_transactions.remove(&transaction.hash());
+        }
+        self.persist_head(network).await?;
+        self.refresh_gossip_keys(network).await;
+        Ok(())
+    }
+
+    /// Pass the active validator keys to gossip validation when they differ
+    /// from the ones it has
+    async fn refresh_gossip_keys(&mut self, network: &NetworkHandle) {
+        let keys = self.consensus.validator_set.indexed_keys();
+        if keys == self.gossip_keys {
+            return;
+        }
+        match network.update_validator_keys(keys.clone()).await {
+            Ok(()) => {
+                debug!("Updated gossip validation with {} validator keys", keys.len());
+                self.gossip_keys = keys;
+            }
+            Err(e) => warn!("Failed to update validator keys: {}", e),
+        }
+    }
+
+    /// Apply a block's transactions to its parent's accounts, returning the
+    /// accounts it changes and the account state root after it
+    async fn execute_block(&self, block: &Block) -> Result<(Vec<storage::AccountChange>, Hash)> {
+        let mut overlay = self.account_overlay_at(block.header.previous_hash).await?;
+        let mut state = self.account_state_for(&overlay, &block.transactions).await?;
+        let before = state.accounts.clone();
+        state.apply_block(block).map_err(|e| anyhow::anyhow!("{}", e))?;
+        let changes: Vec<storage::AccountChange> = state.accounts
+            .into_values()
+            .map(|after| storage::AccountChange {
+                address: after.address,
+                before: before.get(&after.address).cloned(),
+                after,
+            })
+            .collect();
+
+        for change in &changes {
+            overlay.insert(change.address, Some(change.after.clone()));
+        }
+        let state_root = self.storage.state_root_after(&overlay.into_iter().collect::<Vec<_>>());
+        Ok((changes, state_root))
+    }
+
+    /// Load the accounts that `transactions` send from or to, as stored
+    /// for the canonical head with `overlay` applied on top
+    async fn account_state_for<'a>(
+        &self,
+        overlay: &HashMap<Address, Option<Account>>,
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Result<AccountState> {
+        let mut state = AccountState::new();
+        for transaction in transactions {
+            for address in [transaction.from, transaction.to] {
+                if state.accounts.contains_key(&address) {
+                    continue;
+                }
+                let account = match overlay.get(&address) {
+                    Some(account) => account.clone(),
+                    None => self.storage.get_account(&address).await.map_err(storage_error)?,
+                };
+                if let Some(account) = account {
+                    state.accounts.insert(address, account);
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// Accounts that differ between the stored canonical head and the state
+    /// after block `hash`: canonical blocks above the fork point are rolled
+    /// back and the fork's blocks applied. Empty when `hash` is the head.
+    async fn account_overlay_at(&self, hash: Hash) -> Result<HashMap<Address, Option<Account>>> {
+        let mut fork_blocks = Vec::new();
+        let mut current = hash;
+        let fork_height = loop {
+            let block = match self.consensus.fork_choice.get_block(&current) {
+                Some(block) => block.clone(),
+                None => self.storage
+                    .get_block(&current)
+                    .await
+                    .map_err(storage_error)?
+                    .ok_or_else(|| anyhow::anyhow!("Unknown parent block {}", hex::encode(current)))?,
+            };
+            let height = block.header.height;
+            if self.storage.get_canonical_hash(height).await.map_err(storage_error)? == Some(current) {
+                break height;
+            }
+            fork_blocks.push(current);
+            current = block.header.previous_hash;
+        };
+
+        let mut overlay = HashMap::new();
+        let latest_height = self.storage.get_latest_height().await.map_err(storage_error)?;
+        for height in (fork_height + 1..=latest_height).rev() {
+            let Some(canonical) = self.storage.get_canonical_hash(height).await.map_err(storage_error)? else {
+                continue;
+            };
+            for change in self.block_changes(&canonical).await? {
+                overlay.insert(change.address, change.before);
+            }
+        }
+        for fork_block in fork_blocks.iter().rev() {
+            for change in self.block_changes(fork_block).await? {
+                overlay.insert(change.address, Some(change.after));
+            }
+        }
+        Ok(overlay)
+    }
+
+    async fn block_changes(&self, hash: &Hash) -> Result<Vec<storage::AccountChange>> {
+        self.storage
+            .get_block_changes(hash)
+            .await
+            .map_err(storage_error)?
+            .ok_or_else(|| anyhow::anyhow!("No account changes recorded for block {}", hex::encode(hash)))
+    }
+
+    /// Store the canonical chain from the head back to where it meets the
+    /// stored chain, publish the head change and announce it to peers
+    async fn persist_head(&mut self, network: &NetworkHandle) -> Result<()> {
+        let Some(head) = self.get_head() else {
+            return Ok(());
+        };
+        let old_height = self.storage.get_latest_height().await.map_err(storage_error)?;
+        let old_head = self.storage.get_canonical_hash(old_height).await.map_err(storage_error)?;
+
+        let mut new_blocks = Vec::new();
+        let mut current = head;
+        while let Some(block) = self.consensus.fork_choice.get_block(&current) {
+            let stored = self.storage.get_canonical_hash(block.header.height).await.map_err(storage_error)?;
+            if stored == Some(current) {
+                break;
+            }
+            new_blocks.push(block.clone());
+            current = block.header.previous_hash;
+        }
+        if new_blocks.is_empty() {
+            return Ok(());
+        }
+
+        let head_height = new_blocks[0].header.height;
+        let fork_height = new_blocks[new_blocks.len() - 1].header.height;
+        let head_block = new_blocks[0].clone();
+
+        // Rebuild accounts from the fork point: undo the blocks leaving the
+        // chain, newest first, then apply the new ones
+        let overlay = self.account_overlay_at(head).await?;
+        self.storage.set_accounts(overlay.into_iter().collect()).await.map_err(storage_error)?;
+
+        for block in new_blocks.into_iter().rev() {
+            for (index, transaction) in block.transactions.iter().enumerate() {
+                let location = storage::TransactionLocation {
+                    block_hash: block.hash(),
+                    block_height: block.header.height,
+                    index,
+                };
+                self.events.publish(api::ApiEvent::TransactionIncluded { hash: transaction.hash(), location });
+            }
+            self.storage.store_block(block).await.map_err(storage_error)?;
+        }
+        self.storage.set_canonical_head(head_height).await.map_err(storage_error)?;
+
+        // Stored blocks at or above the first new height left the chain
+        if let Some(old_head) = old_head.filter(|_| fork_height <= old_height) {
+            self.metrics.reorgs.inc();
+            self.events.publish(api::ApiEvent::Reorg {
+                old_head,
+                new_head: head,
+                depth: old_height - fork_height + 1,
+            });
+        }
+        self.events.publish(api::ApiEvent::NewHead(Box::new(head_block)));
+
+        self.announce_head(network).await;
+        Ok(())
+    }
+
+    async fn announce_head(&self, network: &NetworkHandle) {
+        let head = self.get_head().unwrap_or([0u8; 32]);
+        let finalized = self.consensus.fork_choice.finalized_checkpoint.clone();
+        if let Err(e) = network.update_chain_status(head, self.head_height(), finalized).await {
+            debug!("Failed to update chain status: {}", e);
+        }
+    }
+
+    /// Run the duties of every managed key for a slot event: refresh
+    /// duties at each epoch, propose at slot start when selected, attest at
+    /// a third of the slot and aggregate at two thirds when an aggregator
+    async fn perform_duties(&mut self, network: &NetworkHandle, p2p_config: &network::NetworkConfig, event: SlotEvent) {
+        match event {
+            SlotEvent::SlotStart(slot) => {
+                let epoch = self.consensus.slot_to_epoch(slot);
+                if self.consensus.is_epoch_boundary(slot) || !self.validators.has_duties(epoch) {
+                    self.update_duties(network, epoch).await;
+                }
+
+                // Slot 0 belongs to the genesis block
+                if slot == 0 {
+                    return;
+                }
+                // Only one block per slot, even if several keys are eligible
+                let Some((proposer, duty)) = self.validators.proposer_duties(slot).into_iter().next() else {
+                    return;
+                };
+                let result = self.propose_block(network, proposer, slot, duty.vrf_proof).await;
+                self.metrics.record_duty("proposal", &result);
+                if let Err(e) = result {
+                    warn!("Failed to propose block for slot {}: {}", slot, e);
+                }
+            }
+            SlotEvent::AttestationDeadline(slot) => {
+                for (address, duty) in self.validators.attester_duties(slot) {
+                    let result = self.attest(network, p2p_config, address, duty).await;
+                    self.metrics.record_duty("attestation", &result);
+                    if let Err(e) = result {
+                        debug!("{} failed to attest at slot {}: {}", address, slot, e);
+                    }
+                }
+            }
+            SlotEvent::AggregationDeadline(slot) => {
+                for result in self.validators.aggregate(slot) {
+                    let published = match result {
+                        Ok(aggregate) => network.broadcast_aggregate_and_proof(aggregate).await.map_err(network_error),
+                        Err(e) => Err(anyhow::anyhow!("{}", e)),
+                    };
+                    self.metrics.record_duty("aggregation", &published);
+                    if let Err(e) = published {
+                        debug!("Failed to publish aggregate for slot {}: {}", slot, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compute duties of every key for `epoch` and the next one, and
+    /// subscribe to the attestation subnets of the assigned committees
+    async fn update_duties(&mut self, network: &NetworkHandle, epoch: Epoch) {
+        let assignments = match self.validators.update_duties(epoch, &self.consensus) {
+            Ok(assignments) => assignments,
+            Err(e) => {
+                warn!("Failed to compute duties for epoch {}: {}", epoch, e);
+                return;
+            }
+        };
+        debug!("Computed duties for epoch {}: {} committee assignments", epoch, assignments.len());
+        if let Err(e) = network.update_subnet_subscriptions(assignments).await {
+            debug!("Failed to update subnet subscriptions: {}", e);
+        }
+    }
+
+    /// Sign an attestation to the head for a key's committee and publish it
+    /// on the committee's subnet
+    async fn attest(
+        &mut self,
+        network: &NetworkHandle,
+        p2p_config: &network::NetworkConfig,
+        address: Address,
+        duty: validator::AttesterDuty,
+    ) -> Result<()> {
+        let Some(head) = self.get_head() else {
+            return Ok(());
+        };
+        let validator = self.validators.service(&address).ok_or_else(|| anyhow::anyhow!("Key was removed"))?;
+
+        let slot = duty.assignment.slot;
+        let source = self.consensus.fork_choice.justified_checkpoint.clone();
+        let target_epoch = self.consensus.slot_to_epoch(slot);
+        let attestation = validator
+            .create_attestation(slot, head, duty.validator_index, &source, target_epoch)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        self.consensus.process_attestation(&attestation)?;
+        self.validators.observe_attestation(&attestation);
+
+        let subnet_id = network::compute_subnet_for_attestation(
+            slot,
+            duty.assignment.committee_index,
+            p2p_config.attestation_subnet_count,
+        );
+        network.broadcast_attestation(attestation, subnet_id).await.map_err(network_error)
+    }
+
+    async fn propose_block(
+        &mut self,
+        network: &NetworkHandle,
+        proposer: Address,
+        slot: Slot,
+        vrf_proof: Option<VrfProof>,
+    ) -> Result<()> {
+        let parent = self.get_head().unwrap_or([0u8; 32]);
+
+        // Take each sender's transactions in nonce order, skipping those that
+        // do not fit or do not apply so others can still fill the block
+        let mut candidates: Vec<&Transaction> = self.pending_transactions
+            .values()
+            .map(|pending| &pending.transaction)
+            .collect();
+        candidates.sort_by_key(|transaction| (transaction.from.0, transaction.nonce));
+        let mut state = self.account_state_for(&HashMap::new(), candidates.iter().copied()).await?;
+        let mut gas: u64 = 0;
+        let mut transactions = Vec::new();
+        for transaction in candidates {
+            let Some(total) = gas.checked_add(transaction.gas_limit).filter(|total| *total <= BLOCK_GAS_LIMIT) else {
+                continue;
+            };
+            if state.apply_transaction(transaction).is_ok() {
+                gas = total;
+                transactions.push(transaction.clone());
+            }
+        }
+
+        let mut block = Block::new(
+            self.head_height() + 1,
+            parent,
+            [0u8; 32],
+            slot,
+            self.consensus.slot_to_epoch(slot),
+            proposer,
+            transactions,
+            [0u8; 32],
+            BLOCK_GAS_LIMIT,
+        );
+        block.header.vrf_proof = vrf_proof;
+        block.header.state_root = self.execute_block(&block).await?.1;
+        self.validators
+            .service(&proposer)
+            .ok_or_else(|| anyhow::anyhow!("Key was removed"))?
+            .sign_block(&mut block)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        self.import_block(network, &block).await?;
+        info!("Proposed block {} at slot {}", hex::encode(block.hash()), slot);
+        network.broadcast_block(block).await.map_err(network_error)?;
+        Ok(())
+    }
+}
+
+/// Build the genesis in `path` and check it against the network config
+fn load_genesis(path: &std::path::Path, config: &config::NetworkConfig) -> Result<genesis::Genesis> {
+    let genesis = genesis::GenesisSpec::load_from_file(path)?.build()?;
+    if genesis.network_id != config.network_id {
+        return Err(anyhow::anyhow!(
+            "Genesis file is for {}, but the node is configured for {}",
+            genesis.network_id.name(),
+            config.network_id.name()
+        ));
+    }
+    if let Some(expected) = &config.genesis_hash {
+        genesis.verify_hash(expected)?;
+    }
+    Ok(genesis)
+}
+
+fn storage_error(e: Box<dyn std::error::Error>) -> anyhow::Error {
+    anyhow::anyhow!("Storage error: {}", e)
+}
+
+fn network_error(e: Box<dyn std::error::Error + Send + Sync>) -> anyhow::Error {
+    anyhow::anyhow!("Network error: {}", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    const BALANCE: Amount = 5_000;
+
+    struct Harness {
+        node: Node,
+        keys: Vec<KeyPair>,
+        genesis: Hash,
+        _dir: tempfile::TempDir,
+    }
+
+    /// Node with three funded genesis validators and a running network
+    async fn harness() -> (Harness, NetworkHandle) {
+        let dir = tempfile::tempdir().unwrap();
+        let keys: Vec<KeyPair> = (0..3).map(|_| KeyPair::generate()).collect();
+        let mut spec = genesis::GenesisSpec::new(NetworkId::Devnet, 1_700_000_000);
+        for (i, key) in keys.iter().enumerate() {
+            spec.add_validator(key.public_key, 32_000_000_000, &format!("validator-{}", i));
+            spec.add_account(key.address, BALANCE);
+        }
+        let genesis_file = dir.path().join("genesis.json");
+        spec.save_to_file(&genesis_file).unwrap();
+
+        let mut config = config::NodeConfig::default();
+        config.network.genesis_file = Some(genesis_file);
+        let mut node = Node::new(config).await.unwrap();
+        node.storage = storage::StorageService::open(&dir.path().join("data")).unwrap();
+        node.store_genesis().await.unwrap();
+        let genesis = node.get_head().unwrap();
+
+        let (service, network) = NetworkService::new(network::NetworkConfig {
+            enable_mdns: false,
+            ..network::NetworkConfig::default()
+        })
+        .unwrap();
+        tokio::spawn(service.run());
+        (Harness { node, keys, genesis, _dir: dir }, network)
+    }
+
+    impl Harness {
+        fn key(&self, address: &Address) -> &KeyPair {
+            self.keys.iter().find(|key| key.address == *address).unwrap()
+        }
+
+        fn transfer(&self, from: usize, to: usize, amount: Amount, nonce: Nonce) -> Transaction {
+            let mut transaction = Transaction::new(self.keys[from].address, self.keys[to].address, amount, 21_000, 1, nonce, Vec::new());
+            transaction.sign(&self.keys[from].signing_key());
+            transaction
+        }
+
+        /// Signed block committing to the state root its transactions
+        /// produce, or to a zero root if they do not apply
+        async fn block(&self, height: u64, parent: Hash, slot: Slot, transactions: Vec<Transaction>) -> Block {
+            let proposer = self.node.consensus.get_proposer_for_slot(slot).unwrap();
+            let epoch = self.node.consensus.slot_to_epoch(slot);
+            let mut block = Block::new(height, parent, [0u8; 32], slot, epoch, proposer, transactions, [0u8; 32], BLOCK_GAS_LIMIT);
+            if let Ok((_, state_root)) = self.node.execute_block(&block).await {
+                block.header.state_root = state_root;
+            }
+            block.sign(&self.key(&proposer).signing_key());
+            block
+        }
+
+        async fn balance(&self, index: usize) -> Amount {
+            self.node.storage.get_account(&self.keys[index].address).await.unwrap().map_or(0, |account| account.balance)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocks_are_checked_against_their_parent_and_reorgs_roll_back_accounts() {
+        let (mut h, network) = harness().await;
+
+        // Two competing children of genesis. Without votes the proposer
+        // boost of the latest block decides the head, so each import reorgs.
+        let a = h.block(1, h.genesis, 1, vec![h.transfer(0, 1, 1_000, 0)]).await;
+        let b = h.block(1, h.genesis, 2, vec![h.transfer(0, 2, 3_000, 0)]).await;
+        h.node.import_block(&network, &a).await.unwrap();
+        assert_eq!(h.node.get_head(), Some(a.hash()));
+        assert_eq!(h.balance(0).await, BALANCE - 1_000);
+        assert_eq!(h.balance(1).await, BALANCE + 1_000);
+
+        h.node.import_block(&network, &b).await.unwrap();
+        assert_eq!(h.node.get_head(), Some(b.hash()));
+        assert_eq!(h.balance(0).await, BALANCE - 3_000);
+        assert_eq!(h.balance(1).await, BALANCE);
+        assert_eq!(h.balance(2).await, BALANCE + 3_000);
+
+        // Blocks on the side fork are checked against that fork's state:
+        // 4000 is left there, but only 2000 at the head
+        let overspend = h.block(2, a.hash(), 3, vec![h.transfer(0, 1, 4_001, 1)]).await;
+        assert!(h.node.import_block(&network, &overspend).await.is_err());
+        let spend = h.block(2, a.hash(), 3, vec![h.transfer(0, 1, 4_000, 1)]).await;
+        h.node.import_block(&network, &spend).await.unwrap();
+
+        // Switching back rebuilds the accounts from the fork point
+        assert_eq!(h.node.get_head(), Some(spend.hash()));
+        assert_eq!(h.node.storage.state_root(), spend.header.state_root);
+        assert_eq!(h.balance(0).await, 0);
+        assert_eq!(h.balance(1).await, BALANCE + 5_000);
+        assert_eq!(h.balance(2).await, BALANCE);
+
+        // A block reusing a nonce of its parent chain is refused
+        let replay = h.block(2, b.hash(), 4, vec![h.transfer(0, 1, 1, 0)]).await;
+        assert!(h.node.import_block(&network, &replay).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blocks_commit_to_their_state_root() {
+        let (mut h, network) = harness().await;
+
+        let block = h.block(1, h.genesis, 1, vec![h.transfer(0, 1, 1_000, 0)]).await;
+        let mut tampered = block.clone();
+        tampered.header.state_root = [7u8; 32];
+        tampered.sign(&h.key(&tampered.header.proposer).signing_key());
+        assert!(h.node.import_block(&network, &tampered).await.is_err());
+        assert_eq!(h.node.get_head(), Some(h.genesis));
+
+        h.node.import_block(&network, &block).await.unwrap();
+        assert_ne!(block.header.state_root, [0u8; 32]);
+        assert_eq!(h.node.storage.state_root(), block.header.state_root);
+
+        // An empty block keeps its parent's state root
+        let empty = h.block(2, block.hash(), 2, Vec::new()).await;
+        assert_eq!(empty.header.state_root, block.header.state_root);
+        h.node.import_block(&network, &empty).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_submitting_to_a_full_pending_set_is_unavailable() {
+        let (mut h, network) = harness().await;
+        h.node.storage.store_account(Account::new(h.keys[0].address, 1_000_000)).await.unwrap();
+
+        for nonce in 0..MAX_PENDING_TRANSACTIONS as Nonce {
+            let transaction = Transaction::new(h.keys[1].address, h.keys[2].address, 1, 21_000, 1, nonce, Vec::new());
+            h.node.pending_transactions.insert(transaction.hash(), PendingTransaction {
+                transaction,
+                received: std::time::Instant::now(),
+            });
+        }
+        let request = api::ApiRequest::SubmitTransaction(Box::new(h.transfer(0, 1, 1, 0)));
+        let error = h.node.handle_api_request(&network, request).await.unwrap_err();
+        assert_eq!(error.code, api::ErrorCode::PoolFull);
+        assert_eq!(error.code.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
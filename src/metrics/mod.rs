@@ -0,0 +1,257 @@
+// Metrics module - Prometheus metrics for the node's subsystems and the
+// HTTP endpoint that serves them
+
+use crate::config::MetricsConfig;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::{Registry, Unit};
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Path the metrics are served on
+pub const METRICS_PATH: &str = "/metrics";
+
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ResultLabels {
+    pub result: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct PeerLabels {
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct GossipLabels {
+    pub topic: String,
+    pub result: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct DutyLabels {
+    pub duty: String,
+    pub outcome: String,
+}
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+/// Latency buckets from 1ms to about 16s
+fn latency_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.001, 2.0, 15))
+}
+
+/// Metrics of every subsystem. Clones share the same values, so a clone
+/// can be handed to each subsystem.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    pub head_slot: Gauge,
+    pub finalized_epoch: Gauge,
+    pub reorgs: Counter,
+    /// Time spent importing a block through consensus, by whether it was
+    /// accepted
+    pub block_processing: HistogramFamily<ResultLabels>,
+    pub pending_transactions: Gauge,
+    pub peers: Family<PeerLabels, Gauge>,
+    /// Received gossip by topic and validation result
+    pub gossip_messages: Family<GossipLabels, Counter>,
+    pub storage_size: Gauge,
+    /// Time spent writing storage to disk
+    pub storage_write: Histogram,
+    /// Validator duties performed (`hit`) or failed (`miss`)
+    pub validator_duties: Family<DutyLabels, Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(&MetricsConfig::default().namespace)
+    }
+}
+
+impl Metrics {
+    /// Metrics whose names start with `namespace`
+    pub fn new(namespace: &str) -> Self {
+        let mut registry = Registry::with_prefix(namespace);
+
+        let head_slot = Gauge::default();
+        registry.register("head_slot", "Slot of the canonical head", head_slot.clone());
+        let finalized_epoch = Gauge::default();
+        registry.register("finalized_epoch", "Epoch of the finalized checkpoint", finalized_epoch.clone());
+        let reorgs = Counter::default();
+        registry.register("reorgs", "Canonical chain reorganizations", reorgs.clone());
+        let block_processing: HistogramFamily<ResultLabels> =
+            Family::new_with_constructor(latency_histogram as fn() -> Histogram);
+        registry.register_with_unit(
+            "block_processing",
+            "Time to import a block through consensus",
+            Unit::Seconds,
+            block_processing.clone(),
+        );
+        let pending_transactions = Gauge::default();
+        registry.register("pending_transactions", "Transactions waiting for inclusion", pending_transactions.clone());
+        let peers = Family::default();
+        registry.register("peers", "Known peers by status", peers.clone());
+        let gossip_messages = Family::default();
+        registry.register(
+            "gossip_messages",
+            "Received gossip messages by topic and validation result",
+            gossip_messages.clone(),
+        );
+        let storage_size = Gauge::default();
+        registry.register_with_unit("storage_size", "Size of the chain data on disk", Unit::Bytes, storage_size.clone());
+        let storage_write = latency_histogram();
+        registry.register_with_unit("storage_write", "Time to write storage to disk", Unit::Seconds, storage_write.clone());
+        let validator_duties = Family::default();
+        registry.register("validator_duties", "Validator duties by duty and outcome", validator_duties.clone());
+
+        Self {
+            registry: Arc::new(registry),
+            head_slot,
+            finalized_epoch,
+            reorgs,
+            block_processing,
+            pending_transactions,
+            peers,
+            gossip_messages,
+            storage_size,
+            storage_write,
+            validator_duties,
+        }
+    }
+
+    pub fn observe_block_processing(&self, accepted: bool, elapsed: Duration) {
+        let result = if accepted { "accepted" } else { "rejected" };
+        self.block_processing
+            .get_or_create(&ResultLabels { result: result.to_string() })
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_gossip(&self, topic: &str, result: &str) {
+        self.gossip_messages
+            .get_or_create(&GossipLabels {
+                topic: topic.to_string(),
+                result: result.to_string(),
+            })
+            .inc();
+    }
+
+    pub fn set_peers(&self, status: &str, count: usize) {
+        self.peers.get_or_create(&PeerLabels { status: status.to_string() }).set(count as i64);
+    }
+
+    /// Count a duty as a hit when it succeeded and a miss when it failed
+    pub fn record_duty<T, E>(&self, duty: &str, result: &Result<T, E>) {
+        let outcome = if result.is_ok() { "hit" } else { "miss" };
+        self.validator_duties
+            .get_or_create(&DutyLabels {
+                duty: duty.to_string(),
+                outcome: outcome.to_string(),
+            })
+            .inc();
+    }
+
+    /// All metrics in the OpenMetrics text format
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        if let Err(e) = prometheus_client::encoding::text::encode(&mut text, &self.registry) {
+            warn!("Failed to encode metrics: {}", e);
+        }
+        text
+    }
+}
+
+/// HTTP server for Prometheus scrapes of `METRICS_PATH`
+#[derive(Debug)]
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    /// Bind to `config.listen_address` and serve `metrics` until stopped
+    pub fn start(config: &MetricsConfig, metrics: Metrics) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let addr: SocketAddr = config.listen_address
+            .parse()
+            .map_err(|e| format!("Invalid metrics listen address {}: {}", config.listen_address, e))?;
+
+        let make_service = make_service_fn(move |_| {
+            let metrics = metrics.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| serve(metrics.clone(), request))) }
+        });
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        let local_addr = server.local_addr();
+
+        let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let server = server.with_graceful_shutdown(async {
+                let _ = shutdown_signal.await;
+            });
+            if let Err(e) = server.await {
+                warn!("Metrics server failed: {}", e);
+            }
+        });
+
+        info!("Metrics available on http://{}{}", local_addr, METRICS_PATH);
+        Ok(Self { local_addr, shutdown, task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+async fn serve(metrics: Metrics, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = if request.method() == Method::GET && request.uri().path() == METRICS_PATH {
+        Response::builder()
+            .header(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)
+            .body(Body::from(metrics.encode()))
+    } else {
+        Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty())
+    };
+    Ok(response.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_are_namespaced_and_labelled() {
+        let metrics = Metrics::new("testnet");
+        metrics.head_slot.set(42);
+        metrics.reorgs.inc();
+        metrics.observe_block_processing(true, Duration::from_millis(3));
+        metrics.record_gossip("blocks", "accept");
+        metrics.set_peers("connected", 2);
+        metrics.record_duty::<(), ()>("attestation", &Err(()));
+
+        let text = metrics.encode();
+        assert!(text.contains("testnet_head_slot 42"));
+        assert!(text.contains("testnet_reorgs_total 1"));
+        assert!(text.contains("testnet_block_processing_seconds_count{result=\"accepted\"} 1"));
+        assert!(text.contains("testnet_gossip_messages_total{topic=\"blocks\",result=\"accept\"} 1"));
+        assert!(text.contains("testnet_peers{status=\"connected\"} 2"));
+        assert!(text.contains("testnet_validator_duties_total{duty=\"attestation\",outcome=\"miss\"} 1"));
+        assert!(text.contains("# TYPE testnet_storage_write_seconds histogram"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
@@ -0,0 +1,222 @@
+use async_trait::async_trait;
+use bincode::Options;
+use futures::prelude::*;
+use libp2p::{request_response, StreamProtocol};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use super::{SyncRequest, SyncResponse, TrafficCounters, SYNC_PROTOCOL};
+
+/// Version byte leading every binary frame
+pub const FRAME_VERSION: u8 = 1;
+
+/// Sync protocol using snappy-compressed binary frames
+pub const SYNC_PROTOCOL_SNAPPY: &str = "/republic-chain/sync/2/bincode_snappy";
+
+/// Upper bound on a sync response; a batch of blocks is larger than a
+/// single gossip message
+pub const MAX_SYNC_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Encoding used on the wire for gossip and sync messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WireFormat {
+    /// Plain JSON, spoken by nodes predating binary framing
+    Json,
+    /// Version byte followed by snappy-compressed bincode
+    SnappyBincode,
+}
+
+impl WireFormat {
+    /// Suffix appended to gossip topic names. JSON keeps the original
+    /// topic names so older nodes remain reachable.
+    pub fn topic_suffix(&self) -> &'static str {
+        match self {
+            WireFormat::Json => "",
+            WireFormat::SnappyBincode => "/bincode_snappy",
+        }
+    }
+
+    /// Format of the messages published on a full gossip topic name
+    pub fn from_topic(topic: &str) -> Self {
+        if topic.ends_with(WireFormat::SnappyBincode.topic_suffix()) {
+            WireFormat::SnappyBincode
+        } else {
+            WireFormat::Json
+        }
+    }
+
+    /// Request-response protocol name for this format
+    pub fn sync_protocol(&self) -> &'static str {
+        match self {
+            WireFormat::Json => SYNC_PROTOCOL,
+            WireFormat::SnappyBincode => SYNC_PROTOCOL_SNAPPY,
+        }
+    }
+
+    /// Format negotiated for a sync protocol name
+    pub fn from_sync_protocol(protocol: &str) -> Option<Self> {
+        match protocol {
+            SYNC_PROTOCOL => Some(WireFormat::Json),
+            SYNC_PROTOCOL_SNAPPY => Some(WireFormat::SnappyBincode),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            WireFormat::Json => serde_json::to_vec(value).map_err(|e| format!("Failed to encode message: {}", e)),
+            WireFormat::SnappyBincode => {
+                let payload = bincode_options(u64::MAX)
+                    .serialize(value)
+                    .map_err(|e| format!("Failed to encode message: {}", e))?;
+                let compressed = snap::raw::Encoder::new()
+                    .compress_vec(&payload)
+                    .map_err(|e| format!("Failed to compress message: {}", e))?;
+
+                let mut frame = Vec::with_capacity(compressed.len() + 1);
+                frame.push(FRAME_VERSION);
+                frame.extend_from_slice(&compressed);
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Decode a message, rejecting anything that is or would decompress to
+    /// more than `max_size` bytes before allocating for it
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8], max_size: usize) -> Result<T, String> {
+        if data.len() > max_size {
+            return Err(format!("Message of {} bytes exceeds limit of {}", data.len(), max_size));
+        }
+
+        match self {
+            WireFormat::Json => serde_json::from_slice(data).map_err(|e| format!("Failed to decode message: {}", e)),
+            WireFormat::SnappyBincode => {
+                let Some((&version, compressed)) = data.split_first() else {
+                    return Err("Empty message frame".to_string());
+                };
+                if version != FRAME_VERSION {
+                    return Err(format!("Unsupported frame version {}", version));
+                }
+
+                let length = snap::raw::decompress_len(compressed)
+                    .map_err(|e| format!("Invalid snappy frame: {}", e))?;
+                if length > max_size {
+                    return Err(format!("Decompressed size {} exceeds limit of {}", length, max_size));
+                }
+                let payload = snap::raw::Decoder::new()
+                    .decompress_vec(compressed)
+                    .map_err(|e| format!("Failed to decompress message: {}", e))?;
+
+                bincode_options(max_size as u64)
+                    .deserialize(&payload)
+                    .map_err(|e| format!("Failed to decode message: {}", e))
+            }
+        }
+    }
+}
+
+fn bincode_options(limit: u64) -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_limit(limit)
+}
+
+/// Sync codec speaking both the JSON and the snappy protocol. The format
+/// follows whichever protocol was negotiated for the stream.
+#[derive(Debug, Clone)]
+pub struct SyncCodec {
+    max_request_size: usize,
+    max_response_size: usize,
+    stats: Option<Arc<Mutex<TrafficCounters>>>,
+}
+
+impl SyncCodec {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_request_size: max_message_size,
+            max_response_size: max_message_size.max(MAX_SYNC_RESPONSE_SIZE),
+            stats: None,
+        }
+    }
+
+    /// Count messages and wire bytes in the given counters
+    pub fn with_stats(mut self, stats: Arc<Mutex<TrafficCounters>>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    fn record(&self, update: impl FnOnce(&mut TrafficCounters)) {
+        if let Some(stats) = &self.stats {
+            update(&mut stats.lock().unwrap_or_else(|e| e.into_inner()));
+        }
+    }
+
+    /// Protocols to offer, preferred first
+    pub fn protocols(formats: &[WireFormat]) -> Vec<StreamProtocol> {
+        formats
+            .iter()
+            .map(|format| StreamProtocol::new(format.sync_protocol()))
+            .collect()
+    }
+
+    async fn read<T, M>(&self, protocol: &StreamProtocol, io: &mut T, max_size: usize) -> io::Result<M>
+    where
+        T: AsyncRead + Unpin + Send,
+        M: DeserializeOwned,
+    {
+        let format = WireFormat::from_sync_protocol(protocol.as_ref())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown sync protocol"))?;
+
+        // Read one byte past the limit so oversized messages are detected
+        let mut data = Vec::new();
+        io.take(max_size as u64 + 1).read_to_end(&mut data).await?;
+        self.record(|stats| stats.record_in(data.len()));
+        format.decode(&data, max_size).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn write<T, M>(&self, protocol: &StreamProtocol, io: &mut T, message: &M) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+        M: Serialize,
+    {
+        let format = WireFormat::from_sync_protocol(protocol.as_ref())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown sync protocol"))?;
+        let data = format.encode(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.record(|stats| stats.record_out(data.len()));
+        io.write_all(&data).await
+    }
+}
+
+#[async_trait]
+impl request_response::Codec for SyncCodec {
+    type Protocol = StreamProtocol;
+    type Request = SyncRequest;
+    type Response = SyncResponse;
+
+    async fn read_request<T>(&mut self, protocol: &Self::Protocol, io: &mut T) -> io::Result<SyncRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read(protocol, io, self.max_request_size).await
+    }
+
+    async fn read_response<T>(&mut self, protocol: &Self::Protocol, io: &mut T) -> io::Result<SyncResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read(protocol, io, self.max_response_size).await
+    }
+
+    async fn write_request<T>(&mut self, protocol: &Self::Protocol, io: &mut T, request: SyncRequest) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write(protocol, io, &request).await
+    }
+
+    async fn write_response<T>(&mut self, protocol: &Self::Protocol, io: &mut T, response: SyncResponse) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write(protocol, io, &response).await
+    }
+}
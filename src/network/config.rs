@@ -1,22 +1,75 @@
+use libp2p::multiaddr::Protocol;
 use libp2p::Multiaddr;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::time::Duration;
+use crate::crypto::compute_fork_digest;
+use crate::types::{Checkpoint, Hash, NetworkId};
+use super::{MessageType, RateLimitConfig, StatusMessage, WireFormat};
 
+/// P2P settings of a node. This is the `[network]` section of the node
+/// config file; fields derived from the genesis at startup are not read
+/// from the file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct NetworkConfig {
-    /// Port to listen on
+    /// Network this node belongs to
+    pub network_id: NetworkId,
+
+    /// Genesis file defining the chain; without one the node starts with
+    /// an empty validator set
+    pub genesis_file: Option<PathBuf>,
+
+    /// Hex encoded genesis hash the genesis file must produce
+    pub genesis_hash: Option<String>,
+
+    /// Genesis validators root of the chain
+    #[serde(skip)]
+    pub genesis_root: Hash,
+
+    /// Current fork version
+    pub fork_version: [u8; 4],
+
+    /// Genesis time in unix seconds; 0 disables slot timing checks on gossip
+    #[serde(skip)]
+    pub genesis_time: u64,
+
+    /// Slot duration in seconds
+    #[serde(skip)]
+    pub seconds_per_slot: u64,
+
+    /// IP address to listen on
+    pub listen_address: String,
+
+    /// Port to listen on; 9000 when omitted from a config file
+    #[serde(default = "default_port")]
     pub port: u16,
 
     /// Maximum number of connections
+    #[serde(alias = "max_peers")]
     pub max_connections: u32,
 
+    /// Number of connections discovery tries to maintain
+    pub target_peers: u32,
+
+    /// Interval between discovery rounds
+    #[serde(with = "duration_secs")]
+    pub discovery_interval: Duration,
+
+    /// Publish a signed node record (network, fork digest, subnets) to the DHT
+    pub advertise_node_record: bool,
+
     /// Connection timeout
+    #[serde(with = "duration_secs")]
     pub connection_timeout: Duration,
 
     /// Heartbeat interval for gossipsub
+    #[serde(with = "duration_secs")]
     pub heartbeat_interval: Duration,
 
     /// Bootstrap peers to connect to on startup
+    #[serde(alias = "bootstrap_nodes")]
     pub bootstrap_peers: Vec<Multiaddr>,
 
     /// Enable mDNS for local peer discovery
@@ -25,14 +78,31 @@ pub struct NetworkConfig {
     /// Topics to subscribe to
     pub default_topics: Vec<String>,
 
-    /// Maximum message size
+    /// Number of attestation subnet topics
+    pub attestation_subnet_count: u64,
+
+    /// Maximum message size, checked before decompression
     pub max_message_size: usize,
 
+    /// Wire formats spoken for gossip and sync, preferred first. Gossip is
+    /// published in the preferred format only; adding `Json` also accepts
+    /// gossip and sync from nodes without binary framing.
+    pub wire_formats: Vec<WireFormat>,
+
+    /// Per-peer quotas for gossip and sync requests
+    pub rate_limits: RateLimitConfig,
+
     /// Local network configuration for testing
     pub local_network: LocalNetworkConfig,
+
+    /// Directory for persistent network state such as peer bans; set by
+    /// the node inside its data directory
+    #[serde(skip)]
+    pub data_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct LocalNetworkConfig {
     /// Enable local network mode (useful for testing)
     pub enabled: bool,
@@ -50,19 +120,37 @@ pub struct LocalNetworkConfig {
 impl Default for NetworkConfig {
     fn default() -> Self {
         Self {
+            network_id: NetworkId::Devnet,
+            genesis_file: None,
+            genesis_hash: None,
+            genesis_root: [0u8; 32],
+            fork_version: [0u8; 4],
+            genesis_time: 0,
+            seconds_per_slot: 12,
+            listen_address: "0.0.0.0".to_string(),
             port: 0, // Let the OS choose a port
             max_connections: 50,
+            target_peers: 25,
+            discovery_interval: Duration::from_secs(30),
+            advertise_node_record: true,
             connection_timeout: Duration::from_secs(30),
             heartbeat_interval: Duration::from_secs(10),
             bootstrap_peers: vec![],
             enable_mdns: true,
             default_topics: vec![
-                "blocks".to_string(),
-                "transactions".to_string(),
-                "consensus".to_string(),
+                MessageType::Block.topic().to_string(),
+                MessageType::Transaction.topic().to_string(),
+                MessageType::AggregateAndProof.topic().to_string(),
+                MessageType::ProposerSlashing.topic().to_string(),
+                MessageType::AttesterSlashing.topic().to_string(),
+                MessageType::VoluntaryExit.topic().to_string(),
             ],
+            attestation_subnet_count: 64,
             max_message_size: 1024 * 1024, // 1MB
+            wire_formats: vec![WireFormat::SnappyBincode],
+            rate_limits: RateLimitConfig::default(),
             local_network: LocalNetworkConfig::default(),
+            data_dir: None,
         }
     }
 }
@@ -101,9 +189,65 @@ impl NetworkConfig {
 
     /// Create config for a specific port
     pub fn with_port(port: u16) -> Self {
-        let mut config = Self::default();
-        config.port = port;
-        config
+        Self {
+            port,
+            ..Self::default()
+        }
+    }
+
+    /// Address to listen on for P2P connections
+    pub fn listen_multiaddr(&self) -> Result<Multiaddr, String> {
+        let ip: IpAddr = self.listen_address
+            .parse()
+            .map_err(|e| format!("Invalid listen address {}: {}", self.listen_address, e))?;
+        Ok(Multiaddr::from(ip).with(Protocol::Tcp(self.port)))
+    }
+
+    /// Digest of the fork version and genesis root
+    pub fn fork_digest(&self) -> [u8; 4] {
+        compute_fork_digest(&self.fork_version, &self.genesis_root)
+    }
+
+    /// Full gossip topic name, namespaced by network and fork digest
+    pub fn topic_name(&self, topic: &str) -> String {
+        format!(
+            "/republic-chain/{}/{}/{}",
+            self.network_id.name(),
+            hex::encode(self.fork_digest()),
+            topic
+        )
+    }
+
+    /// Format gossip is published in
+    pub fn preferred_wire_format(&self) -> WireFormat {
+        self.wire_formats.first().copied().unwrap_or(WireFormat::SnappyBincode)
+    }
+
+    /// Gossip topic name carrying messages in the given wire format
+    pub fn format_topic_name(&self, topic: &str, format: WireFormat) -> String {
+        format!("{}{}", self.topic_name(topic), format.topic_suffix())
+    }
+
+    /// Kademlia protocol name; separate DHTs per network
+    pub fn kad_protocol(&self) -> String {
+        format!("/republic-chain/{}/kad/1.0.0", self.network_id.name())
+    }
+
+    /// Protocol version advertised via identify
+    pub fn protocol_version(&self) -> String {
+        format!("/republic-chain/{}/1.0.0", self.network_id.name())
+    }
+
+    /// Status message for the given chain head
+    pub fn local_status(&self, head_hash: Hash, head_height: u64, finalized_checkpoint: Checkpoint) -> StatusMessage {
+        StatusMessage {
+            network_id: self.network_id,
+            genesis_root: self.genesis_root,
+            fork_version: self.fork_version,
+            head_hash,
+            head_height,
+            finalized_checkpoint,
+        }
     }
 
     /// Add a bootstrap peer
@@ -122,4 +266,25 @@ impl NetworkConfig {
             self.default_topics.push(topic);
         }
     }
-}
\ No newline at end of file
+}
+
+/// P2P port of a node configured from a file
+pub const DEFAULT_PORT: u16 = 9000;
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+/// Durations in config files are whole seconds
+mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+    }
+}
@@ -0,0 +1,91 @@
+use libp2p::{identity, kad, multiaddr::Protocol, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::types::NetworkId;
+use super::NetworkConfig;
+
+/// Information a node advertises about itself in the DHT
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    /// Network the node belongs to
+    pub network_id: NetworkId,
+    /// Fork digest the node follows
+    pub fork_digest: [u8; 4],
+    /// Attestation subnets the node is subscribed to
+    pub attestation_subnets: Vec<u64>,
+    /// Incremented on every change so newer records win
+    pub seq: u64,
+}
+
+/// Node record signed with the node's libp2p identity key. DHT records are
+/// not authenticated, so the signature ties a record to its peer id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedNodeRecord {
+    pub record: NodeRecord,
+    /// Protobuf-encoded libp2p public key
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl NodeRecord {
+    /// DHT key under which a peer publishes its record
+    pub fn dht_key(network_id: NetworkId, peer_id: &PeerId) -> kad::RecordKey {
+        kad::RecordKey::new(&format!("/republic-chain/{}/node/{}", network_id.name(), peer_id))
+    }
+
+    /// Check that the record belongs to our network and fork
+    pub fn check_compatible(&self, config: &NetworkConfig) -> Result<(), String> {
+        if self.network_id != config.network_id {
+            return Err(format!("Node record is for network {}", self.network_id.name()));
+        }
+        if self.fork_digest != config.fork_digest() {
+            return Err(format!("Node record has fork digest {}", hex::encode(self.fork_digest)));
+        }
+        Ok(())
+    }
+
+    pub fn serves_subnet(&self, subnet_id: u64) -> bool {
+        self.attestation_subnets.contains(&subnet_id)
+    }
+
+    pub fn sign(self, keypair: &identity::Keypair) -> Result<SignedNodeRecord, String> {
+        let payload = serde_json::to_vec(&self).map_err(|e| e.to_string())?;
+        let signature = keypair.sign(&payload).map_err(|e| format!("Failed to sign node record: {}", e))?;
+        Ok(SignedNodeRecord {
+            record: self,
+            public_key: keypair.public().encode_protobuf(),
+            signature,
+        })
+    }
+}
+
+impl SignedNodeRecord {
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| e.to_string())
+    }
+
+    /// Decode a record fetched from the DHT and check it was signed by `peer_id`
+    pub fn decode_and_verify(data: &[u8], peer_id: &PeerId) -> Result<NodeRecord, String> {
+        let signed: SignedNodeRecord = serde_json::from_slice(data)
+            .map_err(|e| format!("Invalid node record: {}", e))?;
+        let public_key = identity::PublicKey::try_decode_protobuf(&signed.public_key)
+            .map_err(|e| format!("Invalid node record key: {}", e))?;
+        if PeerId::from(&public_key) != *peer_id {
+            return Err("Node record key does not match peer id".to_string());
+        }
+
+        let payload = serde_json::to_vec(&signed.record).map_err(|e| e.to_string())?;
+        if !public_key.verify(&payload, &signed.signature) {
+            return Err("Invalid node record signature".to_string());
+        }
+        Ok(signed.record)
+    }
+}
+
+/// Peer id embedded in a `/p2p/...` multiaddr, if any
+pub fn peer_id_from_multiaddr(addr: &Multiaddr) -> Option<PeerId> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::P2p(peer_id) => Some(peer_id),
+        _ => None,
+    })
+}
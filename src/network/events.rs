@@ -1,5 +1,8 @@
-use libp2p::{Multiaddr, PeerId};
-use crate::types::{Block, Transaction};
+use libp2p::{request_response::InboundRequestId, Multiaddr, PeerId};
+use crate::types::{
+    AggregateAndProof, Attestation, AttesterSlashing, Block, ProposerSlashing, Transaction, VoluntaryExit,
+};
+use super::{StatusMessage, SyncRequest};
 
 /// Network events that can be emitted by the network service
 #[derive(Debug, Clone)]
@@ -19,6 +22,24 @@ pub enum NetworkEvent {
         peer_id: PeerId,
     },
 
+    /// A peer completed the status handshake
+    PeerStatusReceived {
+        peer_id: PeerId,
+        status: StatusMessage,
+    },
+
+    /// A peer was disconnected because it is on another network or fork
+    PeerRejected {
+        peer_id: PeerId,
+        reason: String,
+    },
+
+    /// A peer was banned for misbehaviour
+    PeerBanned {
+        peer_id: PeerId,
+        reason: String,
+    },
+
     /// A new block was received from a peer
     BlockReceived {
         block: Block,
@@ -31,11 +52,50 @@ pub enum NetworkEvent {
         from: PeerId,
     },
 
+    /// An attestation was received on a subnet
+    AttestationReceived {
+        attestation: Attestation,
+        subnet_id: u64,
+        from: PeerId,
+    },
+
+    /// An aggregated attestation was received from a peer
+    AggregateAndProofReceived {
+        aggregate: AggregateAndProof,
+        from: PeerId,
+    },
+
+    /// Proposer slashing evidence was received from a peer
+    ProposerSlashingReceived {
+        slashing: ProposerSlashing,
+        from: PeerId,
+    },
+
+    /// Attester slashing evidence was received from a peer
+    AttesterSlashingReceived {
+        slashing: AttesterSlashing,
+        from: PeerId,
+    },
+
+    /// A voluntary exit was received from a peer
+    VoluntaryExitReceived {
+        exit: VoluntaryExit,
+        from: PeerId,
+    },
+
     /// A ping was received from a peer
     PingReceived {
         from: PeerId,
     },
 
+    /// A peer sent a block sync request; answer it with
+    /// `NetworkHandle::send_sync_response`
+    SyncRequestReceived {
+        peer_id: PeerId,
+        request_id: InboundRequestId,
+        request: SyncRequest,
+    },
+
     /// Failed to connect to a peer
     ConnectionFailed {
         peer_id: Option<PeerId>,
@@ -84,9 +144,18 @@ impl NetworkEvent {
         match self {
             NetworkEvent::PeerConnected { peer_id } => Some(*peer_id),
             NetworkEvent::PeerDisconnected { peer_id } => Some(*peer_id),
+            NetworkEvent::PeerStatusReceived { peer_id, .. } => Some(*peer_id),
+            NetworkEvent::PeerRejected { peer_id, .. } => Some(*peer_id),
+            NetworkEvent::PeerBanned { peer_id, .. } => Some(*peer_id),
             NetworkEvent::BlockReceived { from, .. } => Some(*from),
             NetworkEvent::TransactionReceived { from, .. } => Some(*from),
+            NetworkEvent::AttestationReceived { from, .. } => Some(*from),
+            NetworkEvent::AggregateAndProofReceived { from, .. } => Some(*from),
+            NetworkEvent::ProposerSlashingReceived { from, .. } => Some(*from),
+            NetworkEvent::AttesterSlashingReceived { from, .. } => Some(*from),
+            NetworkEvent::VoluntaryExitReceived { from, .. } => Some(*from),
             NetworkEvent::PingReceived { from } => Some(*from),
+            NetworkEvent::SyncRequestReceived { peer_id, .. } => Some(*peer_id),
             NetworkEvent::ConnectionFailed { peer_id, .. } => *peer_id,
             NetworkEvent::PeerDiscovered { peer_id, .. } => Some(*peer_id),
             NetworkEvent::MessageValidationFailed { from, .. } => Some(*from),
@@ -106,15 +175,51 @@ impl NetworkEvent {
             NetworkEvent::PeerDisconnected { peer_id } => {
                 format!("Disconnected from peer {}", peer_id)
             }
+            NetworkEvent::PeerStatusReceived { peer_id, status } => {
+                format!("Peer {} is at height {}", peer_id, status.head_height)
+            }
+            NetworkEvent::PeerRejected { peer_id, reason } => {
+                format!("Rejected peer {}: {}", peer_id, reason)
+            }
+            NetworkEvent::PeerBanned { peer_id, reason } => {
+                format!("Banned peer {}: {}", peer_id, reason)
+            }
             NetworkEvent::BlockReceived { block, from } => {
                 format!("Received block #{} from {}", block.header.height, from)
             }
             NetworkEvent::TransactionReceived { transaction, from } => {
                 format!("Received transaction {:?} from {}", transaction.hash(), from)
             }
+            NetworkEvent::AttestationReceived { attestation, subnet_id, from } => {
+                format!(
+                    "Received attestation for slot {} on subnet {} from {}",
+                    attestation.slot, subnet_id, from
+                )
+            }
+            NetworkEvent::AggregateAndProofReceived { aggregate, from } => {
+                format!(
+                    "Received aggregate for slot {} from aggregator {} via {}",
+                    aggregate.aggregate.data.slot, aggregate.aggregator_index, from
+                )
+            }
+            NetworkEvent::ProposerSlashingReceived { slashing, from } => {
+                format!(
+                    "Received proposer slashing for validator {} from {}",
+                    slashing.signed_header_1.header.proposer_index, from
+                )
+            }
+            NetworkEvent::AttesterSlashingReceived { from, .. } => {
+                format!("Received attester slashing from {}", from)
+            }
+            NetworkEvent::VoluntaryExitReceived { exit, from } => {
+                format!("Received voluntary exit for validator {} from {}", exit.validator_index, from)
+            }
             NetworkEvent::PingReceived { from } => {
                 format!("Received ping from {}", from)
             }
+            NetworkEvent::SyncRequestReceived { peer_id, request, .. } => {
+                format!("Received sync request {:?} from {}", request, peer_id)
+            }
             NetworkEvent::ConnectionFailed { peer_id, error } => {
                 if let Some(peer_id) = peer_id {
                     format!("Failed to connect to {}: {}", peer_id, error)
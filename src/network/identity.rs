@@ -0,0 +1,57 @@
+use libp2p::identity::Keypair;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+/// File in the network data directory holding the node's libp2p identity
+pub const IDENTITY_FILE: &str = "identity.key";
+
+/// Load the node identity from `data_dir`, or generate one and save it
+/// there so the PeerId survives restarts. Without a data directory the
+/// identity is ephemeral.
+pub fn load_or_create_identity(data_dir: Option<&Path>) -> Result<Keypair, Box<dyn Error + Send + Sync>> {
+    let Some(dir) = data_dir else {
+        return Ok(Keypair::generate_ed25519());
+    };
+    let path = dir.join(IDENTITY_FILE);
+    if path.exists() {
+        let bytes = std::fs::read(&path)?;
+        return Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| format!("Invalid network identity {}: {}", path.display(), e).into());
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    write_identity(&path, &keypair)?;
+    info!("Generated network identity {}", path.display());
+    Ok(keypair)
+}
+
+/// Write an identity key readable only by its owner, via a temporary file
+/// so a crash never leaves a truncated key behind
+pub fn write_identity(path: &Path, keypair: &Keypair) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let bytes = keypair.to_protobuf_encoding().map_err(|e| e.to_string())?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("key.tmp");
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp)?;
+    #[cfg(unix)]
+    {
+        // The mode only applies to new files
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
@@ -1,7 +1,12 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use super::WireFormat;
+use crate::types::{
+    AggregateAndProof, Attestation, AttesterSlashing, Block, Hash, ProposerSlashing, Slot, Transaction, VoluntaryExit,
+};
 
 /// Types of messages that can be sent over the network
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum MessageType {
     /// Block message
     Block,
@@ -9,10 +14,71 @@ pub enum MessageType {
     Transaction,
     /// Ping message for connectivity testing
     Ping,
+    /// Single-validator attestation, published on a subnet
+    Attestation,
+    /// Aggregated attestation with the aggregator's selection proof
+    AggregateAndProof,
+    /// Evidence of a proposer signing two blocks for one slot
+    ProposerSlashing,
+    /// Evidence of conflicting attestations
+    AttesterSlashing,
+    /// Validator exit request
+    VoluntaryExit,
 }
 
-/// Network message wrapper
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl MessageType {
+    /// Gossip topic carrying this message type. Attestations are sharded
+    /// across subnet topics, see `attestation_subnet_topic`.
+    pub fn topic(&self) -> &'static str {
+        match self {
+            MessageType::Block => "blocks",
+            MessageType::Transaction => "transactions",
+            MessageType::Ping => "ping",
+            MessageType::Attestation => "beacon_attestation",
+            MessageType::AggregateAndProof => "beacon_aggregate_and_proof",
+            MessageType::ProposerSlashing => "proposer_slashing",
+            MessageType::AttesterSlashing => "attester_slashing",
+            MessageType::VoluntaryExit => "voluntary_exit",
+        }
+    }
+
+    /// Message type carried on a full gossip topic name
+    pub fn from_topic(topic: &str) -> Option<Self> {
+        let topic = topic.strip_suffix(WireFormat::SnappyBincode.topic_suffix()).unwrap_or(topic);
+        let name = topic.rsplit('/').next()?;
+        [
+            MessageType::Block,
+            MessageType::Transaction,
+            MessageType::Ping,
+            MessageType::AggregateAndProof,
+            MessageType::ProposerSlashing,
+            MessageType::AttesterSlashing,
+            MessageType::VoluntaryExit,
+        ]
+        .into_iter()
+        .find(|msg_type| msg_type.topic() == name)
+        .or_else(|| {
+            name.strip_prefix(MessageType::Attestation.topic())
+                .filter(|subnet| subnet.starts_with('_'))
+                .map(|_| MessageType::Attestation)
+        })
+    }
+}
+
+/// Topic name for an attestation subnet
+pub fn attestation_subnet_topic(subnet_id: u64) -> String {
+    format!("{}_{}", MessageType::Attestation.topic(), subnet_id)
+}
+
+/// Subnet an attestation for the given slot and committee is published on
+pub fn compute_subnet_for_attestation(slot: Slot, committee_index: u64, subnet_count: u64) -> u64 {
+    slot.wrapping_add(committee_index) % subnet_count.max(1)
+}
+
+/// Network message wrapper. `data` holds the payload as JSON; binary
+/// formats such as bincode carry the decoded payload instead, see
+/// `BinaryMessage`.
+#[derive(Debug, Clone)]
 pub struct NetworkMessage {
     /// Type of the message
     pub msg_type: MessageType,
@@ -44,11 +110,61 @@ impl NetworkMessage {
         Ok(Self::new(MessageType::Transaction, data))
     }
 
+    /// Create an attestation message
+    pub fn attestation(attestation: &Attestation) -> Result<Self, serde_json::Error> {
+        let data = serde_json::to_vec(attestation)?;
+        Ok(Self::new(MessageType::Attestation, data))
+    }
+
+    /// Create an aggregate and proof message
+    pub fn aggregate_and_proof(aggregate: &AggregateAndProof) -> Result<Self, serde_json::Error> {
+        let data = serde_json::to_vec(aggregate)?;
+        Ok(Self::new(MessageType::AggregateAndProof, data))
+    }
+
+    /// Create a proposer slashing message
+    pub fn proposer_slashing(slashing: &ProposerSlashing) -> Result<Self, serde_json::Error> {
+        let data = serde_json::to_vec(slashing)?;
+        Ok(Self::new(MessageType::ProposerSlashing, data))
+    }
+
+    /// Create an attester slashing message
+    pub fn attester_slashing(slashing: &AttesterSlashing) -> Result<Self, serde_json::Error> {
+        let data = serde_json::to_vec(slashing)?;
+        Ok(Self::new(MessageType::AttesterSlashing, data))
+    }
+
+    /// Create a voluntary exit message
+    pub fn voluntary_exit(exit: &VoluntaryExit) -> Result<Self, serde_json::Error> {
+        let data = serde_json::to_vec(exit)?;
+        Ok(Self::new(MessageType::VoluntaryExit, data))
+    }
+
     /// Create a ping message
     pub fn ping() -> Self {
         Self::new(MessageType::Ping, vec![])
     }
 
+    /// Content identifier: SHA-256 over the message type and payload.
+    /// The timestamp is excluded so that identical payloads sent at
+    /// different times or by different peers share one ID.
+    pub fn content_id(&self) -> Hash {
+        let tag: u8 = match self.msg_type {
+            MessageType::Block => 0,
+            MessageType::Transaction => 1,
+            MessageType::Ping => 2,
+            MessageType::Attestation => 3,
+            MessageType::AggregateAndProof => 4,
+            MessageType::ProposerSlashing => 5,
+            MessageType::AttesterSlashing => 6,
+            MessageType::VoluntaryExit => 7,
+        };
+        let mut hasher = Sha256::new();
+        hasher.update([tag]);
+        hasher.update(&self.data);
+        hasher.finalize().into()
+    }
+
     /// Get the size of the message in bytes
     pub fn size(&self) -> usize {
         self.data.len() + std::mem::size_of::<MessageType>() + std::mem::size_of::<u64>()
@@ -98,26 +214,153 @@ impl NetworkMessage {
                     return Err("Ping message should be empty".to_string());
                 }
             }
+            MessageType::Attestation
+            | MessageType::AggregateAndProof
+            | MessageType::ProposerSlashing
+            | MessageType::AttesterSlashing
+            | MessageType::VoluntaryExit => {
+                if self.data.is_empty() {
+                    return Err(format!("{:?} message cannot be empty", self.msg_type));
+                }
+            }
         }
 
         Ok(())
     }
 }
 
-/// Message validation result
-#[derive(Debug, Clone)]
+/// JSON layout of a `NetworkMessage`, unchanged from older nodes
+#[derive(Serialize, Deserialize)]
+struct JsonMessage {
+    msg_type: MessageType,
+    data: Vec<u8>,
+    timestamp: u64,
+}
+
+/// Payload of a message in binary formats, encoded in the same format as
+/// the envelope rather than as nested JSON bytes
+#[derive(Serialize, Deserialize)]
+enum BinaryPayload {
+    Block(Block),
+    Transaction(Transaction),
+    Ping,
+    Attestation(Attestation),
+    AggregateAndProof(AggregateAndProof),
+    ProposerSlashing(ProposerSlashing),
+    AttesterSlashing(AttesterSlashing),
+    VoluntaryExit(VoluntaryExit),
+}
+
+/// Layout of a `NetworkMessage` in binary formats
+#[derive(Serialize, Deserialize)]
+struct BinaryMessage {
+    payload: BinaryPayload,
+    timestamp: u64,
+}
+
+impl TryFrom<&NetworkMessage> for BinaryMessage {
+    type Error = serde_json::Error;
+
+    fn try_from(message: &NetworkMessage) -> Result<Self, Self::Error> {
+        let data = &message.data;
+        let payload = match message.msg_type {
+            MessageType::Block => BinaryPayload::Block(serde_json::from_slice(data)?),
+            MessageType::Transaction => BinaryPayload::Transaction(serde_json::from_slice(data)?),
+            MessageType::Ping => BinaryPayload::Ping,
+            MessageType::Attestation => BinaryPayload::Attestation(serde_json::from_slice(data)?),
+            MessageType::AggregateAndProof => BinaryPayload::AggregateAndProof(serde_json::from_slice(data)?),
+            MessageType::ProposerSlashing => BinaryPayload::ProposerSlashing(serde_json::from_slice(data)?),
+            MessageType::AttesterSlashing => BinaryPayload::AttesterSlashing(serde_json::from_slice(data)?),
+            MessageType::VoluntaryExit => BinaryPayload::VoluntaryExit(serde_json::from_slice(data)?),
+        };
+        Ok(BinaryMessage { payload, timestamp: message.timestamp })
+    }
+}
+
+impl TryFrom<BinaryMessage> for NetworkMessage {
+    type Error = serde_json::Error;
+
+    /// Re-encodes the payload as JSON, which gives the same `data` (and so
+    /// the same content ID) as the sender's JSON copy
+    fn try_from(message: BinaryMessage) -> Result<Self, Self::Error> {
+        let (msg_type, data) = match &message.payload {
+            BinaryPayload::Block(block) => (MessageType::Block, serde_json::to_vec(block)?),
+            BinaryPayload::Transaction(transaction) => (MessageType::Transaction, serde_json::to_vec(transaction)?),
+            BinaryPayload::Ping => (MessageType::Ping, Vec::new()),
+            BinaryPayload::Attestation(attestation) => (MessageType::Attestation, serde_json::to_vec(attestation)?),
+            BinaryPayload::AggregateAndProof(aggregate) => (MessageType::AggregateAndProof, serde_json::to_vec(aggregate)?),
+            BinaryPayload::ProposerSlashing(slashing) => (MessageType::ProposerSlashing, serde_json::to_vec(slashing)?),
+            BinaryPayload::AttesterSlashing(slashing) => (MessageType::AttesterSlashing, serde_json::to_vec(slashing)?),
+            BinaryPayload::VoluntaryExit(exit) => (MessageType::VoluntaryExit, serde_json::to_vec(exit)?),
+        };
+        Ok(NetworkMessage { msg_type, data, timestamp: message.timestamp })
+    }
+}
+
+impl Serialize for NetworkMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            JsonMessage {
+                msg_type: self.msg_type,
+                data: self.data.clone(),
+                timestamp: self.timestamp,
+            }
+            .serialize(serializer)
+        } else {
+            BinaryMessage::try_from(self)
+                .map_err(|e| ser::Error::custom(format!("Invalid {:?} payload: {}", self.msg_type, e)))?
+                .serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NetworkMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let message = JsonMessage::deserialize(deserializer)?;
+            Ok(NetworkMessage {
+                msg_type: message.msg_type,
+                data: message.data,
+                timestamp: message.timestamp,
+            })
+        } else {
+            NetworkMessage::try_from(BinaryMessage::deserialize(deserializer)?).map_err(de::Error::custom)
+        }
+    }
+}
+
+/// Deterministic ID for a raw gossip payload. Payloads that do not decode
+/// as a `NetworkMessage` are identified by the hash of their bytes.
+pub fn compute_message_id(data: &[u8]) -> Hash {
+    compute_framed_message_id(data, WireFormat::Json, usize::MAX)
+}
+
+/// Deterministic ID for a gossip payload in the given wire format. The ID
+/// depends only on the content, so it is the same in every format.
+pub fn compute_framed_message_id(data: &[u8], format: WireFormat, max_size: usize) -> Hash {
+    match format.decode::<NetworkMessage>(data, max_size) {
+        Ok(message) => message.content_id(),
+        Err(_) => Sha256::digest(data).into(),
+    }
+}
+
+/// Message validation result, reported back to gossipsub
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValidationResult {
-    /// Message is valid
-    Valid,
-    /// Message is invalid
-    Invalid,
+    /// Message is valid and should be propagated
+    Accept,
+    /// Message is not propagated, but the sender is not penalized
+    /// (duplicates, messages outside the slot window)
+    Ignore(String),
+    /// Message is invalid; it is dropped and the sender penalized
+    Reject(String),
 }
 
 impl From<Result<(), String>> for ValidationResult {
     fn from(result: Result<(), String>) -> Self {
         match result {
-            Ok(()) => ValidationResult::Valid,
-            Err(_) => ValidationResult::Invalid,
+            Ok(()) => ValidationResult::Accept,
+            Err(reason) => ValidationResult::Reject(reason),
         }
     }
 }
\ No newline at end of file
@@ -8,13 +8,14 @@ use libp2p::{
     mdns,
     noise,
     ping,
-    swarm::{NetworkBehaviour, SwarmEvent},
+    request_response::{self, InboundRequestId, OutboundRequestId, ProtocolSupport, ResponseChannel},
+    swarm::{behaviour::toggle::Toggle, dial_opts::DialOpts, NetworkBehaviour, SwarmEvent},
     tcp,
     yamux,
-    Multiaddr, PeerId, Swarm, Transport,
+    Multiaddr, PeerId, StreamProtocol, Swarm, Transport,
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     error::Error,
     time::Duration,
 };
@@ -24,36 +25,82 @@ use tokio::{
 };
 use tracing::{debug, error, info, warn};
 
-use crate::types::{Block, Transaction};
+use crate::metrics::Metrics;
+use crate::types::{
+    AggregateAndProof, Address, Attestation, AttesterSlashing, Block, Checkpoint, CommitteeAssignment, Hash,
+    ProposerSlashing, PublicKey, Transaction, VoluntaryExit,
+};
 
+mod codec;
 mod config;
+mod discovery;
 mod events;
+mod identity;
 mod messages;
 mod peer;
+mod peer_manager;
+mod rate_limit;
+mod stats;
+mod sync_protocol;
+mod validation;
 
-pub use config::NetworkConfig;
+pub use codec::{SyncCodec, WireFormat, FRAME_VERSION, MAX_SYNC_RESPONSE_SIZE, SYNC_PROTOCOL_SNAPPY};
+pub use config::{NetworkConfig, DEFAULT_PORT};
+pub use discovery::{peer_id_from_multiaddr, NodeRecord, SignedNodeRecord};
 pub use events::NetworkEvent;
-pub use messages::{NetworkMessage, MessageType};
+pub use identity::{load_or_create_identity, write_identity, IDENTITY_FILE};
+pub use messages::{
+    attestation_subnet_topic, compute_framed_message_id, compute_message_id, compute_subnet_for_attestation, NetworkMessage, MessageType,
+    ValidationResult,
+};
 pub use peer::{PeerInfo, PeerStatus};
+pub use peer_manager::{
+    BanRecord, PeerAction, PeerManager, StoredPeer, BAN_COUNT_RESET, BAN_FILE, MAX_STORED_PEERS, MIN_REDIAL_REPUTATION,
+    NEUTRAL_REPUTATION, PEER_STORE_FILE, PEER_STORE_SAVE_INTERVAL, REPUTATION_RECOVERY, REPUTATION_RECOVERY_INTERVAL,
+    TARGET_SUBNET_PEERS,
+};
+pub use stats::{NetworkStats, StatsCollector, TrafficCounters, SYNC_STATS_KEY};
+pub use rate_limit::{Quota, RateLimitConfig, RateLimitKey, RateLimiter};
+pub use validation::{GossipValidator, SeenCache, SEEN_CACHE_SIZE, MAXIMUM_GOSSIP_CLOCK_DISPARITY_MS, MAX_GOSSIP_BLOCK_SLOT_AGE};
+pub use sync_protocol::{StatusMessage, SyncRequest, SyncResponse, MAX_BLOCKS_PER_REQUEST, SYNC_PROTOCOL};
+pub use request_response::InboundRequestId as SyncRequestId;
 
 #[derive(NetworkBehaviour)]
 pub struct P2PBehaviour {
     pub gossipsub: gossipsub::Behaviour,
-    pub mdns: mdns::tokio::Behaviour,
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
     pub kademlia: kad::Behaviour<MemoryStore>,
     pub identify: identify::Behaviour,
     pub ping: ping::Behaviour,
+    pub sync: request_response::Behaviour<SyncCodec>,
 }
 
+type SyncResult = Result<SyncResponse, Box<dyn Error + Send + Sync>>;
+
 pub struct NetworkService {
     swarm: Swarm<P2PBehaviour>,
     command_receiver: mpsc::UnboundedReceiver<NetworkCommand>,
     command_sender: mpsc::UnboundedSender<NetworkCommand>,
     event_sender: mpsc::UnboundedSender<NetworkEvent>,
-    peers: HashMap<PeerId, PeerInfo>,
+    peer_manager: PeerManager,
+    validator: GossipValidator,
+    rate_limiter: RateLimiter,
+    stats: StatsCollector,
+    metrics: Metrics,
     config: NetworkConfig,
     local_peer_id: PeerId,
-    topics: HashMap<String, gossipsub::IdentTopic>,
+    local_key: libp2p::identity::Keypair,
+    topics: HashMap<String, Vec<gossipsub::IdentTopic>>,
+    subnet_topics: HashMap<gossipsub::TopicHash, u64>,
+    pending_sync_requests: HashMap<OutboundRequestId, oneshot::Sender<SyncResult>>,
+    pending_sync_responses: HashMap<InboundRequestId, (PeerId, ResponseChannel<SyncResponse>)>,
+    handshake_requests: HashMap<OutboundRequestId, PeerId>,
+    local_status: StatusMessage,
+    random_walks: HashSet<kad::QueryId>,
+    record_lookups: HashMap<kad::QueryId, (PeerId, Vec<Multiaddr>)>,
+    node_record_seq: u64,
+    /// Set by a shutdown command, answered once the run loop has stopped
+    shutdown: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Debug)]
@@ -74,6 +121,31 @@ pub enum NetworkCommand {
         transaction: Transaction,
         response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
     },
+    BroadcastAttestation {
+        attestation: Attestation,
+        subnet_id: u64,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    BroadcastAggregateAndProof {
+        aggregate: AggregateAndProof,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    BroadcastProposerSlashing {
+        slashing: ProposerSlashing,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    BroadcastAttesterSlashing {
+        slashing: AttesterSlashing,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    BroadcastVoluntaryExit {
+        exit: VoluntaryExit,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    UpdateSubnetSubscriptions {
+        assignments: Vec<CommitteeAssignment>,
+        response: oneshot::Sender<Result<Vec<u64>, Box<dyn Error + Send + Sync>>>,
+    },
     GetPeers {
         response: oneshot::Sender<Vec<PeerInfo>>,
     },
@@ -81,6 +153,58 @@ pub enum NetworkCommand {
         topic: String,
         response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
     },
+    SendSyncRequest {
+        peer_id: PeerId,
+        request: SyncRequest,
+        response: oneshot::Sender<SyncResult>,
+    },
+    SendSyncResponse {
+        request_id: InboundRequestId,
+        sync_response: SyncResponse,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    UpdateValidatorKeys {
+        keys: Vec<(u64, Address, PublicKey)>,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    ReportPeer {
+        peer_id: PeerId,
+        action: PeerAction,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    UpdateChainStatus {
+        head_hash: Hash,
+        head_height: u64,
+        finalized_checkpoint: Checkpoint,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    Disconnect {
+        peer_id: PeerId,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    BanPeer {
+        peer_id: PeerId,
+        reason: String,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    UnbanPeer {
+        peer_id: PeerId,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    Unsubscribe {
+        topic: String,
+        response: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    GetNetworkStats {
+        response: oneshot::Sender<NetworkStats>,
+    },
+    GetListenAddrs {
+        response: oneshot::Sender<Vec<Multiaddr>>,
+    },
+    /// Stop the service; answered once the peer store is saved
+    Shutdown {
+        response: oneshot::Sender<()>,
+    },
 }
 
 pub struct NetworkHandle {
@@ -88,10 +212,17 @@ pub struct NetworkHandle {
     event_receiver: mpsc::UnboundedReceiver<NetworkEvent>,
 }
 
+/// Cloneable handle for the sync requests and peer reports of a sync
+/// running in its own task
+#[derive(Clone)]
+pub struct SyncClient {
+    command_sender: mpsc::UnboundedSender<NetworkCommand>,
+}
+
 impl NetworkService {
     pub fn new(config: NetworkConfig) -> Result<(Self, NetworkHandle), Box<dyn Error + Send + Sync>> {
-        // Create identity keypair
-        let local_key = libp2p::identity::Keypair::generate_ed25519();
+        // Reuse the identity saved in the data directory so the PeerId is stable
+        let local_key = load_or_create_identity(config.data_dir.as_deref())?;
         let local_peer_id = PeerId::from(local_key.public());
 
         info!("Local peer id: {}", local_peer_id);
@@ -108,19 +239,25 @@ impl NetworkService {
         let dns_transport = dns::tokio::Transport::system(transport)?;
 
         // Set up gossipsub
-        let message_id_fn = |message: &gossipsub::Message| {
-            use std::hash::{Hash, Hasher};
-            let mut s = std::collections::hash_map::DefaultHasher::new();
-            message.data.hash(&mut s);
-            if let Some(source) = &message.source {
-                source.to_bytes().hash(&mut s);
-            }
-            MessageId::from(s.finish().to_string())
+        // Message IDs depend only on the payload, so the same block or
+        // transaction relayed by different peers is deduplicated. Binary
+        // topics get a prefix so publishing one message in every format
+        // is not mistaken for a duplicate by gossipsub.
+        let max_message_size = config.max_message_size;
+        let message_id_fn = move |message: &gossipsub::Message| {
+            let format = WireFormat::from_topic(message.topic.as_str());
+            let id = hex::encode(compute_framed_message_id(&message.data, format, max_message_size));
+            match format {
+                WireFormat::Json => MessageId::from(id),
+                WireFormat::SnappyBincode => MessageId::from(format!("snappy:{}", id)),
+            }
         };
 
         let gossipsub_config = gossipsub::ConfigBuilder::default()
-            .heartbeat_interval(Duration::from_secs(10))
+            .heartbeat_interval(config.heartbeat_interval)
+            .max_transmit_size(config.max_message_size)
             .validation_mode(ValidationMode::Strict)
+            .validate_messages()
             .message_id_fn(message_id_fn)
             .build()
             .map_err(|e| format!("Invalid gossipsub config: {}", e))?;
@@ -130,28 +267,48 @@ impl NetworkService {
             gossipsub_config,
         ).map_err(|e| format!("Failed to create gossipsub behaviour: {}", e))?;
 
-        // Set up mDNS for local peer discovery
-        let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?;
+        // Set up mDNS for local peer discovery when enabled
+        let mdns = if config.enable_mdns {
+            Some(mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?)
+        } else {
+            None
+        };
 
-        // Set up Kademlia DHT
+        // Set up Kademlia DHT, separate per network
         let store = MemoryStore::new(local_peer_id);
-        let kademlia = kad::Behaviour::new(local_peer_id, store);
+        let kad_protocol = StreamProtocol::try_from_owned(config.kad_protocol())
+            .map_err(|e| format!("Invalid Kademlia protocol: {}", e))?;
+        let mut kademlia = kad::Behaviour::with_config(local_peer_id, store, kad::Config::new(kad_protocol));
+        kademlia.set_mode(Some(kad::Mode::Server));
 
         // Set up identify protocol
         let identify = identify::Behaviour::new(identify::Config::new(
-            "/republic-chain/1.0.0".to_string(),
+            config.protocol_version(),
             local_key.public(),
         ));
 
         // Set up ping protocol
         let ping = ping::Behaviour::new(ping::Config::new().with_interval(Duration::from_secs(15)));
 
+        // Set up block sync request/response protocol
+        // Protocols are offered in order of preference, so peers without
+        // binary framing fall back to JSON
+        let stats = StatsCollector::new();
+        let sync = request_response::Behaviour::with_codec(
+            SyncCodec::new(config.max_message_size).with_stats(stats.sync_counters()),
+            SyncCodec::protocols(&config.wire_formats)
+                .into_iter()
+                .map(|protocol| (protocol, ProtocolSupport::Full)),
+            request_response::Config::default().with_request_timeout(config.connection_timeout),
+        );
+
         let behaviour = P2PBehaviour {
             gossipsub,
-            mdns,
+            mdns: mdns.into(),
             kademlia,
             identify,
             ping,
+            sync,
         };
 
         let swarm = Swarm::new(dns_transport.boxed(), behaviour, local_peer_id, libp2p::swarm::Config::with_tokio_executor());
@@ -164,20 +321,45 @@ impl NetworkService {
             event_receiver,
         };
 
+        let peer_manager = PeerManager::new(config.data_dir.as_deref())?;
+        let validator = GossipValidator::new(&config);
+        let rate_limiter = RateLimiter::new(config.rate_limits.clone());
+        let local_status = config.local_status([0u8; 32], 0, Checkpoint { epoch: 0, root: [0u8; 32] });
+
         let service = NetworkService {
             swarm,
             command_receiver,
             command_sender,
             event_sender,
-            peers: HashMap::new(),
+            peer_manager,
+            validator,
+            rate_limiter,
+            stats,
+            metrics: Metrics::default(),
             config,
             local_peer_id,
+            local_key,
             topics: HashMap::new(),
+            subnet_topics: HashMap::new(),
+            pending_sync_requests: HashMap::new(),
+            pending_sync_responses: HashMap::new(),
+            handshake_requests: HashMap::new(),
+            local_status,
+            random_walks: HashSet::new(),
+            record_lookups: HashMap::new(),
+            node_record_seq: 0,
+            shutdown: None,
         };
 
         Ok((service, handle))
     }
 
+    /// Record gossip and peer metrics into `metrics`
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
     /// Get the local peer ID
     pub fn local_peer_id(&self) -> PeerId {
         self.local_peer_id
@@ -190,30 +372,68 @@ impl NetworkService {
 
     pub async fn run(mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
         // Subscribe to default topics
-        self.subscribe_to_topic("blocks").await?;
-        self.subscribe_to_topic("transactions").await?;
+        for topic in self.config.default_topics.clone() {
+            self.subscribe_to_topic(&topic).await?;
+        }
 
-        // Start listening on default address
-        let listen_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", self.config.port)
-            .parse()
-            .map_err(|e| format!("Invalid listen address: {}", e))?;
+        let listen_addr = self.config.listen_multiaddr()?;
 
         self.swarm.listen_on(listen_addr.clone())?;
         info!("Started listening on {}", listen_addr);
 
-        // Connect to bootstrap peers
+        // Redial known good peers first; bootstrap nodes are the fallback
+        for (peer_id, addresses) in self.peer_manager.dial_candidates(self.config.max_connections as usize) {
+            debug!("Redialing known peer {}", peer_id);
+            for addr in &addresses {
+                self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+            }
+            let opts = DialOpts::peer_id(peer_id).addresses(addresses).build();
+            if let Err(e) = self.swarm.dial(opts) {
+                debug!("Failed to redial known peer {}: {}", peer_id, e);
+            }
+        }
+
+        // Connect to bootstrap peers and seed the DHT with those that name
+        // their peer id
         for peer_addr in &self.config.bootstrap_peers {
+            if let Some(peer_id) = peer_id_from_multiaddr(peer_addr) {
+                self.swarm.behaviour_mut().kademlia.add_address(&peer_id, peer_addr.clone());
+            }
             if let Err(e) = self.swarm.dial(peer_addr.clone()) {
                 warn!("Failed to dial bootstrap peer {}: {}", peer_addr, e);
             }
         }
+        if let Err(e) = self.swarm.behaviour_mut().kademlia.bootstrap() {
+            debug!("Skipping DHT bootstrap: {}", e);
+        }
+        self.publish_node_record();
 
+        let mut discovery = tokio::time::interval(self.config.discovery_interval);
+        let mut peer_store = tokio::time::interval(PEER_STORE_SAVE_INTERVAL);
+        let mut reputation_recovery = tokio::time::interval(REPUTATION_RECOVERY_INTERVAL);
         loop {
             select! {
+                _ = discovery.tick() => {
+                    self.discover_peers();
+                    self.update_peer_metrics();
+                }
+                _ = peer_store.tick() => {
+                    self.peer_manager.save_peers_if_dirty();
+                }
+                _ = reputation_recovery.tick() => {
+                    self.peer_manager.recover_reputation();
+                }
                 event = self.swarm.select_next_some() => {
+                    let peers_changed = matches!(
+                        event,
+                        SwarmEvent::ConnectionEstablished { .. } | SwarmEvent::ConnectionClosed { .. }
+                    );
                     if let Err(e) = self.handle_swarm_event(event).await {
                         error!("Error handling swarm event: {}", e);
                     }
+                    if peers_changed {
+                        self.update_peer_metrics();
+                    }
                 }
                 command = self.command_receiver.recv() => {
                     match command {
@@ -221,6 +441,9 @@ impl NetworkService {
                             if let Err(e) = self.handle_command(cmd).await {
                                 error!("Error handling command: {}", e);
                             }
+                            if self.shutdown.is_some() {
+                                break;
+                            }
                         }
                         None => {
                             info!("Command channel closed, shutting down network service");
@@ -231,6 +454,10 @@ impl NetworkService {
             }
         }
 
+        self.peer_manager.save_peers();
+        if let Some(response) = self.shutdown.take() {
+            let _ = response.send(());
+        }
         Ok(())
     }
 
@@ -243,14 +470,47 @@ impl NetworkService {
                 info!("Listening on {}", address);
                 let _ = self.event_sender.send(NetworkEvent::ListeningStarted { address });
             }
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } => {
+                if num_established.get() > 1 {
+                    return Ok(());
+                }
+                // Only addresses we dialed are known to accept connections
+                if endpoint.is_dialer() {
+                    self.peer_manager.add_address(peer_id, endpoint.get_remote_address().clone());
+                }
+                self.stats.on_connection();
+                if !self.peer_manager.on_connected_from(peer_id, endpoint.get_remote_address()) {
+                    debug!("Disconnecting banned peer {}", peer_id);
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                    return Ok(());
+                }
                 info!("Connected to peer: {}", peer_id);
-                self.peers.insert(peer_id, PeerInfo::new(peer_id, PeerStatus::Connected));
+
+                // Keep within the connection limit by dropping the lowest-scoring peers
+                for pruned in self.peer_manager.peers_to_prune(self.config.max_connections as usize) {
+                    info!("Pruning peer {} to stay within connection limit", pruned);
+                    if let Some(peer_info) = self.peer_manager.get_mut(&pruned) {
+                        peer_info.reject("Connection limit reached".to_string());
+                    }
+                    let _ = self.swarm.disconnect_peer_id(pruned);
+                }
+                if self.peer_manager.get(&peer_id).is_some_and(|peer| peer.status != PeerStatus::Connected) {
+                    return Ok(());
+                }
+
+                // Exchange status to make sure the peer is on our network and fork
+                let request_id = self.send_sync_request(peer_id, SyncRequest::Status(self.local_status.clone()));
+                self.handshake_requests.insert(request_id, peer_id);
+
                 let _ = self.event_sender.send(NetworkEvent::PeerConnected { peer_id });
             }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed { peer_id, num_established, .. } => {
+                if num_established > 0 {
+                    return Ok(());
+                }
                 info!("Disconnected from peer: {}", peer_id);
-                self.peers.remove(&peer_id);
+                self.peer_manager.on_disconnected(&peer_id);
+                self.rate_limiter.remove_peer(&peer_id);
                 let _ = self.event_sender.send(NetworkEvent::PeerDisconnected { peer_id });
             }
             SwarmEvent::Behaviour(P2PBehaviourEvent::Gossipsub(gossipsub::Event::Message {
@@ -259,11 +519,12 @@ impl NetworkService {
                 message,
             })) => {
                 debug!("Received gossipsub message from {}: {:?}", peer_id, id);
-                self.handle_gossip_message(peer_id, message).await?;
+                self.handle_gossip_message(peer_id, id, message).await?;
             }
             SwarmEvent::Behaviour(P2PBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
                 for (peer_id, multiaddr) in list {
                     info!("Discovered peer via mDNS: {} at {}", peer_id, multiaddr);
+                    self.peer_manager.add_address(peer_id, multiaddr.clone());
                     self.swarm.behaviour_mut().kademlia.add_address(&peer_id, multiaddr);
                 }
             }
@@ -278,7 +539,20 @@ impl NetworkService {
                 ..
             })) => {
                 debug!("Received identify info from {}: {:?}", peer_id, info);
+                self.peer_manager.update_protocol_info(
+                    &peer_id,
+                    info.protocol_version.clone(),
+                    info.agent_version.clone(),
+                );
+                if info.protocol_version != self.config.protocol_version() {
+                    self.reject_peer(
+                        peer_id,
+                        format!("Protocol version mismatch: {}", info.protocol_version),
+                    );
+                    return Ok(());
+                }
                 for addr in info.listen_addrs {
+                    self.peer_manager.add_address(peer_id, addr.clone());
                     self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
                 }
             }
@@ -288,10 +562,25 @@ impl NetworkService {
                 ..
             })) => {
                 debug!("Ping to {} succeeded with RTT: {:?}", peer, rtt);
-                if let Some(peer_info) = self.peers.get_mut(&peer) {
+                if let Some(peer_info) = self.peer_manager.get_mut(&peer) {
                     peer_info.update_rtt(rtt);
                 }
             }
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } => {
+                debug!("Failed to connect to {}: {}", peer_id, error);
+                self.stats.on_connection_failed();
+                self.peer_manager.on_dial_failure(&peer_id);
+            }
+            SwarmEvent::Behaviour(P2PBehaviourEvent::Sync(event)) => {
+                self.handle_sync_event(event);
+            }
+            SwarmEvent::Behaviour(P2PBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result,
+                ..
+            })) => {
+                self.handle_kad_query(id, result);
+            }
             _ => {}
         }
         Ok(())
@@ -318,101 +607,618 @@ impl NetworkService {
                 let result = self.broadcast_transaction(&transaction).await;
                 let _ = response.send(result);
             }
+            NetworkCommand::BroadcastAttestation { attestation, subnet_id, response } => {
+                let result = NetworkMessage::attestation(&attestation)
+                    .map_err(|e| e.into())
+                    .and_then(|message| self.publish(&attestation_subnet_topic(subnet_id), message));
+                let _ = response.send(result);
+            }
+            NetworkCommand::BroadcastAggregateAndProof { aggregate, response } => {
+                let result = NetworkMessage::aggregate_and_proof(&aggregate)
+                    .map_err(|e| e.into())
+                    .and_then(|message| self.publish(MessageType::AggregateAndProof.topic(), message));
+                let _ = response.send(result);
+            }
+            NetworkCommand::BroadcastProposerSlashing { slashing, response } => {
+                let result = NetworkMessage::proposer_slashing(&slashing)
+                    .map_err(|e| e.into())
+                    .and_then(|message| self.publish(MessageType::ProposerSlashing.topic(), message));
+                let _ = response.send(result);
+            }
+            NetworkCommand::BroadcastAttesterSlashing { slashing, response } => {
+                let result = NetworkMessage::attester_slashing(&slashing)
+                    .map_err(|e| e.into())
+                    .and_then(|message| self.publish(MessageType::AttesterSlashing.topic(), message));
+                let _ = response.send(result);
+            }
+            NetworkCommand::BroadcastVoluntaryExit { exit, response } => {
+                let result = NetworkMessage::voluntary_exit(&exit)
+                    .map_err(|e| e.into())
+                    .and_then(|message| self.publish(MessageType::VoluntaryExit.topic(), message));
+                let _ = response.send(result);
+            }
+            NetworkCommand::UpdateSubnetSubscriptions { assignments, response } => {
+                let result = self.update_subnet_subscriptions(&assignments).await;
+                let _ = response.send(result);
+            }
             NetworkCommand::GetPeers { response } => {
-                let peers = self.peers.values().cloned().collect();
+                let peers = self.peer_manager.peers().cloned().collect();
                 let _ = response.send(peers);
             }
             NetworkCommand::Subscribe { topic, response } => {
                 let result = self.subscribe_to_topic(&topic).await;
                 let _ = response.send(result);
             }
+            NetworkCommand::SendSyncRequest { peer_id, request, response } => {
+                let request_id = self.send_sync_request(peer_id, request);
+                self.pending_sync_requests.insert(request_id, response);
+            }
+            NetworkCommand::UpdateValidatorKeys { keys, response } => {
+                self.validator.set_validator_keys(keys);
+                let _ = response.send(Ok(()));
+            }
+            NetworkCommand::ReportPeer { peer_id, action, response } => {
+                self.report_peer(peer_id, action);
+                let _ = response.send(Ok(()));
+            }
+            NetworkCommand::UpdateChainStatus { head_hash, head_height, finalized_checkpoint, response } => {
+                self.local_status = self.config.local_status(head_hash, head_height, finalized_checkpoint);
+                let _ = response.send(Ok(()));
+            }
+            NetworkCommand::SendSyncResponse { request_id, sync_response, response } => {
+                let result = match self.pending_sync_responses.remove(&request_id) {
+                    Some((peer_id, channel)) => self
+                        .send_sync_response(peer_id, channel, sync_response)
+                        .map_err(|_| "Sync response channel closed".into()),
+                    None => Err("Unknown sync request".into()),
+                };
+                let _ = response.send(result);
+            }
+            NetworkCommand::Disconnect { peer_id, response } => {
+                let result = self.swarm
+                    .disconnect_peer_id(peer_id)
+                    .map_err(|_| format!("Not connected to peer {}", peer_id).into());
+                if result.is_ok() {
+                    if let Some(peer_info) = self.peer_manager.get_mut(&peer_id) {
+                        peer_info.reject("Disconnected by operator".to_string());
+                    }
+                }
+                let _ = response.send(result);
+            }
+            NetworkCommand::BanPeer { peer_id, reason, response } => {
+                self.peer_manager.ban(peer_id, reason.clone());
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+                let _ = self.event_sender.send(NetworkEvent::PeerBanned { peer_id, reason });
+                let _ = response.send(Ok(()));
+            }
+            NetworkCommand::UnbanPeer { peer_id, response } => {
+                let result = if self.peer_manager.ban_record(&peer_id).is_some() {
+                    self.peer_manager.unban(&peer_id);
+                    Ok(())
+                } else {
+                    Err(format!("Peer {} is not banned", peer_id).into())
+                };
+                let _ = response.send(result);
+            }
+            NetworkCommand::Unsubscribe { topic, response } => {
+                let result = self.unsubscribe_from_topic(&topic);
+                let _ = response.send(result);
+            }
+            NetworkCommand::GetNetworkStats { response } => {
+                let stats = self.stats.snapshot(self.peer_manager.connected_count(), self.topics.len());
+                let _ = response.send(stats);
+            }
+            NetworkCommand::GetListenAddrs { response } => {
+                let _ = response.send(self.swarm.listeners().cloned().collect());
+            }
+            NetworkCommand::Shutdown { response } => {
+                info!("Shutting down network service");
+                self.shutdown = Some(response);
+            }
         }
         Ok(())
     }
 
+    /// Size of a sync message on the wire in the preferred format. The
+    /// codec counts exact bytes, but does not know which peer they are for.
+    fn sync_message_size<T: serde::Serialize>(&self, message: &T) -> usize {
+        self.config.preferred_wire_format().encode(message).map_or(0, |data| data.len())
+    }
+
+    fn send_sync_request(&mut self, peer_id: PeerId, request: SyncRequest) -> OutboundRequestId {
+        self.stats.record_sync_out(peer_id, self.sync_message_size(&request));
+        self.swarm.behaviour_mut().sync.send_request(&peer_id, request)
+    }
+
+    fn send_sync_response(
+        &mut self,
+        peer_id: PeerId,
+        channel: ResponseChannel<SyncResponse>,
+        response: SyncResponse,
+    ) -> Result<(), SyncResponse> {
+        self.stats.record_sync_out(peer_id, self.sync_message_size(&response));
+        self.swarm.behaviour_mut().sync.send_response(channel, response)
+    }
+
+    fn handle_sync_event(&mut self, event: request_response::Event<SyncRequest, SyncResponse>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { request_id, request, channel },
+            } => {
+                debug!("Received sync request from {}: {:?}", peer, request);
+                self.stats.record_sync_in(peer, self.sync_message_size(&request));
+                let (key, cost) = RateLimitKey::for_request(&request);
+                if let Err(retry_after) = self.rate_limiter.check(peer, key, cost) {
+                    debug!("Sync request from {} exceeds its {:?} quota", peer, key);
+                    let retry_after_ms = retry_after.as_millis().min(u64::MAX as u128) as u64;
+                    let _ = self.send_sync_response(peer, channel, SyncResponse::RateLimited { retry_after_ms });
+                    self.report_peer(peer, PeerAction::RateLimited);
+                    return;
+                }
+                if let SyncRequest::Status(status) = request {
+                    let _ = self.send_sync_response(peer, channel, SyncResponse::Status(self.local_status.clone()));
+                    self.handle_peer_status(peer, status);
+                    return;
+                }
+                if let Err(reason) = request.validate() {
+                    let _ = self.send_sync_response(peer, channel, SyncResponse::Error(reason));
+                    return;
+                }
+                self.pending_sync_responses.insert(request_id, (peer, channel));
+                let _ = self.event_sender.send(NetworkEvent::SyncRequestReceived {
+                    peer_id: peer,
+                    request_id,
+                    request,
+                });
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { request_id, response },
+            } => {
+                self.stats.record_sync_in(peer, self.sync_message_size(&response));
+                if self.handshake_requests.remove(&request_id).is_some() {
+                    match response {
+                        SyncResponse::Status(status) => self.handle_peer_status(peer, status),
+                        _ => self.reject_peer(peer, "Invalid status handshake response".to_string()),
+                    }
+                    return;
+                }
+                if let Some(sender) = self.pending_sync_requests.remove(&request_id) {
+                    let _ = sender.send(Ok(response));
+                }
+            }
+            request_response::Event::OutboundFailure { peer, request_id, error } => {
+                warn!("Sync request to {} failed: {}", peer, error);
+                if matches!(error, request_response::OutboundFailure::Timeout) {
+                    self.report_peer(peer, PeerAction::Timeout);
+                }
+                if self.handshake_requests.remove(&request_id).is_some() {
+                    self.reject_peer(peer, format!("Status handshake failed: {}", error));
+                    return;
+                }
+                if let Some(sender) = self.pending_sync_requests.remove(&request_id) {
+                    let _ = sender.send(Err(error.to_string().into()));
+                }
+            }
+            request_response::Event::InboundFailure { peer, request_id, error } => {
+                debug!("Inbound sync request from {} failed: {}", peer, error);
+                self.pending_sync_responses.remove(&request_id);
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    fn handle_peer_status(&mut self, peer_id: PeerId, status: StatusMessage) {
+        if let Err(reason) = self.local_status.check_compatible(&status) {
+            self.reject_peer(peer_id, reason);
+            return;
+        }
+
+        if let Some(peer_info) = self.peer_manager.get_mut(&peer_id) {
+            peer_info.chain_status = Some(status.clone());
+        }
+        let _ = self.event_sender.send(NetworkEvent::PeerStatusReceived { peer_id, status });
+    }
+
+    fn reject_peer(&mut self, peer_id: PeerId, reason: String) {
+        warn!("Disconnecting peer {}: {}", peer_id, reason);
+        if let Some(peer_info) = self.peer_manager.get_mut(&peer_id) {
+            peer_info.reject(reason.clone());
+        }
+        let _ = self.swarm.disconnect_peer_id(peer_id);
+        let _ = self.event_sender.send(NetworkEvent::PeerRejected { peer_id, reason });
+    }
+
+    fn report_peer(&mut self, peer_id: PeerId, action: PeerAction) {
+        debug!("Peer {} reported for {:?}", peer_id, action);
+        if self.peer_manager.report(&peer_id, action) {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+            let reason = self.peer_manager
+                .ban_record(&peer_id)
+                .map(|ban| ban.reason.clone())
+                .unwrap_or_default();
+            let _ = self.event_sender.send(NetworkEvent::PeerBanned { peer_id, reason });
+        }
+    }
+
     async fn handle_gossip_message(
         &mut self,
         peer_id: PeerId,
+        message_id: MessageId,
         message: gossipsub::Message,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let _topic_str = message.topic.to_string();
-
-        match serde_json::from_slice::<NetworkMessage>(&message.data) {
-            Ok(network_msg) => {
-                debug!("Received valid network message from {}: {:?}", peer_id, network_msg.msg_type);
-
-                let event = match network_msg.msg_type {
-                    MessageType::Block => {
-                        if let Ok(block) = serde_json::from_slice::<Block>(&network_msg.data) {
-                            NetworkEvent::BlockReceived { block, from: peer_id }
-                        } else {
-                            warn!("Failed to deserialize block from {}", peer_id);
-                            return Ok(());
-                        }
-                    }
-                    MessageType::Transaction => {
-                        if let Ok(transaction) = serde_json::from_slice::<Transaction>(&network_msg.data) {
-                            NetworkEvent::TransactionReceived { transaction, from: peer_id }
-                        } else {
-                            warn!("Failed to deserialize transaction from {}", peer_id);
-                            return Ok(());
-                        }
-                    }
-                    MessageType::Ping => {
-                        NetworkEvent::PingReceived { from: peer_id }
-                    }
-                };
+        let topic_name = self.short_topic_name(&message.topic);
+        self.stats.record_gossip_in(&topic_name, peer_id, message.data.len());
 
-                let _ = self.event_sender.send(event);
+        // Drop floods before spending any work on decoding
+        if let Some(msg_type) = MessageType::from_topic(message.topic.as_str()) {
+            if self.rate_limiter.check(peer_id, RateLimitKey::Gossip(msg_type), 1).is_err() {
+                debug!("Gossip from {} exceeds its {:?} quota", peer_id, msg_type);
+                self.metrics.record_gossip(&topic_name, "rate_limited");
+                self.swarm.behaviour_mut().gossipsub
+                    .report_message_validation_result(&message_id, &peer_id, gossipsub::MessageAcceptance::Ignore)?;
+                self.report_peer(peer_id, PeerAction::RateLimited);
+                return Ok(());
             }
-            Err(e) => {
-                warn!("Failed to deserialize network message from {}: {}", peer_id, e);
+        }
+
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let subnet_id = self.subnet_topics.get(&message.topic).copied();
+        let format = WireFormat::from_topic(message.topic.as_str());
+        let (result, event) = self.validator.validate_framed(peer_id, subnet_id, format, &message.data, now_ms);
+
+        self.metrics.record_gossip(&topic_name, match &result {
+            ValidationResult::Accept => "accept",
+            ValidationResult::Ignore(_) => "ignore",
+            ValidationResult::Reject(_) => "reject",
+        });
+        let acceptance = match &result {
+            ValidationResult::Accept => gossipsub::MessageAcceptance::Accept,
+            ValidationResult::Ignore(reason) => {
+                debug!("Ignoring gossip message {} from {}: {}", message_id, peer_id, reason);
+                gossipsub::MessageAcceptance::Ignore
+            }
+            ValidationResult::Reject(reason) => {
+                warn!("Rejecting gossip message {} from {}: {}", message_id, peer_id, reason);
+                gossipsub::MessageAcceptance::Reject
             }
+        };
+        self.swarm.behaviour_mut().gossipsub
+            .report_message_validation_result(&message_id, &peer_id, acceptance)?;
+
+        if let ValidationResult::Reject(reason) = result {
+            self.report_peer(peer_id, PeerAction::InvalidGossip);
+            let _ = self.event_sender.send(NetworkEvent::MessageValidationFailed { from: peer_id, reason });
+            return Ok(());
+        }
+
+        if let Some(event) = event {
+            debug!("Received valid gossip message from {}: {}", peer_id, event.description());
+            let _ = self.event_sender.send(event);
         }
 
         Ok(())
     }
 
+    /// Subscribe to a topic in every enabled wire format
     async fn subscribe_to_topic(&mut self, topic_name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let topic = gossipsub::IdentTopic::new(topic_name);
-        self.swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
-        self.topics.insert(topic_name.to_string(), topic);
+        let mut topics = Vec::new();
+        for format in &self.config.wire_formats {
+            let topic = gossipsub::IdentTopic::new(self.config.format_topic_name(topic_name, *format));
+            self.swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+            topics.push(topic);
+        }
+        self.topics.insert(topic_name.to_string(), topics);
         info!("Subscribed to topic: {}", topic_name);
+        let _ = self.event_sender.send(NetworkEvent::TopicSubscribed { topic: topic_name.to_string() });
+        Ok(())
+    }
+
+    /// Leave a topic in every wire format
+    fn unsubscribe_from_topic(&mut self, topic_name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let topics = self.topics
+            .remove(topic_name)
+            .ok_or_else(|| format!("Not subscribed to topic {}", topic_name))?;
+        for topic in &topics {
+            self.swarm.behaviour_mut().gossipsub.unsubscribe(topic)?;
+            self.subnet_topics.remove(&topic.hash());
+        }
+        info!("Unsubscribed from topic: {}", topic_name);
+        let _ = self.event_sender.send(NetworkEvent::TopicUnsubscribed { topic: topic_name.to_string() });
         Ok(())
     }
 
+    /// Short topic name (e.g. `blocks`) for a subscribed topic hash
+    fn short_topic_name(&self, hash: &gossipsub::TopicHash) -> String {
+        self.topics
+            .iter()
+            .find(|(_, topics)| topics.iter().any(|topic| topic.hash() == *hash))
+            .map(|(name, _)| name.clone())
+            .unwrap_or_else(|| hash.to_string())
+    }
+
     async fn broadcast_block(&mut self, block: &Block) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let message = NetworkMessage {
-            msg_type: MessageType::Block,
-            data: serde_json::to_vec(block)?,
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-        };
+        self.publish(MessageType::Block.topic(), NetworkMessage::block(block)?)?;
+        info!("Broadcasted block with height: {}", block.header.height);
+        Ok(())
+    }
 
-        let serialized = serde_json::to_vec(&message)?;
+    async fn broadcast_transaction(&mut self, transaction: &Transaction) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.publish(MessageType::Transaction.topic(), NetworkMessage::transaction(transaction)?)?;
+        info!("Broadcasted transaction: {:?}", transaction.hash());
+        Ok(())
+    }
+
+    /// Publish a message on a topic in the preferred wire format.
+    /// Subscribing is not required; gossipsub forwards to fanout peers of
+    /// topics we are not subscribed to.
+    fn publish(&mut self, topic_name: &str, message: NetworkMessage) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.validator.mark_published(&message);
 
-        if let Some(topic) = self.topics.get("blocks") {
-            self.swarm.behaviour_mut().gossipsub.publish(topic.clone(), serialized)?;
-            info!("Broadcasted block with height: {}", block.header.height);
+        let format = self.config.preferred_wire_format();
+        let serialized = format.encode(&message)?;
+        if serialized.len() > self.config.max_message_size {
+            return Err(format!("Message of {} bytes exceeds limit", serialized.len()).into());
         }
 
+        let topic = gossipsub::IdentTopic::new(self.config.format_topic_name(topic_name, format));
+        let topic_hash = topic.hash();
+        let size = serialized.len();
+        self.swarm.behaviour_mut().gossipsub.publish(topic, serialized)?;
+
+        // Gossipsub sends to the topic's mesh, or to the peers subscribed to
+        // it when we are not in the mesh ourselves
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let mut recipients: Vec<PeerId> = gossipsub.mesh_peers(&topic_hash).copied().collect();
+        if recipients.is_empty() {
+            recipients = gossipsub
+                .all_peers()
+                .filter(|(_, topics)| topics.contains(&&topic_hash))
+                .map(|(peer_id, _)| *peer_id)
+                .collect();
+        }
+        self.stats.record_gossip_out(topic_name, &recipients, size);
+        debug!("Published {:?} message on {} as {:?}", message.msg_type, topic_name, format);
         Ok(())
     }
 
-    async fn broadcast_transaction(&mut self, transaction: &Transaction) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let message = NetworkMessage {
-            msg_type: MessageType::Transaction,
-            data: serde_json::to_vec(transaction)?,
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+    fn update_peer_metrics(&self) {
+        let statuses = [
+            PeerStatus::Connected,
+            PeerStatus::Connecting,
+            PeerStatus::Disconnected,
+            PeerStatus::Failed,
+            PeerStatus::Banned,
+        ];
+        for status in statuses {
+            let count = self.peer_manager.peers().filter(|peer| peer.status == status).count();
+            self.metrics.set_peers(&format!("{:?}", status).to_lowercase(), count);
+        }
+    }
+
+    /// One discovery round: when below the target peer count, redial known
+    /// peers and start a random walk through the DHT for new ones
+    fn discover_peers(&mut self) {
+        self.dial_subnet_peers();
+
+        let connected = self.peer_manager.connected_count();
+        let target = self.config.target_peers.min(self.config.max_connections) as usize;
+        if connected >= target {
+            return;
+        }
+
+        for (peer_id, addresses) in self.peer_manager.dial_candidates(target - connected) {
+            self.dial_peer(peer_id, addresses);
+        }
+
+        debug!("{} of {} target peers connected, starting random walk", connected, target);
+        let query_id = self.swarm.behaviour_mut().kademlia.get_closest_peers(PeerId::random());
+        self.random_walks.insert(query_id);
+    }
+
+    fn handle_kad_query(&mut self, id: kad::QueryId, result: kad::QueryResult) {
+        match result {
+            kad::QueryResult::GetClosestPeers(result) => {
+                if !self.random_walks.remove(&id) {
+                    return;
+                }
+                let peers = match result {
+                    Ok(ok) => ok.peers,
+                    Err(kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+                };
+                for peer in peers {
+                    self.on_peer_discovered(peer.peer_id, peer.addrs);
+                }
+            }
+            kad::QueryResult::GetRecord(result) => {
+                let Some((peer_id, addresses)) = self.record_lookups.remove(&id) else {
+                    return;
+                };
+                match result {
+                    Ok(kad::GetRecordOk::FoundRecord(found)) => {
+                        match SignedNodeRecord::decode_and_verify(&found.record.value, &peer_id)
+                            .and_then(|record| record.check_compatible(&self.config).map(|_| record))
+                        {
+                            Ok(record) => {
+                                self.peer_manager.set_node_record(&peer_id, record);
+                                self.dial_peer(peer_id, addresses);
+                            }
+                            Err(reason) => debug!("Not dialing discovered peer {}: {}", peer_id, reason),
+                        }
+                    }
+                    // Records are optional; the status handshake still
+                    // checks the peer's network and fork
+                    _ => self.dial_peer(peer_id, addresses),
+                }
+            }
+            kad::QueryResult::PutRecord(Err(e)) => {
+                debug!("Failed to publish node record: {}", e);
+            }
+            kad::QueryResult::Bootstrap(Err(e)) => {
+                debug!("DHT bootstrap failed: {}", e);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_peer_discovered(&mut self, peer_id: PeerId, addresses: Vec<Multiaddr>) {
+        if peer_id == self.local_peer_id
+            || addresses.is_empty()
+            || self.peer_manager.is_banned(&peer_id)
+            || addresses.iter().all(|addr| self.peer_manager.is_address_banned(addr))
+            || self.swarm.is_connected(&peer_id)
+        {
+            return;
+        }
+        for addr in &addresses {
+            self.peer_manager.add_address(peer_id, addr.clone());
+        }
+
+        if self.config.advertise_node_record {
+            let key = NodeRecord::dht_key(self.config.network_id, &peer_id);
+            let query_id = self.swarm.behaviour_mut().kademlia.get_record(key);
+            self.record_lookups.insert(query_id, (peer_id, addresses));
+        } else {
+            self.dial_peer(peer_id, addresses);
+        }
+    }
+
+    /// Dial a peer unless we already have enough connections
+    fn dial_peer(&mut self, peer_id: PeerId, addresses: Vec<Multiaddr>) {
+        if self.peer_manager.connected_count() >= self.config.target_peers as usize
+            || self.swarm.is_connected(&peer_id)
+        {
+            return;
+        }
+        debug!("Dialing discovered peer {}", peer_id);
+        let opts = DialOpts::peer_id(peer_id).addresses(addresses).build();
+        if let Err(e) = self.swarm.dial(opts) {
+            debug!("Failed to dial {}: {}", peer_id, e);
+        }
+    }
+
+    /// Dial known peers serving our attestation subnets until each has
+    /// `TARGET_SUBNET_PEERS` connected peers, going past the target peer
+    /// count but not `max_connections`
+    fn dial_subnet_peers(&mut self) {
+        let mut subnets: Vec<u64> = self.subnet_topics.values().copied().collect();
+        subnets.sort_unstable();
+        subnets.dedup();
+
+        for subnet_id in subnets {
+            let connected = self.peer_manager.peers_on_subnet(subnet_id).len();
+            if connected >= TARGET_SUBNET_PEERS {
+                continue;
+            }
+            for (peer_id, addresses) in self.peer_manager.subnet_dial_candidates(subnet_id, TARGET_SUBNET_PEERS - connected) {
+                if self.peer_manager.connected_count() >= self.config.max_connections as usize {
+                    return;
+                }
+                debug!("Dialing {} for attestation subnet {}", peer_id, subnet_id);
+                let opts = DialOpts::peer_id(peer_id).addresses(addresses).build();
+                if let Err(e) = self.swarm.dial(opts) {
+                    debug!("Failed to dial {}: {}", peer_id, e);
+                }
+            }
+        }
+    }
+
+    /// Publish our signed node record with the current subnets to the DHT
+    fn publish_node_record(&mut self) {
+        if !self.config.advertise_node_record {
+            return;
+        }
+
+        self.node_record_seq += 1;
+        let mut attestation_subnets: Vec<u64> = self.subnet_topics.values().copied().collect();
+        attestation_subnets.sort_unstable();
+        attestation_subnets.dedup();
+        let record = NodeRecord {
+            network_id: self.config.network_id,
+            fork_digest: self.config.fork_digest(),
+            attestation_subnets,
+            seq: self.node_record_seq,
+        };
+
+        let value = match record.sign(&self.local_key).and_then(|signed| signed.encode()) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to build node record: {}", e);
+                return;
+            }
         };
+        let key = NodeRecord::dht_key(self.config.network_id, &self.local_peer_id);
+        if let Err(e) = self.swarm.behaviour_mut().kademlia.put_record(kad::Record::new(key, value), kad::Quorum::One) {
+            warn!("Failed to store node record: {}", e);
+        }
+    }
 
-        let serialized = serde_json::to_vec(&message)?;
+    /// Subscribe to exactly the attestation subnets needed for the given
+    /// committee assignments
+    async fn update_subnet_subscriptions(
+        &mut self,
+        assignments: &[CommitteeAssignment],
+    ) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+        let subnet_count = self.config.attestation_subnet_count;
+        let mut wanted: Vec<u64> = assignments
+            .iter()
+            .map(|assignment| compute_subnet_for_attestation(assignment.slot, assignment.committee_index, subnet_count))
+            .collect();
+        wanted.sort_unstable();
+        wanted.dedup();
 
-        if let Some(topic) = self.topics.get("transactions") {
-            self.swarm.behaviour_mut().gossipsub.publish(topic.clone(), serialized)?;
-            info!("Broadcasted transaction: {:?}", transaction.hash());
+        let stale: Vec<(gossipsub::TopicHash, u64)> = self.subnet_topics
+            .iter()
+            .filter(|(_, subnet_id)| !wanted.contains(subnet_id))
+            .map(|(hash, subnet_id)| (hash.clone(), *subnet_id))
+            .collect();
+        for (hash, subnet_id) in stale {
+            let name = attestation_subnet_topic(subnet_id);
+            if self.topics.contains_key(&name) {
+                self.unsubscribe_from_topic(&name)?;
+            }
+            self.subnet_topics.remove(&hash);
         }
 
-        Ok(())
+        for subnet_id in &wanted {
+            let name = attestation_subnet_topic(*subnet_id);
+            if !self.topics.contains_key(&name) {
+                self.subscribe_to_topic(&name).await?;
+            }
+            for topic in self.topics.get(&name).into_iter().flatten() {
+                self.subnet_topics.insert(topic.hash(), *subnet_id);
+            }
+        }
+
+        self.publish_node_record();
+        self.dial_subnet_peers();
+        Ok(wanted)
+    }
+}
+
+impl SyncClient {
+    /// Send a sync request to a peer and wait for its response
+    pub async fn send_sync_request(&self, peer_id: PeerId, request: SyncRequest) -> SyncResult {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::SendSyncRequest {
+            peer_id,
+            request,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    /// Penalize a peer for misbehaviour, banning it if its reputation
+    /// drops too low
+    pub async fn report_peer(&self, peer_id: PeerId, action: PeerAction) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::ReportPeer {
+            peer_id,
+            action,
+            response: tx,
+        })?;
+        rx.await?
     }
 }
 
@@ -453,6 +1259,72 @@ impl NetworkHandle {
         rx.await?
     }
 
+    /// Publish an attestation on its subnet topic
+    pub async fn broadcast_attestation(
+        &self,
+        attestation: Attestation,
+        subnet_id: u64,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::BroadcastAttestation {
+            attestation,
+            subnet_id,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    pub async fn broadcast_aggregate_and_proof(&self, aggregate: AggregateAndProof) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::BroadcastAggregateAndProof {
+            aggregate,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    pub async fn broadcast_proposer_slashing(&self, slashing: ProposerSlashing) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::BroadcastProposerSlashing {
+            slashing,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    pub async fn broadcast_attester_slashing(&self, slashing: AttesterSlashing) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::BroadcastAttesterSlashing {
+            slashing,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    pub async fn broadcast_voluntary_exit(&self, exit: VoluntaryExit) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::BroadcastVoluntaryExit {
+            exit,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    /// Subscribe to the attestation subnets for the given committee
+    /// assignments, leaving subnets that are no longer needed. Returns the
+    /// subscribed subnet ids.
+    pub async fn update_subnet_subscriptions(
+        &self,
+        assignments: Vec<CommitteeAssignment>,
+    ) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::UpdateSubnetSubscriptions {
+            assignments,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
     pub async fn get_peers(&self) -> Result<Vec<PeerInfo>, Box<dyn Error + Send + Sync>> {
         let (tx, rx) = oneshot::channel();
         self.command_sender.send(NetworkCommand::GetPeers {
@@ -470,6 +1342,131 @@ impl NetworkHandle {
         rx.await?
     }
 
+    /// Send a sync request to a peer and wait for its response
+    pub async fn send_sync_request(&self, peer_id: PeerId, request: SyncRequest) -> SyncResult {
+        self.sync_client().send_sync_request(peer_id, request).await
+    }
+
+    pub fn sync_client(&self) -> SyncClient {
+        SyncClient {
+            command_sender: self.command_sender.clone(),
+        }
+    }
+
+    /// Answer a sync request received via `NetworkEvent::SyncRequestReceived`
+    pub async fn send_sync_response(
+        &self,
+        request_id: InboundRequestId,
+        sync_response: SyncResponse,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::SendSyncResponse {
+            request_id,
+            sync_response,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    /// Set the validator keys used to check signatures on gossip, as
+    /// `(index, address, public key)`
+    pub async fn update_validator_keys(&self, keys: Vec<(u64, Address, PublicKey)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::UpdateValidatorKeys {
+            keys,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    /// Penalize a peer for misbehaviour, banning it if its reputation
+    /// drops too low
+    pub async fn report_peer(&self, peer_id: PeerId, action: PeerAction) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.sync_client().report_peer(peer_id, action).await
+    }
+
+    /// Update the chain head advertised in status handshakes
+    pub async fn update_chain_status(
+        &self,
+        head_hash: Hash,
+        head_height: u64,
+        finalized_checkpoint: Checkpoint,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::UpdateChainStatus {
+            head_hash,
+            head_height,
+            finalized_checkpoint,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    /// Close all connections to a peer
+    pub async fn disconnect_peer(&self, peer_id: PeerId) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::Disconnect {
+            peer_id,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    /// Ban a peer and disconnect it
+    pub async fn ban_peer(&self, peer_id: PeerId, reason: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::BanPeer {
+            peer_id,
+            reason,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    pub async fn unban_peer(&self, peer_id: PeerId) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::UnbanPeer {
+            peer_id,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    pub async fn unsubscribe_from_topic(&self, topic: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::Unsubscribe {
+            topic,
+            response: tx,
+        })?;
+        rx.await?
+    }
+
+    /// Traffic and connection statistics
+    pub async fn get_network_stats(&self) -> Result<NetworkStats, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::GetNetworkStats {
+            response: tx,
+        })?;
+        Ok(rx.await?)
+    }
+
+    pub async fn get_listen_addrs(&self) -> Result<Vec<Multiaddr>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::GetListenAddrs {
+            response: tx,
+        })?;
+        Ok(rx.await?)
+    }
+
+    /// Stop the network service, waiting until it has saved its peer store
+    pub async fn shutdown(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(NetworkCommand::Shutdown {
+            response: tx,
+        })?;
+        Ok(rx.await?)
+    }
+
     pub async fn next_event(&mut self) -> Option<NetworkEvent> {
         self.event_receiver.recv().await
     }
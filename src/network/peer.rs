@@ -1,6 +1,7 @@
 use libp2p::{Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
+use super::{NodeRecord, StatusMessage};
 
 /// Status of a peer connection
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -46,6 +47,12 @@ pub struct PeerInfo {
     pub reputation: u8,
     /// Last time reputation was updated
     pub last_reputation_update: Option<Instant>,
+    /// Chain status from the handshake
+    pub chain_status: Option<StatusMessage>,
+    /// Why the peer was disconnected by us, if it was
+    pub disconnect_reason: Option<String>,
+    /// Verified node record from the DHT
+    pub node_record: Option<NodeRecord>,
 }
 
 impl PeerInfo {
@@ -66,6 +73,9 @@ impl PeerInfo {
             agent_version: None,
             reputation: 50, // Start with neutral reputation
             last_reputation_update: Some(Instant::now()),
+            chain_status: None,
+            disconnect_reason: None,
+            node_record: None,
         }
     }
 
@@ -98,6 +108,12 @@ impl PeerInfo {
         self.status = status;
     }
 
+    /// Mark the peer as disconnected by us and record why
+    pub fn reject(&mut self, reason: String) {
+        self.disconnect_reason = Some(reason);
+        self.set_status(PeerStatus::Disconnected);
+    }
+
     /// Update round-trip time
     pub fn update_rtt(&mut self, rtt: Duration) {
         self.latest_rtt = Some(rtt);
@@ -149,7 +165,7 @@ impl PeerInfo {
 
     /// Check if peer should be prioritized for connections
     pub fn is_priority(&self) -> bool {
-        self.is_reliable() && self.avg_rtt.map_or(false, |rtt| rtt < Duration::from_millis(100))
+        self.is_reliable() && self.avg_rtt.is_some_and(|rtt| rtt < Duration::from_millis(100))
     }
 
     /// Get connection success rate
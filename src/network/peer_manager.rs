@@ -0,0 +1,540 @@
+use libp2p::{Multiaddr, PeerId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use libp2p::multiaddr::Protocol;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::{NodeRecord, PeerInfo, PeerStatus};
+
+/// File under the data directory holding active bans
+pub const BAN_FILE: &str = "banned_peers.json";
+
+/// File under the data directory holding known peers
+pub const PEER_STORE_FILE: &str = "peers.json";
+
+/// Number of peers kept in the peer store, best scores first
+pub const MAX_STORED_PEERS: usize = 1000;
+
+/// Peers at or below this reputation are not redialed on startup
+pub const MIN_REDIAL_REPUTATION: u8 = 30;
+
+/// Ban duration for a first offence; doubles with every repeat ban
+pub const BASE_BAN_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// Upper bound on ban duration
+pub const MAX_BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A ban that ended this long ago no longer counts towards the next one
+pub const BAN_COUNT_RESET: Duration = MAX_BAN_DURATION;
+
+/// Reputation peers start with and recover towards
+pub const NEUTRAL_REPUTATION: u8 = 50;
+
+/// How often penalised peers regain reputation
+pub const REPUTATION_RECOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Reputation points regained every `REPUTATION_RECOVERY_INTERVAL`
+pub const REPUTATION_RECOVERY: u8 = 5;
+
+/// How often the network service writes a changed peer store
+pub const PEER_STORE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Connected peers wanted on each subscribed attestation subnet
+pub const TARGET_SUBNET_PEERS: usize = 2;
+
+/// Misbehaviour reported against a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerAction {
+    /// Gossip message that failed to decode or validate
+    InvalidGossip,
+    /// Sync response with wrong, unlinked or invalid blocks
+    BadSyncResponse,
+    /// Request that was not answered in time
+    Timeout,
+    /// Messages or requests beyond the peer's quota
+    RateLimited,
+}
+
+impl PeerAction {
+    /// Reputation points removed for this action
+    pub fn penalty(&self) -> u8 {
+        match self {
+            PeerAction::InvalidGossip => 10,
+            PeerAction::BadSyncResponse => 20,
+            PeerAction::Timeout => 5,
+            PeerAction::RateLimited => 5,
+        }
+    }
+}
+
+/// An active or expired ban
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanRecord {
+    /// Unix time in milliseconds when the ban expires
+    pub until: u64,
+    /// Number of times the peer has been banned
+    pub ban_count: u32,
+    /// Why the peer was last banned
+    pub reason: String,
+    /// Remote IPs the peer connected from, banned along with the PeerId so
+    /// a fresh identity does not escape the ban
+    #[serde(default)]
+    pub ips: Vec<IpAddr>,
+}
+
+/// A known peer as kept in the peer store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredPeer {
+    pub addresses: Vec<Multiaddr>,
+    pub first_seen: Option<u64>,
+    pub last_seen: Option<u64>,
+    pub connection_count: u32,
+    pub failed_connections: u32,
+    pub reputation: u8,
+    pub protocol_version: Option<String>,
+    pub agent_version: Option<String>,
+}
+
+impl From<&PeerInfo> for StoredPeer {
+    fn from(peer: &PeerInfo) -> Self {
+        Self {
+            addresses: peer.addresses.clone(),
+            first_seen: peer.first_seen,
+            last_seen: peer.last_seen,
+            connection_count: peer.connection_count,
+            failed_connections: peer.failed_connections,
+            reputation: peer.reputation,
+            protocol_version: peer.protocol_version.clone(),
+            agent_version: peer.agent_version.clone(),
+        }
+    }
+}
+
+impl StoredPeer {
+    fn into_peer_info(self, peer_id: PeerId) -> PeerInfo {
+        let mut peer = PeerInfo::new(peer_id, PeerStatus::Disconnected);
+        peer.addresses = self.addresses;
+        peer.first_seen = self.first_seen;
+        peer.last_seen = self.last_seen;
+        peer.connection_count = self.connection_count;
+        peer.failed_connections = self.failed_connections;
+        peer.reputation = self.reputation;
+        peer.protocol_version = self.protocol_version;
+        peer.agent_version = self.agent_version;
+        peer
+    }
+}
+
+/// Tracks peer reputation, bans and connection limits
+#[derive(Debug, Default)]
+pub struct PeerManager {
+    peers: HashMap<PeerId, PeerInfo>,
+    bans: HashMap<PeerId, BanRecord>,
+    ban_file: Option<PathBuf>,
+    peer_file: Option<PathBuf>,
+    /// Remote IP of each connected peer
+    remote_ips: HashMap<PeerId, IpAddr>,
+    /// Peers changed since the peer store was last written
+    peers_dirty: bool,
+}
+
+impl PeerManager {
+    /// Create a peer manager, loading known peers and bans from `data_dir`
+    /// if given
+    pub fn new(data_dir: Option<&Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut manager = PeerManager {
+            ban_file: data_dir.map(|dir| dir.join(BAN_FILE)),
+            peer_file: data_dir.map(|dir| dir.join(PEER_STORE_FILE)),
+            ..Default::default()
+        };
+
+        if let Some(path) = &manager.peer_file {
+            if let Some(stored) = load_json::<HashMap<String, StoredPeer>>(path) {
+                for (peer_id, record) in stored {
+                    match peer_id.parse::<PeerId>() {
+                        Ok(peer_id) => {
+                            manager.peers.insert(peer_id, record.into_peer_info(peer_id));
+                        }
+                        Err(e) => warn!("Ignoring invalid stored peer id {}: {}", peer_id, e),
+                    }
+                }
+                info!("Loaded {} known peers from {}", manager.peers.len(), path.display());
+            }
+        }
+
+        if let Some(path) = &manager.ban_file {
+            if let Some(stored) = load_json::<HashMap<String, BanRecord>>(path) {
+                for (peer_id, record) in stored {
+                    match peer_id.parse::<PeerId>() {
+                        Ok(peer_id) => {
+                            manager.bans.insert(peer_id, record);
+                        }
+                        Err(e) => warn!("Ignoring invalid banned peer id {}: {}", peer_id, e),
+                    }
+                }
+                info!("Loaded {} peer bans from {}", manager.bans.len(), path.display());
+            }
+        }
+
+        // Peers banned before the restart keep their banned status
+        let now = now_millis();
+        for (peer_id, ban) in &manager.bans {
+            if ban.until > now {
+                if let Some(peer) = manager.peers.get_mut(peer_id) {
+                    peer.status = PeerStatus::Banned;
+                    peer.reputation = 0;
+                }
+            }
+        }
+
+        Ok(manager)
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&PeerInfo> {
+        self.peers.get(peer_id)
+    }
+
+    pub fn get_mut(&mut self, peer_id: &PeerId) -> Option<&mut PeerInfo> {
+        self.peers_dirty = true;
+        self.peers.get_mut(peer_id)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerInfo> {
+        self.peers.values()
+    }
+
+    pub fn connected_count(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|peer| peer.status == PeerStatus::Connected)
+            .count()
+    }
+
+    /// Check whether a peer is currently banned
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.bans
+            .get(peer_id)
+            .is_some_and(|ban| ban.until > now_millis())
+    }
+
+    pub fn ban_record(&self, peer_id: &PeerId) -> Option<&BanRecord> {
+        self.bans.get(peer_id)
+    }
+
+    /// Check whether an address is on an IP banned together with a peer.
+    /// Loopback addresses are never banned, since local nodes share them.
+    pub fn is_address_banned(&self, addr: &Multiaddr) -> bool {
+        let Some(ip) = bannable_ip(addr) else {
+            return false;
+        };
+        let now = now_millis();
+        self.bans.values().any(|ban| ban.until > now && ban.ips.contains(&ip))
+    }
+
+    /// Record a new connection from `remote`. Returns false if the peer or
+    /// its IP is banned and the connection must be dropped.
+    pub fn on_connected_from(&mut self, peer_id: PeerId, remote: &Multiaddr) -> bool {
+        if self.is_address_banned(remote) {
+            return false;
+        }
+        if let Some(ip) = bannable_ip(remote) {
+            self.remote_ips.insert(peer_id, ip);
+        }
+        self.on_connected(peer_id)
+    }
+
+    /// Record a new connection. Returns false if the peer is banned and must
+    /// be disconnected.
+    pub fn on_connected(&mut self, peer_id: PeerId) -> bool {
+        if self.is_banned(&peer_id) {
+            return false;
+        }
+
+        let peer = self.peers
+            .entry(peer_id)
+            .or_insert_with(|| PeerInfo::new(peer_id, PeerStatus::Connecting));
+        if peer.status == PeerStatus::Banned {
+            // Ban has expired; start over with a fresh reputation
+            *peer = PeerInfo::new(peer_id, PeerStatus::Connecting);
+        }
+        peer.disconnect_reason = None;
+        peer.set_status(PeerStatus::Connected);
+        self.peers_dirty = true;
+        true
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        self.remote_ips.remove(peer_id);
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            if peer.status == PeerStatus::Connected || peer.status == PeerStatus::Connecting {
+                peer.set_status(PeerStatus::Disconnected);
+            }
+        }
+        self.peers_dirty = true;
+    }
+
+    /// Record a failed dial attempt
+    pub fn on_dial_failure(&mut self, peer_id: &PeerId) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            if peer.status != PeerStatus::Banned && peer.status != PeerStatus::Connected {
+                peer.set_status(PeerStatus::Failed);
+            }
+        }
+        self.peers_dirty = true;
+    }
+
+    /// Remember a dialable address for a peer
+    pub fn add_address(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        self.peers
+            .entry(peer_id)
+            .or_insert_with(|| PeerInfo::new(peer_id, PeerStatus::Disconnected))
+            .add_address(addr);
+        self.peers_dirty = true;
+    }
+
+    /// Record the protocol and agent version a peer reported via identify
+    pub fn update_protocol_info(&mut self, peer_id: &PeerId, protocol_version: String, agent_version: String) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.update_protocol_info(protocol_version, agent_version);
+        }
+        self.peers_dirty = true;
+    }
+
+    /// Attach a verified node record, keeping the newest one
+    pub fn set_node_record(&mut self, peer_id: &PeerId, record: NodeRecord) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            if peer.node_record.as_ref().is_none_or(|current| current.seq < record.seq) {
+                peer.node_record = Some(record);
+            }
+        }
+    }
+
+    /// Connected peers whose node record lists the given attestation subnet
+    pub fn peers_on_subnet(&self, subnet_id: u64) -> Vec<PeerId> {
+        self.peers
+            .values()
+            .filter(|peer| peer.status == PeerStatus::Connected)
+            .filter(|peer| peer.node_record.as_ref().is_some_and(|record| record.serves_subnet(subnet_id)))
+            .map(|peer| peer.peer_id)
+            .collect()
+    }
+
+    /// Known, unbanned, disconnected peers worth redialing, best first
+    pub fn dial_candidates(&self, limit: usize) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        self.redial_candidates(limit, |_| true)
+    }
+
+    /// Dial candidates whose node record lists the given attestation
+    /// subnet, for subnets with fewer than `TARGET_SUBNET_PEERS` in
+    /// `peers_on_subnet`
+    pub fn subnet_dial_candidates(&self, subnet_id: u64, limit: usize) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        self.redial_candidates(limit, |peer| {
+            peer.node_record.as_ref().is_some_and(|record| record.serves_subnet(subnet_id))
+        })
+    }
+
+    fn redial_candidates(&self, limit: usize, filter: impl Fn(&PeerInfo) -> bool) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let mut candidates: Vec<&PeerInfo> = self.peers
+            .values()
+            .filter(|peer| !peer.addresses.is_empty())
+            .filter(|peer| peer.status != PeerStatus::Connected && peer.status != PeerStatus::Connecting)
+            .filter(|peer| peer.reputation > MIN_REDIAL_REPUTATION && !self.is_banned(&peer.peer_id))
+            .filter(|peer| filter(peer))
+            .collect();
+
+        candidates.sort_by_key(|peer| std::cmp::Reverse(peer.score()));
+        candidates
+            .into_iter()
+            .take(limit)
+            .map(|peer| (peer.peer_id, peer.addresses.clone()))
+            .collect()
+    }
+
+    /// Apply a penalty for misbehaviour. Returns true if the peer is now
+    /// banned and must be disconnected.
+    pub fn report(&mut self, peer_id: &PeerId, action: PeerAction) -> bool {
+        if self.is_banned(peer_id) {
+            return false;
+        }
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            return false;
+        };
+
+        peer.decrease_reputation(action.penalty());
+        self.peers_dirty = true;
+        if peer.status != PeerStatus::Banned {
+            return false;
+        }
+
+        self.ban(*peer_id, format!("Reputation dropped below threshold after {:?}", action));
+        true
+    }
+
+    /// Move penalised peers back towards `NEUTRAL_REPUTATION`, so one-off
+    /// penalties wear off instead of adding up to a ban. Called every
+    /// `REPUTATION_RECOVERY_INTERVAL`.
+    pub fn recover_reputation(&mut self) {
+        for peer in self.peers.values_mut() {
+            if peer.status != PeerStatus::Banned && peer.reputation < NEUTRAL_REPUTATION {
+                peer.reputation = peer.reputation.saturating_add(REPUTATION_RECOVERY).min(NEUTRAL_REPUTATION);
+                self.peers_dirty = true;
+            }
+        }
+    }
+
+    /// Ban a peer, and the IPs it connected from, for a duration that
+    /// doubles with every repeat offence. Offences from before the last ban
+    /// ended more than `BAN_COUNT_RESET` ago are forgotten.
+    pub fn ban(&mut self, peer_id: PeerId, reason: String) {
+        let now = now_millis();
+        let previous = self.bans.get(&peer_id)
+            .filter(|ban| ban.until + BAN_COUNT_RESET.as_millis() as u64 > now);
+        let ban_count = previous.map_or(0, |ban| ban.ban_count) + 1;
+        let mut ips = previous.map(|ban| ban.ips.clone()).unwrap_or_default();
+        if let Some(ip) = self.remote_ips.get(&peer_id) {
+            if !ips.contains(ip) {
+                ips.push(*ip);
+            }
+        }
+        let duration = BASE_BAN_DURATION
+            .saturating_mul(1 << (ban_count - 1).min(16))
+            .min(MAX_BAN_DURATION);
+
+        warn!("Banning peer {} for {:?}: {}", peer_id, duration, reason);
+        if let Some(peer) = self.peers.get_mut(&peer_id) {
+            peer.disconnect_reason = Some(reason.clone());
+            peer.set_status(PeerStatus::Banned);
+        }
+        self.bans.insert(peer_id, BanRecord {
+            until: now + duration.as_millis() as u64,
+            ban_count,
+            reason,
+            ips,
+        });
+        self.peers_dirty = true;
+        self.save_bans();
+    }
+
+    /// Lift a ban and reset the peer's reputation
+    pub fn unban(&mut self, peer_id: &PeerId) {
+        if self.bans.remove(peer_id).is_some() {
+            if let Some(peer) = self.peers.get_mut(peer_id) {
+                let addresses = std::mem::take(&mut peer.addresses);
+                *peer = PeerInfo::new(*peer_id, PeerStatus::Disconnected);
+                peer.addresses = addresses;
+            }
+            self.peers_dirty = true;
+            self.save_bans();
+        }
+    }
+
+    /// Connected peers to drop so that at most `max_connections` remain,
+    /// lowest score first
+    pub fn peers_to_prune(&self, max_connections: usize) -> Vec<PeerId> {
+        let mut connected: Vec<&PeerInfo> = self.peers
+            .values()
+            .filter(|peer| peer.status == PeerStatus::Connected)
+            .collect();
+        if connected.len() <= max_connections {
+            return Vec::new();
+        }
+
+        connected.sort_by_key(|peer| peer.score());
+        connected
+            .iter()
+            .take(connected.len() - max_connections)
+            .map(|peer| peer.peer_id)
+            .collect()
+    }
+
+    /// Write the peer store if peers changed since it was last written
+    pub fn save_peers_if_dirty(&mut self) {
+        if self.peers_dirty {
+            self.save_peers();
+        }
+    }
+
+    /// Write known peers with addresses to the peer store
+    pub fn save_peers(&mut self) {
+        self.peers_dirty = false;
+        let Some(path) = &self.peer_file else {
+            return;
+        };
+
+        let mut known: Vec<&PeerInfo> = self.peers
+            .values()
+            .filter(|peer| !peer.addresses.is_empty())
+            .collect();
+        known.sort_by_key(|peer| std::cmp::Reverse(peer.score()));
+        let stored: HashMap<String, StoredPeer> = known
+            .into_iter()
+            .take(MAX_STORED_PEERS)
+            .map(|peer| (peer.peer_id.to_string(), StoredPeer::from(peer)))
+            .collect();
+        if let Err(e) = write_json(path, &stored) {
+            warn!("Failed to persist peers to {}: {}", path.display(), e);
+        }
+    }
+
+    fn save_bans(&self) {
+        let Some(path) = &self.ban_file else {
+            return;
+        };
+
+        let stored: HashMap<String, &BanRecord> = self.bans
+            .iter()
+            .map(|(peer_id, record)| (peer_id.to_string(), record))
+            .collect();
+        if let Err(e) = write_json(path, &stored) {
+            warn!("Failed to persist peer bans to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Write a JSON file through a temporary file and a rename, so a crash
+/// never leaves it half-written
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, content).map_err(|e| e.to_string())?;
+    std::fs::rename(&temp, path).map_err(|e| e.to_string())
+}
+
+/// Read a JSON file, or None if it is missing. An unreadable or corrupt file
+/// is logged and treated as missing, so the node starts with an empty list.
+fn load_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    if !path.exists() {
+        return None;
+    }
+    let result = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()));
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Ignoring unreadable {}, starting empty: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// The IP of an address, unless it is missing or loopback
+fn bannable_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    let ip = addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })?;
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
@@ -0,0 +1,149 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::{MessageType, SyncRequest};
+
+/// Token bucket parameters: up to `burst` units at once, refilled at
+/// `per_second` units per second
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    pub burst: u32,
+    pub per_second: u32,
+}
+
+impl Quota {
+    pub const fn new(burst: u32, per_second: u32) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+/// Per-peer quotas for each gossip message type and sync protocol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Gossip blocks
+    pub blocks: Quota,
+    /// Gossip transactions
+    pub transactions: Quota,
+    /// Gossip attestations, across all subnets
+    pub attestations: Quota,
+    /// Gossip aggregates
+    pub aggregates: Quota,
+    /// Slashings, voluntary exits and pings
+    pub other_gossip: Quota,
+    /// Status requests
+    pub sync_status: Quota,
+    /// Blocks requested by range or root; each block costs one token
+    pub sync_blocks: Quota,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            blocks: Quota::new(16, 4),
+            transactions: Quota::new(256, 128),
+            attestations: Quota::new(1024, 512),
+            aggregates: Quota::new(128, 64),
+            other_gossip: Quota::new(32, 8),
+            sync_status: Quota::new(5, 1),
+            sync_blocks: Quota::new(512, 128),
+        }
+    }
+}
+
+/// What a rate limit applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    Gossip(MessageType),
+    SyncStatus,
+    SyncBlocks,
+}
+
+impl RateLimitKey {
+    /// Key and token cost of an inbound sync request
+    pub fn for_request(request: &SyncRequest) -> (Self, u32) {
+        match request {
+            SyncRequest::Status(_) => (RateLimitKey::SyncStatus, 1),
+            SyncRequest::BlocksByRange { count, .. } => (RateLimitKey::SyncBlocks, (*count).min(u32::MAX as u64) as u32),
+            SyncRequest::BlocksByRoot { hashes } => (RateLimitKey::SyncBlocks, hashes.len().min(u32::MAX as usize) as u32),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per peer and rate limit key
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<(PeerId, RateLimitKey), TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn quota(&self, key: RateLimitKey) -> Quota {
+        match key {
+            RateLimitKey::Gossip(MessageType::Block) => self.config.blocks,
+            RateLimitKey::Gossip(MessageType::Transaction) => self.config.transactions,
+            RateLimitKey::Gossip(MessageType::Attestation) => self.config.attestations,
+            RateLimitKey::Gossip(MessageType::AggregateAndProof) => self.config.aggregates,
+            RateLimitKey::Gossip(_) => self.config.other_gossip,
+            RateLimitKey::SyncStatus => self.config.sync_status,
+            RateLimitKey::SyncBlocks => self.config.sync_blocks,
+        }
+    }
+
+    /// Take `cost` tokens from the peer's bucket. On failure returns how
+    /// long until enough tokens are available.
+    pub fn check(&mut self, peer_id: PeerId, key: RateLimitKey, cost: u32) -> Result<(), Duration> {
+        self.check_at(peer_id, key, cost, Instant::now())
+    }
+
+    pub fn check_at(&mut self, peer_id: PeerId, key: RateLimitKey, cost: u32, now: Instant) -> Result<(), Duration> {
+        let quota = self.quota(key);
+        let cost = cost as f64;
+        if cost > quota.burst as f64 {
+            // Can never be satisfied; wait for a full bucket and ask for less
+            return Err(Self::refill_time(quota, quota.burst as f64));
+        }
+
+        let bucket = self.buckets
+            .entry((peer_id, key))
+            .or_insert(TokenBucket { tokens: quota.burst as f64, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * quota.per_second as f64).min(quota.burst as f64);
+        bucket.updated = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Self::refill_time(quota, cost - bucket.tokens))
+        }
+    }
+
+    /// Forget all buckets of a disconnected peer
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.buckets.retain(|(peer, _), _| peer != peer_id);
+    }
+
+    fn refill_time(quota: Quota, tokens: f64) -> Duration {
+        if quota.per_second == 0 {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64(tokens / quota.per_second as f64)
+    }
+}
@@ -0,0 +1,146 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Key under which sync protocol traffic is reported in `per_topic`
+pub const SYNC_STATS_KEY: &str = "sync";
+
+/// Message and byte counts in each direction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficCounters {
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl TrafficCounters {
+    pub fn record_in(&mut self, bytes: usize) {
+        self.messages_in += 1;
+        self.bytes_in += bytes as u64;
+    }
+
+    pub fn record_out(&mut self, bytes: usize) {
+        self.messages_out += 1;
+        self.bytes_out += bytes as u64;
+    }
+}
+
+/// Snapshot of network statistics
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetworkStats {
+    /// Seconds since the service started
+    pub uptime: u64,
+    /// Connections established since start
+    pub total_connections: u64,
+    /// Currently connected peers
+    pub current_connections: usize,
+    /// Failed outgoing connection attempts since start
+    pub failed_connections: u64,
+    /// Subscribed gossip topics
+    pub topics_subscribed: usize,
+    /// Traffic across all topics and protocols
+    pub total: TrafficCounters,
+    /// Traffic per gossip topic, plus sync traffic under `SYNC_STATS_KEY`
+    pub per_topic: HashMap<String, TrafficCounters>,
+    /// Traffic per peer. Received gossip counts the peer that forwarded the
+    /// message and published gossip every peer it was sent to. Sync
+    /// messages are counted at their size in the preferred wire format.
+    pub per_peer: HashMap<String, TrafficCounters>,
+}
+
+/// Collects traffic counters inside the network service
+#[derive(Debug)]
+pub struct StatsCollector {
+    started: Instant,
+    total_connections: u64,
+    failed_connections: u64,
+    per_topic: HashMap<String, TrafficCounters>,
+    per_peer: HashMap<PeerId, TrafficCounters>,
+    sync: Arc<Mutex<TrafficCounters>>,
+}
+
+impl Default for StatsCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            total_connections: 0,
+            failed_connections: 0,
+            per_topic: HashMap::new(),
+            per_peer: HashMap::new(),
+            sync: Arc::new(Mutex::new(TrafficCounters::default())),
+        }
+    }
+
+    /// Counters shared with the sync codec, which sees the wire bytes
+    pub fn sync_counters(&self) -> Arc<Mutex<TrafficCounters>> {
+        self.sync.clone()
+    }
+
+    pub fn on_connection(&mut self) {
+        self.total_connections += 1;
+    }
+
+    pub fn on_connection_failed(&mut self) {
+        self.failed_connections += 1;
+    }
+
+    pub fn record_gossip_in(&mut self, topic: &str, peer_id: PeerId, bytes: usize) {
+        self.per_topic.entry(topic.to_string()).or_default().record_in(bytes);
+        self.per_peer.entry(peer_id).or_default().record_in(bytes);
+    }
+
+    /// A message published once on `topic` and sent to each of `peers`
+    pub fn record_gossip_out(&mut self, topic: &str, peers: &[PeerId], bytes: usize) {
+        self.per_topic.entry(topic.to_string()).or_default().record_out(bytes);
+        for peer_id in peers {
+            self.per_peer.entry(*peer_id).or_default().record_out(bytes);
+        }
+    }
+
+    pub fn record_sync_in(&mut self, peer_id: PeerId, bytes: usize) {
+        self.per_peer.entry(peer_id).or_default().record_in(bytes);
+    }
+
+    pub fn record_sync_out(&mut self, peer_id: PeerId, bytes: usize) {
+        self.per_peer.entry(peer_id).or_default().record_out(bytes);
+    }
+
+    pub fn snapshot(&self, current_connections: usize, topics_subscribed: usize) -> NetworkStats {
+        let mut per_topic = self.per_topic.clone();
+        let sync = *self.sync.lock().unwrap_or_else(|e| e.into_inner());
+        if sync != TrafficCounters::default() {
+            per_topic.insert(SYNC_STATS_KEY.to_string(), sync);
+        }
+
+        let total = per_topic.values().fold(TrafficCounters::default(), |mut total, counters| {
+            total.messages_in += counters.messages_in;
+            total.messages_out += counters.messages_out;
+            total.bytes_in += counters.bytes_in;
+            total.bytes_out += counters.bytes_out;
+            total
+        });
+
+        NetworkStats {
+            uptime: self.started.elapsed().as_secs(),
+            total_connections: self.total_connections,
+            current_connections,
+            failed_connections: self.failed_connections,
+            topics_subscribed,
+            total,
+            per_topic,
+            per_peer: self.per_peer
+                .iter()
+                .map(|(peer_id, counters)| (peer_id.to_string(), *counters))
+                .collect(),
+        }
+    }
+}
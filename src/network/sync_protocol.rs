@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use crate::types::{Block, Checkpoint, Hash, NetworkId};
+
+/// Protocol ID for block sync request/response
+pub const SYNC_PROTOCOL: &str = "/republic-chain/sync/1";
+
+/// Maximum number of blocks served for a single request
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 64;
+
+/// Chain status exchanged between peers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusMessage {
+    /// Network the peer belongs to
+    pub network_id: NetworkId,
+    /// Genesis validators root of the peer's chain
+    pub genesis_root: Hash,
+    /// Current fork version
+    pub fork_version: [u8; 4],
+    /// Hash of the peer's head block
+    pub head_hash: Hash,
+    /// Height of the peer's head block
+    pub head_height: u64,
+    /// Latest finalized checkpoint
+    pub finalized_checkpoint: Checkpoint,
+}
+
+/// Block sync requests
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// Exchange chain status
+    Status(StatusMessage),
+    /// Canonical blocks with heights in `start..start + count`
+    BlocksByRange { start: u64, count: u64 },
+    /// Blocks with the given hashes
+    BlocksByRoot { hashes: Vec<Hash> },
+}
+
+/// Block sync responses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncResponse {
+    /// The responder's chain status
+    Status(StatusMessage),
+    /// Requested blocks in ascending height order
+    Blocks(Vec<Block>),
+    /// The request could not be served
+    Error(String),
+    /// The requester exceeded its quota and should retry later
+    RateLimited { retry_after_ms: u64 },
+}
+
+impl StatusMessage {
+    /// Check that a remote status belongs to the same network and fork
+    pub fn check_compatible(&self, remote: &StatusMessage) -> Result<(), String> {
+        if self.network_id != remote.network_id {
+            return Err(format!(
+                "Network mismatch: expected {}, got {}",
+                self.network_id.name(),
+                remote.network_id.name()
+            ));
+        }
+        if self.genesis_root != remote.genesis_root {
+            return Err(format!(
+                "Genesis root mismatch: expected {}, got {}",
+                hex::encode(self.genesis_root),
+                hex::encode(remote.genesis_root)
+            ));
+        }
+        if self.fork_version != remote.fork_version {
+            return Err(format!(
+                "Fork version mismatch: expected {}, got {}",
+                hex::encode(self.fork_version),
+                hex::encode(remote.fork_version)
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl SyncRequest {
+    /// Check request bounds before doing any work
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            SyncRequest::Status(_) => Ok(()),
+            SyncRequest::BlocksByRange { count, .. } => {
+                if *count == 0 || *count > MAX_BLOCKS_PER_REQUEST {
+                    return Err(format!("Block count must be between 1 and {}", MAX_BLOCKS_PER_REQUEST));
+                }
+                Ok(())
+            }
+            SyncRequest::BlocksByRoot { hashes } => {
+                if hashes.is_empty() || hashes.len() as u64 > MAX_BLOCKS_PER_REQUEST {
+                    return Err(format!("Hash count must be between 1 and {}", MAX_BLOCKS_PER_REQUEST));
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::crypto::CryptoProvider;
+use crate::types::{
+    AggregateAndProof, Address, Attestation, AttesterSlashing, Block, Hash, IndexedAttestation, ProposerSlashing,
+    PublicKey, Signature, Slot, Transaction, VoluntaryExit,
+};
+use crate::validator::duties::selection_proof_root;
+use super::{MessageType, NetworkConfig, NetworkEvent, NetworkMessage, ValidationResult, WireFormat};
+
+/// Tolerated clock difference when checking gossip slot timing
+pub const MAXIMUM_GOSSIP_CLOCK_DISPARITY_MS: u64 = 500;
+
+/// Blocks and attestations more than this many slots behind the current
+/// slot are ignored
+pub const MAX_GOSSIP_BLOCK_SLOT_AGE: u64 = 32;
+
+/// Number of message IDs remembered for duplicate checks
+pub const SEEN_CACHE_SIZE: usize = 8192;
+
+/// Bounded set of recently seen message IDs, oldest evicted first
+#[derive(Debug)]
+pub struct SeenCache {
+    capacity: usize,
+    seen: HashSet<Hash>,
+    order: VecDeque<Hash>,
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn contains(&self, id: &Hash) -> bool {
+        self.seen.contains(id)
+    }
+
+    /// Record an ID, returning false if it was already present
+    pub fn insert(&mut self, id: Hash) -> bool {
+        if !self.seen.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+/// Application-level validation of gossip messages before they are
+/// propagated or handed to consensus
+#[derive(Debug)]
+pub struct GossipValidator {
+    genesis_time: u64,
+    seconds_per_slot: u64,
+    max_message_size: usize,
+    /// Validator keys by address, for block proposers
+    proposer_keys: HashMap<Address, PublicKey>,
+    /// Validator keys by the index attestations, exits and slashings use
+    index_keys: HashMap<u64, PublicKey>,
+    seen: SeenCache,
+}
+
+impl GossipValidator {
+    pub fn new(config: &NetworkConfig) -> Self {
+        Self {
+            genesis_time: config.genesis_time,
+            seconds_per_slot: config.seconds_per_slot.max(1),
+            max_message_size: config.max_message_size,
+            proposer_keys: HashMap::new(),
+            index_keys: HashMap::new(),
+            seen: SeenCache::new(SEEN_CACHE_SIZE),
+        }
+    }
+
+    /// Replace the validator keys used to check signatures, given as
+    /// `(index, address, public key)`. Until keys are set, signed messages
+    /// are ignored rather than accepted unchecked.
+    pub fn set_validator_keys(&mut self, keys: Vec<(u64, Address, PublicKey)>) {
+        self.proposer_keys = keys.iter().map(|(_, address, public_key)| (*address, *public_key)).collect();
+        self.index_keys = keys.into_iter().map(|(index, _, public_key)| (index, public_key)).collect();
+    }
+
+    /// Record a locally published message so echoes are not re-emitted
+    pub fn mark_published(&mut self, message: &NetworkMessage) {
+        self.seen.insert(message.content_id());
+    }
+
+    /// Validate a raw JSON gossip payload, returning the event to emit if
+    /// accepted. `subnet_id` is set when the message arrived on an
+    /// attestation subnet.
+    pub fn validate(
+        &mut self,
+        from: PeerId,
+        subnet_id: Option<u64>,
+        data: &[u8],
+        now_ms: u64,
+    ) -> (ValidationResult, Option<NetworkEvent>) {
+        self.validate_framed(from, subnet_id, WireFormat::Json, data, now_ms)
+    }
+
+    /// Validate a gossip payload encoded in the given wire format
+    pub fn validate_framed(
+        &mut self,
+        from: PeerId,
+        subnet_id: Option<u64>,
+        format: WireFormat,
+        data: &[u8],
+        now_ms: u64,
+    ) -> (ValidationResult, Option<NetworkEvent>) {
+        let message = match format.decode::<NetworkMessage>(data, self.max_message_size) {
+            Ok(message) => message,
+            Err(e) => return (ValidationResult::Reject(format!("Failed to decode message: {}", e)), None),
+        };
+
+        // Consensus must never see the same block or transaction twice
+        let message_id = message.content_id();
+        if self.seen.contains(&message_id) {
+            return (ValidationResult::Ignore("Duplicate message".to_string()), None);
+        }
+
+        if !message.is_recent() {
+            return (ValidationResult::Ignore("Message is too old".to_string()), None);
+        }
+        if let Err(reason) = message.validate() {
+            return (ValidationResult::Reject(reason), None);
+        }
+
+        let (result, event) = match message.msg_type {
+            MessageType::Block => match serde_json::from_slice::<Block>(&message.data) {
+                Ok(block) => (
+                    self.validate_block(&block, now_ms),
+                    NetworkEvent::BlockReceived { block, from },
+                ),
+                Err(e) => return (ValidationResult::Reject(format!("Invalid block data: {}", e)), None),
+            },
+            MessageType::Transaction => match serde_json::from_slice::<Transaction>(&message.data) {
+                Ok(transaction) => (
+                    self.validate_transaction(&transaction),
+                    NetworkEvent::TransactionReceived { transaction, from },
+                ),
+                Err(e) => return (ValidationResult::Reject(format!("Invalid transaction data: {}", e)), None),
+            },
+            MessageType::Ping => (ValidationResult::Accept, NetworkEvent::PingReceived { from }),
+            MessageType::Attestation => {
+                let Some(subnet_id) = subnet_id else {
+                    return (ValidationResult::Reject("Attestation outside an attestation subnet".to_string()), None);
+                };
+                match serde_json::from_slice::<Attestation>(&message.data) {
+                    Ok(attestation) => (
+                        self.validate_attestation(&attestation, now_ms),
+                        NetworkEvent::AttestationReceived { attestation, subnet_id, from },
+                    ),
+                    Err(e) => return (ValidationResult::Reject(format!("Invalid attestation data: {}", e)), None),
+                }
+            }
+            MessageType::AggregateAndProof => match serde_json::from_slice::<AggregateAndProof>(&message.data) {
+                Ok(aggregate) => (
+                    self.validate_aggregate(&aggregate, now_ms),
+                    NetworkEvent::AggregateAndProofReceived { aggregate, from },
+                ),
+                Err(e) => return (ValidationResult::Reject(format!("Invalid aggregate data: {}", e)), None),
+            },
+            MessageType::ProposerSlashing => match serde_json::from_slice::<ProposerSlashing>(&message.data) {
+                Ok(slashing) => (
+                    self.validate_proposer_slashing(&slashing),
+                    NetworkEvent::ProposerSlashingReceived { slashing, from },
+                ),
+                Err(e) => return (ValidationResult::Reject(format!("Invalid proposer slashing data: {}", e)), None),
+            },
+            MessageType::AttesterSlashing => match serde_json::from_slice::<AttesterSlashing>(&message.data) {
+                Ok(slashing) => (
+                    self.validate_attester_slashing(&slashing),
+                    NetworkEvent::AttesterSlashingReceived { slashing, from },
+                ),
+                Err(e) => return (ValidationResult::Reject(format!("Invalid attester slashing data: {}", e)), None),
+            },
+            MessageType::VoluntaryExit => match serde_json::from_slice::<VoluntaryExit>(&message.data) {
+                Ok(exit) => (
+                    self.verify_indexed(exit.validator_index, &exit.signing_root(), &exit.signature, "exit"),
+                    NetworkEvent::VoluntaryExitReceived { exit, from },
+                ),
+                Err(e) => return (ValidationResult::Reject(format!("Invalid voluntary exit data: {}", e)), None),
+            },
+        };
+
+        if result != ValidationResult::Accept {
+            return (result, None);
+        }
+        self.seen.insert(message_id);
+        (result, Some(event))
+    }
+
+    fn validate_block(&self, block: &Block, now_ms: u64) -> ValidationResult {
+        if let Err(reason) = self.check_slot_window(block.header.slot, now_ms) {
+            return ValidationResult::Ignore(format!("Block {}", reason));
+        }
+
+        if !block.is_valid() {
+            return ValidationResult::Reject("Block failed basic validation".to_string());
+        }
+
+        // Signatures can only be checked once validator keys are known
+        if self.proposer_keys.is_empty() {
+            return ValidationResult::Ignore("Validator keys are not known yet".to_string());
+        }
+        match self.proposer_keys.get(&block.header.proposer) {
+            Some(public_key) if block.verify_signature(public_key).is_ok() => ValidationResult::Accept,
+            Some(_) => ValidationResult::Reject("Invalid proposer signature".to_string()),
+            // Our view of the validator set may lag the sender's, e.g. just
+            // after an activation, so an unknown proposer is not misbehaviour
+            None => ValidationResult::Ignore("Block proposer is not a known validator".to_string()),
+        }
+    }
+
+    fn validate_transaction(&self, transaction: &Transaction) -> ValidationResult {
+        if !transaction.is_valid() {
+            return ValidationResult::Reject("Transaction failed basic validation".to_string());
+        }
+        // Multisig signatures are checked against the sender's signer set
+        // when the transaction is applied
+        if transaction.multisig.is_none() {
+            if let Err(reason) = transaction.verify_sender() {
+                return ValidationResult::Reject(reason);
+            }
+        }
+
+        ValidationResult::Accept
+    }
+
+    fn validate_attestation(&self, attestation: &Attestation, now_ms: u64) -> ValidationResult {
+        if let Err(reason) = self.check_slot_window(attestation.slot, now_ms) {
+            return ValidationResult::Ignore(format!("Attestation {}", reason));
+        }
+        if attestation.source_epoch > attestation.target_epoch {
+            return ValidationResult::Reject("Attestation source is newer than its target".to_string());
+        }
+        self.verify_indexed(attestation.validator_index, &attestation.signing_root(), &attestation.signature, "attestation")
+    }
+
+    fn validate_aggregate(&self, aggregate: &AggregateAndProof, now_ms: u64) -> ValidationResult {
+        let data = &aggregate.aggregate.data;
+        if let Err(reason) = self.check_slot_window(data.slot, now_ms) {
+            return ValidationResult::Ignore(format!("Aggregate {}", reason));
+        }
+        if aggregate.aggregate.attesting_indices.is_empty() {
+            return ValidationResult::Reject("Aggregate has no attesters".to_string());
+        }
+        if data.source.epoch > data.target.epoch {
+            return ValidationResult::Reject("Aggregate source is newer than its target".to_string());
+        }
+
+        // The aggregator signs the selection proof, the aggregated vote and
+        // the whole message
+        let index = aggregate.aggregator_index;
+        for (root, signature, what) in [
+            (selection_proof_root(data.slot), &aggregate.selection_proof, "selection proof"),
+            (data.signing_root(), &aggregate.aggregate.signature, "aggregate"),
+            (aggregate.signing_root(), &aggregate.signature, "aggregate and proof"),
+        ] {
+            let result = self.verify_indexed(index, &root, signature, what);
+            if result != ValidationResult::Accept {
+                return result;
+            }
+        }
+        ValidationResult::Accept
+    }
+
+    fn validate_proposer_slashing(&self, slashing: &ProposerSlashing) -> ValidationResult {
+        if !slashing.is_slashable() {
+            return ValidationResult::Reject("Proposer slashing evidence is not slashable".to_string());
+        }
+        for signed in [&slashing.signed_header_1, &slashing.signed_header_2] {
+            let header = &signed.header;
+            let result = self.verify_indexed(header.proposer_index, &header.signing_root(), &signed.signature, "slashed header");
+            if result != ValidationResult::Accept {
+                return result;
+            }
+        }
+        ValidationResult::Accept
+    }
+
+    fn validate_attester_slashing(&self, slashing: &AttesterSlashing) -> ValidationResult {
+        if !slashing.is_slashable() {
+            return ValidationResult::Reject("Attester slashing evidence is not slashable".to_string());
+        }
+        for attestation in [&slashing.attestation_1, &slashing.attestation_2] {
+            let result = self.verify_indexed_attestation(attestation);
+            if result != ValidationResult::Accept {
+                return result;
+            }
+        }
+        ValidationResult::Accept
+    }
+
+    /// Signatures are not aggregated yet, so an indexed attestation carries
+    /// the signature of one of its attesters over the vote
+    fn verify_indexed_attestation(&self, attestation: &IndexedAttestation) -> ValidationResult {
+        if self.index_keys.is_empty() {
+            return ValidationResult::Ignore("Validator keys are not known yet".to_string());
+        }
+        if !attestation.attesting_indices.iter().any(|index| self.index_keys.contains_key(index)) {
+            return ValidationResult::Ignore("No attester of the slashing evidence is known".to_string());
+        }
+        let root = attestation.data.signing_root();
+        let signed = attestation.attesting_indices.iter().any(|index| {
+            self.index_keys
+                .get(index)
+                .is_some_and(|public_key| CryptoProvider::verify(public_key, &root, &attestation.signature).is_ok())
+        });
+        if signed {
+            ValidationResult::Accept
+        } else {
+            ValidationResult::Reject("Slashing evidence is not signed by an attester".to_string())
+        }
+    }
+
+    /// Check a signature by the validator at `index`
+    fn verify_indexed(&self, index: u64, root: &Hash, signature: &Signature, what: &str) -> ValidationResult {
+        if self.index_keys.is_empty() {
+            return ValidationResult::Ignore("Validator keys are not known yet".to_string());
+        }
+        match self.index_keys.get(&index) {
+            Some(public_key) if CryptoProvider::verify(public_key, root, signature).is_ok() => ValidationResult::Accept,
+            Some(_) => ValidationResult::Reject(format!("Invalid {} signature", what)),
+            None => ValidationResult::Ignore(format!("Validator {} of {} is not known", index, what)),
+        }
+    }
+
+    /// Check that a slot is neither in the future nor too far in the past
+    fn check_slot_window(&self, slot: Slot, now_ms: u64) -> Result<(), String> {
+        let Some(current_slot) = self.current_slot(now_ms) else {
+            return Ok(());
+        };
+
+        // Slots come from the network, so a start time that overflows is
+        // simply far in the future
+        let slot_start_ms = slot
+            .checked_mul(self.seconds_per_slot)
+            .and_then(|offset| offset.checked_add(self.genesis_time))
+            .and_then(|start| start.checked_mul(1000));
+        if slot_start_ms.is_none_or(|start| start > now_ms.saturating_add(MAXIMUM_GOSSIP_CLOCK_DISPARITY_MS)) {
+            return Err(format!("slot {} is in the future", slot));
+        }
+        if slot.saturating_add(MAX_GOSSIP_BLOCK_SLOT_AGE) < current_slot {
+            return Err(format!("slot {} is too old", slot));
+        }
+        Ok(())
+    }
+
+    fn current_slot(&self, now_ms: u64) -> Option<Slot> {
+        if self.genesis_time == 0 {
+            return None;
+        }
+        Some((now_ms / 1000).saturating_sub(self.genesis_time) / self.seconds_per_slot)
+    }
+}
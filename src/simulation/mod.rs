@@ -0,0 +1,680 @@
+// Simulation module - runs several nodes against an in-memory network with
+// a virtual clock, so gossip, sync, reorgs and finality can be tested
+// deterministically
+
+use crate::consensus::{ConsensusEngine, ConsensusEngineCore, SlotClock};
+use crate::crypto::{CryptoProvider, Hasher, KeyPair};
+use crate::network::{
+    attestation_subnet_topic, compute_subnet_for_attestation, GossipValidator, MessageType, NetworkConfig,
+    NetworkEvent, NetworkMessage, StatusMessage, SyncRequest, SyncResponse, ValidationResult, WireFormat,
+};
+use crate::sync::{local_status, SyncManager};
+use crate::types::*;
+use anyhow::Result;
+use libp2p::{identity, PeerId};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use tracing::debug;
+
+/// Index of a node in the simulation
+pub type NodeId = usize;
+
+/// Delivery conditions of a link between two nodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    /// Base one-way latency
+    pub latency_ms: u64,
+    /// Random extra latency of up to this many milliseconds
+    pub jitter_ms: u64,
+    /// Probability that a message is lost
+    pub drop_rate: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency_ms: 50,
+            jitter_ms: 0,
+            drop_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub nodes: usize,
+    /// Validators are assigned to nodes round-robin
+    pub validators: usize,
+    /// Seed for keys, latency jitter and message drops
+    pub seed: u64,
+    pub slot_duration_ms: u64,
+    /// Conditions of every link unless overridden with `set_link`
+    pub link: LinkConfig,
+    pub wire_format: WireFormat,
+    pub consensus: ConsensusConfig,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            nodes: 4,
+            validators: 8,
+            seed: 0,
+            slot_duration_ms: 12_000,
+            link: LinkConfig::default(),
+            wire_format: WireFormat::SnappyBincode,
+            consensus: ConsensusConfig {
+                slots_per_epoch: 8,
+                ..ConsensusConfig::default()
+            },
+        }
+    }
+}
+
+/// Message counters across the whole simulation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulationStats {
+    pub sent: u64,
+    pub delivered: u64,
+    /// Lost to the link's drop rate
+    pub dropped: u64,
+    /// Not sent because sender and receiver are partitioned
+    pub partitioned: u64,
+    pub blocks_imported: u64,
+    pub blocks_rejected: u64,
+}
+
+/// A message on the simulated wire
+#[derive(Debug, Clone)]
+pub enum SimMessage {
+    Gossip { topic: String, data: Vec<u8> },
+    Request(SyncRequest),
+    Response(SyncResponse),
+}
+
+#[derive(Debug)]
+struct Delivery {
+    at: u64,
+    seq: u64,
+    from: NodeId,
+    to: NodeId,
+    message: SimMessage,
+}
+
+impl PartialEq for Delivery {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Delivery {}
+
+impl PartialOrd for Delivery {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delivery {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+/// A simulated node: consensus plus the gossip validation a real node runs
+/// in front of it
+pub struct SimNode {
+    pub id: NodeId,
+    pub peer_id: PeerId,
+    pub consensus: ConsensusEngine,
+    /// Runs epoch processing, and with it finality, as on a real node
+    pub core: ConsensusEngineCore,
+    /// Validator index and key of each validator run by this node
+    pub validator_keys: Vec<(u64, KeyPair)>,
+    /// Number of head changes to a block that does not descend from the
+    /// previous head
+    pub reorgs: u64,
+    gossip: GossipValidator,
+    /// Blocks waiting for their parent, keyed by the parent hash
+    orphans: HashMap<Hash, Vec<Block>>,
+    head: Option<Hash>,
+}
+
+impl SimNode {
+    pub fn head(&self) -> Hash {
+        self.head.unwrap_or([0u8; 32])
+    }
+
+    pub fn head_height(&self) -> u64 {
+        self.consensus.fork_choice
+            .get_block(&self.head())
+            .map(|block| block.header.height)
+            .unwrap_or(0)
+    }
+
+    pub fn finalized_checkpoint(&self) -> &Checkpoint {
+        &self.consensus.fork_choice.finalized_checkpoint
+    }
+
+    /// Blocks from the oldest known ancestor of the head up to the head
+    pub fn canonical_chain(&self) -> Vec<Block> {
+        let mut chain = Vec::new();
+        let mut current = self.head();
+        while let Some(block) = self.consensus.fork_choice.get_block(&current) {
+            chain.push(block.clone());
+            current = block.header.previous_hash;
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Answer a sync request from fork choice, the way a real node answers
+    /// from storage
+    pub fn serve(&self, request: &SyncRequest, config: &NetworkConfig) -> SyncResponse {
+        if let Err(reason) = request.validate() {
+            return SyncResponse::Error(reason);
+        }
+
+        match request {
+            SyncRequest::Status(_) => SyncResponse::Status(local_status(&self.consensus, config)),
+            SyncRequest::BlocksByRange { start, count } => {
+                let end = start.saturating_add(*count);
+                SyncResponse::Blocks(
+                    self.canonical_chain()
+                        .into_iter()
+                        .filter(|block| block.header.height >= *start && block.header.height < end)
+                        .collect(),
+                )
+            }
+            SyncRequest::BlocksByRoot { hashes } => SyncResponse::Blocks(
+                hashes
+                    .iter()
+                    .filter_map(|hash| self.consensus.fork_choice.get_block(hash).cloned())
+                    .collect(),
+            ),
+        }
+    }
+
+    fn update_head(&mut self) {
+        let new_head = self.consensus.get_head();
+        if let (Some(old), Some(new)) = (self.head, new_head) {
+            if old != new && !self.consensus.fork_choice.is_descendant(old, new) {
+                debug!("Node {} reorged from {} to {}", self.id, hex::encode(old), hex::encode(new));
+                self.reorgs += 1;
+            }
+        }
+        self.head = new_head;
+    }
+}
+
+/// Deterministic multi-node simulation. Every node is connected to every
+/// other node; gossip is flooded, so each accepted message is forwarded
+/// once to all reachable peers. Time only advances through `run_until`
+/// and the slot helpers.
+pub struct Simulation {
+    pub config: SimulationConfig,
+    pub nodes: Vec<SimNode>,
+    pub stats: SimulationStats,
+    network_config: NetworkConfig,
+    now_ms: u64,
+    slot: Slot,
+    queue: BinaryHeap<Reverse<Delivery>>,
+    seq: u64,
+    rng: StdRng,
+    links: HashMap<(NodeId, NodeId), LinkConfig>,
+    /// Partition group of each node; nodes only reach their own group
+    groups: Vec<usize>,
+}
+
+impl Simulation {
+    pub fn new(config: SimulationConfig) -> Result<Self> {
+        if config.nodes == 0 {
+            return Err(anyhow::anyhow!("Simulation needs at least one node"));
+        }
+
+        let keypairs: Vec<KeyPair> = (0..config.validators as u64)
+            .map(|i| KeyPair::from_private_key(Self::derive_seed(config.seed, b"validator", i)))
+            .collect::<Result<_>>()?;
+        // Enough stake to stay above the ejection balance at epoch ends
+        let stake = config.consensus.min_deposit_amount.max(config.consensus.ejection_balance);
+        let validators: Vec<Validator> = keypairs
+            .iter()
+            .enumerate()
+            .map(|(i, keypair)| {
+                let metadata = ValidatorMetadata {
+                    name: format!("validator_{}", i),
+                    website: None,
+                    description: None,
+                    contact: None,
+                };
+                Validator::new(keypair.address, keypair.public_key, stake, 500, 0, metadata)
+            })
+            .collect();
+
+        let consensus = ConsensusEngine::new(config.consensus.clone(), validators)?;
+        let network_config = NetworkConfig::default();
+
+        // Attestations carry the validator's registry index
+        let indices: HashMap<Address, u64> = consensus.validator_set
+            .indexed_keys()
+            .into_iter()
+            .map(|(index, address, _)| (address, index))
+            .collect();
+        let validator_keys = consensus.validator_set.indexed_keys();
+
+        let mut nodes: Vec<SimNode> = (0..config.nodes)
+            .map(|id| {
+                let mut secret = Self::derive_seed(config.seed, b"node", id as u64);
+                let identity = identity::Keypair::ed25519_from_bytes(&mut secret)
+                    .map_err(|e| anyhow::anyhow!("Invalid node key: {}", e))?;
+
+                let mut gossip = GossipValidator::new(&network_config);
+                gossip.set_validator_keys(validator_keys.clone());
+
+                Ok(SimNode {
+                    id,
+                    peer_id: identity.public().to_peer_id(),
+                    consensus: consensus.clone(),
+                    core: ConsensusEngineCore::new(SlotClock::new(
+                        0,
+                        (config.slot_duration_ms / 1000).max(1),
+                        config.consensus.slots_per_epoch,
+                    )),
+                    validator_keys: Vec::new(),
+                    reorgs: 0,
+                    gossip,
+                    orphans: HashMap::new(),
+                    head: None,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        for (i, keypair) in keypairs.into_iter().enumerate() {
+            nodes[i % config.nodes].validator_keys.push((indices[&keypair.address], keypair));
+        }
+
+        Ok(Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            groups: vec![0; config.nodes],
+            config,
+            nodes,
+            stats: SimulationStats::default(),
+            network_config,
+            now_ms: 0,
+            slot: 0,
+            queue: BinaryHeap::new(),
+            seq: 0,
+            links: HashMap::new(),
+        })
+    }
+
+    fn derive_seed(seed: u64, domain: &[u8], index: u64) -> [u8; 32] {
+        let mut data = seed.to_le_bytes().to_vec();
+        data.extend_from_slice(domain);
+        data.extend_from_slice(&index.to_le_bytes());
+        Hasher::hash(&data)
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    pub fn current_slot(&self) -> Slot {
+        self.slot
+    }
+
+    /// Override the conditions of the link between two nodes, both ways
+    pub fn set_link(&mut self, a: NodeId, b: NodeId, link: LinkConfig) {
+        self.links.insert((a, b), link);
+        self.links.insert((b, a), link);
+    }
+
+    /// Split the network into groups; nodes not listed form one more group.
+    /// Messages already in flight are still delivered.
+    pub fn partition(&mut self, groups: &[&[NodeId]]) {
+        self.groups = vec![groups.len(); self.nodes.len()];
+        for (group, members) in groups.iter().enumerate() {
+            for &node in members.iter() {
+                self.groups[node] = group;
+            }
+        }
+    }
+
+    pub fn heal(&mut self) {
+        self.groups = vec![0; self.nodes.len()];
+    }
+
+    pub fn can_reach(&self, from: NodeId, to: NodeId) -> bool {
+        self.groups[from] == self.groups[to]
+    }
+
+    /// Whether all nodes agree on the head
+    pub fn heads_agree(&self) -> bool {
+        self.nodes.windows(2).all(|pair| pair[0].head() == pair[1].head())
+    }
+
+    /// Run `count` slots: at each slot start every node's consensus core
+    /// processes the slot, finalizing epochs that ended, then the
+    /// proposer's node builds and gossips a block. A third of the way into
+    /// the slot every validator attests to its node's head.
+    pub async fn run_slots(&mut self, count: u64) {
+        for _ in 0..count {
+            self.run_slot().await;
+        }
+    }
+
+    pub async fn run_slot(&mut self) {
+        self.slot += 1;
+        let slot_start = self.slot * self.config.slot_duration_ms;
+        self.run_until(slot_start);
+
+        self.process_slot(self.slot).await;
+        self.propose(self.slot);
+
+        self.run_until(slot_start + self.config.slot_duration_ms / 3);
+        self.attest(self.slot);
+
+        self.run_until(slot_start + self.config.slot_duration_ms - 1);
+    }
+
+    /// Deliver every message due at or before `time_ms`
+    pub fn run_until(&mut self, time_ms: u64) {
+        while let Some(Reverse(delivery)) = self.queue.peek() {
+            if delivery.at > time_ms {
+                break;
+            }
+            let Reverse(delivery) = self.queue.pop().expect("peeked delivery");
+            self.now_ms = self.now_ms.max(delivery.at);
+            self.stats.delivered += 1;
+            self.deliver(delivery);
+        }
+        self.now_ms = self.now_ms.max(time_ms);
+    }
+
+    /// Run epoch processing through the end of `epoch` on every node and
+    /// return the nodes that finalized its checkpoint. `run_slot` already
+    /// does this once the next epoch starts.
+    pub async fn finalize_epoch(&mut self, epoch: Epoch) -> Vec<NodeId> {
+        let next_epoch_start = self.config.consensus.slots_per_epoch * (epoch + 1);
+        self.process_slot(next_epoch_start).await;
+        self.nodes
+            .iter()
+            .filter(|node| node.finalized_checkpoint().epoch == epoch)
+            .map(|node| node.id)
+            .collect()
+    }
+
+    /// Process `slot` with every node's consensus core. Slots a node has
+    /// already processed are ignored.
+    async fn process_slot(&mut self, slot: Slot) {
+        for node in &mut self.nodes {
+            if let Err(e) = node.core.process_slot(&mut node.consensus, slot).await {
+                debug!("Node {} failed to process slot {}: {}", node.id, slot, e);
+            }
+            node.update_head();
+        }
+    }
+
+    /// Range sync a node from every peer it can reach, using the same
+    /// `SyncManager` as the real node. Requests are answered immediately.
+    pub fn sync_node(&mut self, node: NodeId) -> u64 {
+        let mut manager = SyncManager::new(self.network_config.network_id, 16);
+        let status_request = SyncRequest::Status(self.status(node));
+        let current_slot = self.current_slot();
+        for peer in 0..self.nodes.len() {
+            if peer == node || !self.can_reach(node, peer) {
+                continue;
+            }
+            if let SyncResponse::Status(status) = self.nodes[peer].serve(&status_request, &self.network_config) {
+                manager.add_peer(self.nodes[peer].peer_id, status, current_slot);
+            }
+        }
+
+        manager.start(self.nodes[node].head_height(), self.nodes[node].head());
+        let mut imported = 0;
+        while !manager.is_complete() {
+            let requests = manager.next_requests();
+            if requests.is_empty() {
+                break;
+            }
+            for (peer_id, batch) in requests {
+                let response = self.nodes
+                    .iter()
+                    .find(|peer| peer.peer_id == peer_id)
+                    .map(|peer| peer.serve(&batch.request(), &self.network_config))
+                    .ok_or_else(|| "Unknown peer".into());
+                manager.on_response(peer_id, batch, response);
+            }
+            imported += manager.process_ready(&mut self.nodes[node].consensus).len() as u64;
+        }
+
+        self.stats.blocks_imported += imported;
+        self.nodes[node].update_head();
+        imported
+    }
+
+    fn status(&self, node: NodeId) -> StatusMessage {
+        local_status(&self.nodes[node].consensus, &self.network_config)
+    }
+
+    fn propose(&mut self, slot: Slot) {
+        let Ok(proposer) = self.nodes[0].consensus.get_proposer_for_slot(slot) else {
+            return;
+        };
+        let Some(id) = self.nodes
+            .iter()
+            .position(|node| node.validator_keys.iter().any(|(_, key)| key.address == proposer))
+        else {
+            return;
+        };
+
+        let node = &self.nodes[id];
+        let parent = node.head();
+        let height = node.head_height() + 1;
+        let epoch = node.consensus.slot_to_epoch(slot);
+        let keypair = node.validator_keys
+            .iter()
+            .find(|(_, key)| key.address == proposer)
+            .map(|(_, key)| key.clone())
+            .expect("proposer key");
+
+        let mut block = Block::new(height, parent, [0u8; 32], slot, epoch, proposer, Vec::new(), [0u8; 32], 30_000_000);
+        block.header.timestamp = chrono::DateTime::from_timestamp_millis(self.now_ms as i64).unwrap_or_default();
+        block.sign(&keypair.signing_key());
+
+        if let Err(e) = self.import_block(id, block.clone()) {
+            debug!("Node {} rejected its own block at slot {}: {}", id, slot, e);
+            return;
+        }
+        match NetworkMessage::block(&block) {
+            Ok(message) => self.publish(id, MessageType::Block.topic().to_string(), message),
+            Err(e) => debug!("Failed to encode block: {}", e),
+        }
+    }
+
+    fn attest(&mut self, slot: Slot) {
+        for id in 0..self.nodes.len() {
+            let node = &self.nodes[id];
+            let head = node.head();
+            if head == [0u8; 32] {
+                continue;
+            }
+            let justified = node.consensus.fork_choice.justified_checkpoint.clone();
+            let target_epoch = node.consensus.slot_to_epoch(slot);
+
+            // Fork choice counts votes for the target root, so validators
+            // vote for their head
+            let attestations: Vec<Attestation> = node.validator_keys
+                .iter()
+                .map(|(index, keypair)| {
+                    let mut attestation = Attestation {
+                        slot,
+                        beacon_block_root: head,
+                        source_epoch: justified.epoch,
+                        source_root: justified.root,
+                        target_epoch,
+                        target_root: head,
+                        validator_index: *index,
+                        signature: Signature([0u8; 64]),
+                    };
+                    if let Ok(signature) = CryptoProvider::sign(&keypair.private_key, &attestation.signing_root()) {
+                        attestation.signature = signature;
+                    }
+                    attestation
+                })
+                .collect();
+
+            let subnet_id = compute_subnet_for_attestation(slot, 0, self.network_config.attestation_subnet_count);
+            for attestation in attestations {
+                self.import_attestation(id, &attestation);
+                match NetworkMessage::attestation(&attestation) {
+                    Ok(message) => self.publish(id, attestation_subnet_topic(subnet_id), message),
+                    Err(e) => debug!("Failed to encode attestation: {}", e),
+                }
+            }
+        }
+    }
+
+    fn publish(&mut self, from: NodeId, topic: String, message: NetworkMessage) {
+        self.nodes[from].gossip.mark_published(&message);
+        match self.config.wire_format.encode(&message) {
+            Ok(data) => self.broadcast(from, None, &topic, data),
+            Err(e) => debug!("Failed to encode gossip message: {}", e),
+        }
+    }
+
+    /// Send gossip to every reachable node except the one it came from
+    fn broadcast(&mut self, from: NodeId, source: Option<NodeId>, topic: &str, data: Vec<u8>) {
+        for to in 0..self.nodes.len() {
+            if to != from && Some(to) != source {
+                self.send(from, to, SimMessage::Gossip { topic: topic.to_string(), data: data.clone() });
+            }
+        }
+    }
+
+    fn send(&mut self, from: NodeId, to: NodeId, message: SimMessage) {
+        self.stats.sent += 1;
+        if !self.can_reach(from, to) {
+            self.stats.partitioned += 1;
+            return;
+        }
+
+        let link = self.links.get(&(from, to)).copied().unwrap_or(self.config.link);
+        if link.drop_rate > 0.0 && self.rng.gen_bool(link.drop_rate.min(1.0)) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let jitter = if link.jitter_ms > 0 { self.rng.gen_range(0..=link.jitter_ms) } else { 0 };
+
+        self.seq += 1;
+        self.queue.push(Reverse(Delivery {
+            at: self.now_ms + link.latency_ms + jitter,
+            seq: self.seq,
+            from,
+            to,
+            message,
+        }));
+    }
+
+    fn deliver(&mut self, delivery: Delivery) {
+        let Delivery { from, to, message, .. } = delivery;
+        match message {
+            SimMessage::Gossip { topic, data } => self.on_gossip(from, to, topic, data),
+            SimMessage::Request(request) => {
+                let response = self.nodes[to].serve(&request, &self.network_config);
+                self.send(to, from, SimMessage::Response(response));
+            }
+            SimMessage::Response(SyncResponse::Blocks(blocks)) => {
+                for block in blocks {
+                    self.receive_block(to, from, block);
+                }
+            }
+            SimMessage::Response(response) => {
+                debug!("Node {} got unexpected response from {}: {:?}", to, from, response);
+            }
+        }
+    }
+
+    fn on_gossip(&mut self, from: NodeId, to: NodeId, topic: String, data: Vec<u8>) {
+        let subnet_id = topic
+            .strip_prefix(MessageType::Attestation.topic())
+            .and_then(|suffix| suffix.strip_prefix('_'))
+            .and_then(|subnet| subnet.parse().ok());
+        let peer_id = self.nodes[from].peer_id;
+        let (result, event) = self.nodes[to].gossip.validate_framed(
+            peer_id,
+            subnet_id,
+            self.config.wire_format,
+            &data,
+            self.now_ms,
+        );
+        if result != ValidationResult::Accept {
+            return;
+        }
+
+        self.broadcast(to, Some(from), &topic, data);
+        match event {
+            Some(NetworkEvent::BlockReceived { block, .. }) => self.receive_block(to, from, block),
+            Some(NetworkEvent::AttestationReceived { attestation, .. }) => self.import_attestation(to, &attestation),
+            _ => {}
+        }
+    }
+
+    /// Import a block from a peer, asking that peer for the parent first if
+    /// it is unknown
+    fn receive_block(&mut self, node: NodeId, from: NodeId, block: Block) {
+        let parent = block.header.previous_hash;
+        let fork_choice = &self.nodes[node].consensus.fork_choice;
+        if fork_choice.has_block(&block.hash()) {
+            return;
+        }
+        if parent != [0u8; 32] && !fork_choice.has_block(&parent) {
+            let orphans = self.nodes[node].orphans.entry(parent).or_default();
+            let first = orphans.is_empty();
+            orphans.push(block);
+            if first {
+                self.send(node, from, SimMessage::Request(SyncRequest::BlocksByRoot { hashes: vec![parent] }));
+            }
+            return;
+        }
+
+        if let Err(e) = self.import_block(node, block) {
+            debug!("Node {} rejected block from {}: {}", node, from, e);
+        }
+    }
+
+    /// Import a block and every orphan waiting on it
+    fn import_block(&mut self, node: NodeId, block: Block) -> Result<()> {
+        let mut pending = vec![block];
+        let mut result = Ok(());
+        while let Some(block) = pending.pop() {
+            let hash = block.hash();
+            let node = &mut self.nodes[node];
+            match node.consensus.process_block(&block) {
+                Ok(()) => {
+                    self.stats.blocks_imported += 1;
+                    pending.extend(node.orphans.remove(&hash).unwrap_or_default());
+                }
+                Err(e) => {
+                    self.stats.blocks_rejected += 1;
+                    node.orphans.remove(&hash);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        self.nodes[node].update_head();
+        result
+    }
+
+    fn import_attestation(&mut self, node: NodeId, attestation: &Attestation) {
+        let node = &mut self.nodes[node];
+        if let Err(e) = node.consensus.process_attestation(attestation) {
+            debug!("Node {} rejected attestation: {}", node.id, e);
+            return;
+        }
+        node.update_head();
+    }
+}
@@ -2,52 +2,476 @@
 // TODO: Implement SQLite-based storage
 
 use crate::types::*;
-use std::collections::HashMap;
+use crate::crypto::{MemoryNodeStore, NodeKey, SparseMerkleProof, SparseMerkleTree, EMPTY_LEAF};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// File in the data directory holding the last full snapshot of the chain
+pub const CHAIN_FILE: &str = "chain.json";
+
+/// File in the data directory holding the changes flushed since the
+/// snapshot, one JSON record per line
+pub const CHAIN_LOG_FILE: &str = "chain.log";
 
 pub struct StorageService {
     // In-memory storage for now - would be replaced with SQLite
     blocks: HashMap<Hash, Block>,
+    block_hashes_by_height: HashMap<u64, Hash>,
     accounts: HashMap<Address, Account>,
     validators: HashMap<Address, Validator>,
+    /// Account state tree, updated as accounts are stored
+    state_tree: SparseMerkleTree,
+    /// Accounts each imported block changed, on any fork, so the state of
+    /// any known block can be rebuilt from the canonical head
+    block_changes: HashMap<Hash, Vec<AccountChange>>,
+    latest_height: u64,
+    /// Blocks containing each transaction, on any fork. Rebuilt on open.
+    transaction_blocks: HashMap<Hash, Vec<Hash>>,
+    /// Transactions sent or received by each account. Rebuilt on open.
+    account_transactions: HashMap<Address, Vec<Hash>>,
+    /// Where `flush` writes the chain; None keeps everything in memory
+    chain_file: Option<PathBuf>,
+    /// Changes since the last flush
+    unflushed: Unflushed,
+    /// Number of flushes so far, numbering the chain log records
+    flushes: u64,
+    dirty: bool,
+}
+
+/// Keys changed since the last flush. Their current values are written, so
+/// a key changed several times is written once.
+#[derive(Debug, Default)]
+struct Unflushed {
+    blocks: HashSet<Hash>,
+    heights: HashSet<u64>,
+    accounts: HashSet<Address>,
+    validators: HashSet<Address>,
+    block_changes: HashSet<Hash>,
+}
+
+/// On-disk snapshot of the storage contents
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredChain {
+    blocks: Vec<Block>,
+    block_hashes_by_height: Vec<(u64, Hash)>,
+    accounts: Vec<Account>,
+    validators: Vec<Validator>,
+    state_nodes: Vec<(NodeKey, Hash)>,
+    #[serde(default)]
+    block_changes: Vec<(Hash, Vec<AccountChange>)>,
+    latest_height: u64,
+    /// Flushes included, so log records left over from before the
+    /// snapshot are skipped
+    #[serde(default)]
+    flushes: u64,
+}
+
+/// One flush appended to the chain log. Heights mapped to None are no
+/// longer canonical and accounts mapped to None were deleted.
+#[derive(Debug, Serialize, Deserialize)]
+struct ChainLogRecord {
+    flush: u64,
+    blocks: Vec<Block>,
+    block_hashes_by_height: Vec<(u64, Option<Hash>)>,
+    accounts: Vec<(Address, Option<Account>)>,
+    validators: Vec<Validator>,
+    block_changes: Vec<(Hash, Vec<AccountChange>)>,
     latest_height: u64,
 }
 
+/// An account as it was before and after a block was applied; `before` is
+/// None for accounts the block created
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountChange {
+    pub address: Address,
+    pub before: Option<Account>,
+    pub after: Account,
+}
+
+/// Where a transaction sits in the canonical chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionLocation {
+    pub block_hash: Hash,
+    pub block_height: u64,
+    /// Position in the block's transaction list
+    pub index: usize,
+}
+
 impl StorageService {
     pub fn new() -> Self {
         StorageService {
             blocks: HashMap::new(),
+            block_hashes_by_height: HashMap::new(),
             accounts: HashMap::new(),
             validators: HashMap::new(),
+            state_tree: SparseMerkleTree::new(),
+            block_changes: HashMap::new(),
             latest_height: 0,
+            transaction_blocks: HashMap::new(),
+            account_transactions: HashMap::new(),
+            chain_file: None,
+            unflushed: Unflushed::default(),
+            flushes: 0,
+            dirty: false,
         }
     }
 
-    pub async fn store_block(&mut self, block: Block) -> Result<(), Box<dyn std::error::Error>> {
+    /// Open storage in a data directory, loading the last snapshot and
+    /// replaying the changes logged after it
+    pub fn open(data_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(data_dir)?;
+        let chain_file = data_dir.join(CHAIN_FILE);
+
+        let stored: StoredChain = if chain_file.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&chain_file)?)
+                .map_err(|e| format!("Corrupt chain file {}: {}", chain_file.display(), e))?
+        } else {
+            StoredChain::default()
+        };
+
+        let mut storage = StorageService {
+            blocks: HashMap::new(),
+            block_hashes_by_height: stored.block_hashes_by_height.into_iter().collect(),
+            accounts: stored.accounts.into_iter().map(|account| (account.address, account)).collect(),
+            validators: stored.validators.into_iter().map(|validator| (validator.address, validator)).collect(),
+            state_tree: SparseMerkleTree::with_store(MemoryNodeStore {
+                nodes: stored.state_nodes.into_iter().collect(),
+            }),
+            block_changes: stored.block_changes.into_iter().collect(),
+            latest_height: stored.latest_height,
+            transaction_blocks: HashMap::new(),
+            account_transactions: HashMap::new(),
+            chain_file: Some(chain_file),
+            unflushed: Unflushed::default(),
+            flushes: stored.flushes,
+            dirty: false,
+        };
+        for block in stored.blocks {
+            storage.insert_block(block);
+        }
+
+        // Chain files written before the state tree was stored
+        if storage.state_tree.is_empty() && !storage.accounts.is_empty() {
+            let entries: Vec<(Hash, Hash)> = storage.accounts
+                .values()
+                .map(|account| (account.address.0, account.state_hash()))
+                .collect();
+            storage.state_tree.update_batch(&entries);
+        }
+
+        storage.replay_log(&data_dir.join(CHAIN_LOG_FILE))?;
+        Ok(storage)
+    }
+
+    /// Apply the records of the chain log in order, skipping those already
+    /// in the snapshot. A record cut short by a crash during `flush` is
+    /// dropped and truncated from the file.
+    fn replay_log(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let contents = std::fs::read(path)?;
+        let complete = contents.iter().rposition(|byte| *byte == b'\n').map_or(0, |end| end + 1);
+        for line in contents[..complete].split(|byte| *byte == b'\n').filter(|line| !line.is_empty()) {
+            let record: ChainLogRecord = serde_json::from_slice(line)
+                .map_err(|e| format!("Corrupt chain log {}: {}", path.display(), e))?;
+            if record.flush > self.flushes {
+                self.flushes = record.flush;
+                self.apply_log_record(record);
+            }
+        }
+        if complete < contents.len() {
+            std::fs::OpenOptions::new().write(true).open(path)?.set_len(complete as u64)?;
+        }
+        Ok(())
+    }
+
+    fn apply_log_record(&mut self, record: ChainLogRecord) {
+        for block in record.blocks {
+            self.insert_block(block);
+        }
+        for (height, hash) in record.block_hashes_by_height {
+            match hash {
+                Some(hash) => self.block_hashes_by_height.insert(height, hash),
+                None => self.block_hashes_by_height.remove(&height),
+            };
+        }
+        self.state_tree.update_batch(&state_entries(&record.accounts));
+        for (address, account) in record.accounts {
+            match account {
+                Some(account) => self.accounts.insert(address, account),
+                None => self.accounts.remove(&address),
+            };
+        }
+        for validator in record.validators {
+            self.validators.insert(validator.address, validator);
+        }
+        self.block_changes.extend(record.block_changes);
+        self.latest_height = record.latest_height;
+    }
+
+    fn insert_block(&mut self, block: Block) -> Hash {
         let hash = block.hash();
-        self.latest_height = block.header.height;
+        if self.blocks.contains_key(&hash) {
+            return hash;
+        }
+        for transaction in &block.transactions {
+            let transaction_hash = transaction.hash();
+            self.transaction_blocks.entry(transaction_hash).or_default().push(hash);
+            for address in [transaction.from, transaction.to] {
+                let transactions = self.account_transactions.entry(address).or_default();
+                if !transactions.contains(&transaction_hash) {
+                    transactions.push(transaction_hash);
+                }
+            }
+        }
         self.blocks.insert(hash, block);
+        hash
+    }
+
+    /// Write all changes since the last flush to disk by appending them to
+    /// the chain log. Once the log outgrows the snapshot, a new snapshot is
+    /// written instead and the log removed. The snapshot is replaced
+    /// atomically so a crash never leaves it half-written.
+    pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(path) = &self.chain_file else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let log = path.with_file_name(CHAIN_LOG_FILE);
+        self.flushes += 1;
+        let snapshot_size = file_size(path);
+        if snapshot_size == 0 || file_size(&log) > snapshot_size {
+            self.write_snapshot()?;
+            if log.exists() {
+                std::fs::remove_file(&log)?;
+            }
+        } else {
+            let mut line = serde_json::to_vec(&self.log_record())?;
+            line.push(b'\n');
+            std::fs::OpenOptions::new().create(true).append(true).open(&log)?.write_all(&line)?;
+        }
+        self.unflushed = Unflushed::default();
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn write_snapshot(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(path) = &self.chain_file else {
+            return Ok(());
+        };
+        let mut block_hashes_by_height: Vec<(u64, Hash)> =
+            self.block_hashes_by_height.iter().map(|(height, hash)| (*height, *hash)).collect();
+        block_hashes_by_height.sort_unstable();
+        let stored = StoredChain {
+            blocks: self.blocks.values().cloned().collect(),
+            block_hashes_by_height,
+            accounts: self.accounts.values().cloned().collect(),
+            validators: self.validators.values().cloned().collect(),
+            state_nodes: self.state_tree.store.nodes.iter().map(|(key, hash)| (*key, *hash)).collect(),
+            block_changes: self.block_changes.iter().map(|(hash, changes)| (*hash, changes.clone())).collect(),
+            latest_height: self.latest_height,
+            flushes: self.flushes,
+        };
+
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_vec(&stored)?)?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// The current values of everything changed since the last flush
+    fn log_record(&self) -> ChainLogRecord {
+        let unflushed = &self.unflushed;
+        let mut block_hashes_by_height: Vec<(u64, Option<Hash>)> = unflushed.heights
+            .iter()
+            .map(|height| (*height, self.block_hashes_by_height.get(height).copied()))
+            .collect();
+        block_hashes_by_height.sort_unstable();
+        ChainLogRecord {
+            flush: self.flushes,
+            blocks: unflushed.blocks.iter().filter_map(|hash| self.blocks.get(hash)).cloned().collect(),
+            block_hashes_by_height,
+            accounts: unflushed.accounts
+                .iter()
+                .map(|address| (*address, self.accounts.get(address).cloned()))
+                .collect(),
+            validators: unflushed.validators.iter().filter_map(|address| self.validators.get(address)).cloned().collect(),
+            block_changes: unflushed.block_changes
+                .iter()
+                .filter_map(|hash| Some((*hash, self.block_changes.get(hash)?.clone())))
+                .collect(),
+            latest_height: self.latest_height,
+        }
+    }
+
+    /// Whether there are changes not yet written by `flush`
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Bytes used on disk by the snapshot and the chain log
+    pub fn disk_size(&self) -> u64 {
+        self.chain_file
+            .as_ref()
+            .map(|path| file_size(path) + file_size(&path.with_file_name(CHAIN_LOG_FILE)))
+            .unwrap_or(0)
+    }
+
+    pub async fn store_block(&mut self, block: Block) -> Result<(), Box<dyn std::error::Error>> {
+        let height = block.header.height;
+        let hash = self.insert_block(block);
+        self.latest_height = height;
+        self.block_hashes_by_height.insert(height, hash);
+        self.unflushed.blocks.insert(hash);
+        self.unflushed.heights.insert(height);
+        self.dirty = true;
         Ok(())
     }
 
+    /// Make the block at `height` the canonical head, forgetting canonical
+    /// heights above it after a reorg to a shorter chain
+    pub async fn set_canonical_head(&mut self, height: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let unflushed = &mut self.unflushed;
+        self.block_hashes_by_height.retain(|block_height, _| {
+            if *block_height > height {
+                unflushed.heights.insert(*block_height);
+            }
+            *block_height <= height
+        });
+        self.latest_height = height;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub async fn get_canonical_hash(&self, height: u64) -> Result<Option<Hash>, Box<dyn std::error::Error>> {
+        Ok(self.block_hashes_by_height.get(&height).copied())
+    }
+
     pub async fn get_block(&self, hash: &Hash) -> Result<Option<Block>, Box<dyn std::error::Error>> {
         Ok(self.blocks.get(hash).cloned())
     }
 
+    pub async fn get_block_by_height(&self, height: u64) -> Result<Option<Block>, Box<dyn std::error::Error>> {
+        Ok(self.block_hashes_by_height
+            .get(&height)
+            .and_then(|hash| self.blocks.get(hash))
+            .cloned())
+    }
+
+    /// A transaction included in the canonical chain, with its location
+    pub async fn get_transaction(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(Transaction, TransactionLocation)>, Box<dyn std::error::Error>> {
+        Ok(self.locate_transaction(hash))
+    }
+
+    /// Canonical transactions sent or received by `address`, newest first,
+    /// skipping `offset` and returning at most `limit`. Also returns the
+    /// total number of such transactions.
+    pub async fn get_account_transactions(
+        &self,
+        address: &Address,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<(Transaction, TransactionLocation)>, usize), Box<dyn std::error::Error>> {
+        let mut transactions: Vec<(Transaction, TransactionLocation)> = self.account_transactions
+            .get(address)
+            .into_iter()
+            .flatten()
+            .filter_map(|hash| self.locate_transaction(hash))
+            .collect();
+        transactions.sort_by_key(|(_, location)| std::cmp::Reverse((location.block_height, location.index)));
+
+        let total = transactions.len();
+        Ok((transactions.into_iter().skip(offset).take(limit).collect(), total))
+    }
+
+    fn locate_transaction(&self, hash: &Hash) -> Option<(Transaction, TransactionLocation)> {
+        self.transaction_blocks.get(hash)?.iter().find_map(|block_hash| {
+            let block = self.blocks.get(block_hash)?;
+            if self.block_hashes_by_height.get(&block.header.height) != Some(block_hash) {
+                return None;
+            }
+            let index = block.transactions.iter().position(|transaction| transaction.hash() == *hash)?;
+            Some((block.transactions[index].clone(), TransactionLocation {
+                block_hash: *block_hash,
+                block_height: block.header.height,
+                index,
+            }))
+        })
+    }
+
     pub async fn get_latest_height(&self) -> Result<u64, Box<dyn std::error::Error>> {
         Ok(self.latest_height)
     }
 
     pub async fn store_account(&mut self, account: Account) -> Result<(), Box<dyn std::error::Error>> {
-        self.accounts.insert(account.address, account);
+        self.store_accounts(vec![account]).await
+    }
+
+    /// Store several accounts, updating the state tree in one batch
+    pub async fn store_accounts(&mut self, accounts: Vec<Account>) -> Result<(), Box<dyn std::error::Error>> {
+        self.set_accounts(accounts.into_iter().map(|account| (account.address, Some(account))).collect()).await
+    }
+
+    /// Store or, for None, delete accounts, updating the state tree in one
+    /// batch
+    pub async fn set_accounts(&mut self, accounts: Vec<(Address, Option<Account>)>) -> Result<(), Box<dyn std::error::Error>> {
+        self.state_tree.update_batch(&state_entries(&accounts));
+        for (address, account) in accounts {
+            self.unflushed.accounts.insert(address);
+            match account {
+                Some(account) => self.accounts.insert(address, account),
+                None => self.accounts.remove(&address),
+            };
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Record the accounts a block changed
+    pub async fn store_block_changes(&mut self, hash: Hash, changes: Vec<AccountChange>) -> Result<(), Box<dyn std::error::Error>> {
+        self.block_changes.insert(hash, changes);
+        self.unflushed.block_changes.insert(hash);
+        self.dirty = true;
         Ok(())
     }
 
+    pub async fn get_block_changes(&self, hash: &Hash) -> Result<Option<Vec<AccountChange>>, Box<dyn std::error::Error>> {
+        Ok(self.block_changes.get(hash).cloned())
+    }
+
+    /// Root of the account state tree
+    pub fn state_root(&self) -> Hash {
+        self.state_tree.root()
+    }
+
+    /// State root after storing or deleting `accounts`, without changing
+    /// storage
+    pub fn state_root_after(&self, accounts: &[(Address, Option<Account>)]) -> Hash {
+        self.state_tree.root_after(&state_entries(accounts))
+    }
+
+    /// Proof of an account's presence (or absence) for light clients
+    pub fn account_proof(&self, address: &Address) -> SparseMerkleProof {
+        self.state_tree.get_proof(&address.0)
+    }
+
     pub async fn get_account(&self, address: &Address) -> Result<Option<Account>, Box<dyn std::error::Error>> {
         Ok(self.accounts.get(address).cloned())
     }
 
     pub async fn store_validator(&mut self, validator: Validator) -> Result<(), Box<dyn std::error::Error>> {
+        self.unflushed.validators.insert(validator.address);
         self.validators.insert(validator.address, validator);
+        self.dirty = true;
         Ok(())
     }
 
@@ -56,6 +480,19 @@ impl StorageService {
     }
 }
 
+/// Size of a file, or 0 if it does not exist
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+/// State tree leaves for accounts; deleted accounts get the empty leaf
+fn state_entries(accounts: &[(Address, Option<Account>)]) -> Vec<(Hash, Hash)> {
+    accounts
+        .iter()
+        .map(|(address, account)| (address.0, account.as_ref().map_or(EMPTY_LEAF, Account::state_hash)))
+        .collect()
+}
+
 impl Default for StorageService {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,596 @@
+// Sync module - downloads missing blocks from peers and imports them through consensus
+
+use crate::consensus::ConsensusEngine;
+use crate::network::{NetworkConfig, PeerAction, StatusMessage, SyncClient, SyncRequest, SyncResponse, MAX_BLOCKS_PER_REQUEST};
+use crate::storage::StorageService;
+use crate::types::*;
+use libp2p::PeerId;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Number of failed requests after which a peer is no longer used
+pub const MAX_PEER_FAILURES: u32 = 3;
+
+/// Failed or rate limited requests after which a sync gives up
+pub const MAX_SYNC_RETRIES: u32 = 64;
+
+/// Longest wait honoured when a peer rate limits our requests
+pub const MAX_SYNC_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Batches queued, in flight or downloaded at once; later heights are
+/// queued as earlier batches are imported
+pub const SYNC_WINDOW_BATCHES: usize = 16;
+
+/// A contiguous range of block heights requested from one peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Batch {
+    pub start: u64,
+    pub count: u64,
+}
+
+impl Batch {
+    pub fn request(&self) -> SyncRequest {
+        SyncRequest::BlocksByRange {
+            start: self.start,
+            count: self.count,
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.start.saturating_add(self.count)
+    }
+}
+
+pub struct SyncManager {
+    network_id: NetworkId,
+    batch_size: u64,
+    peers: HashMap<PeerId, StatusMessage>,
+    peer_failures: HashMap<PeerId, u32>,
+    bad_peers: HashSet<PeerId>,
+    pending_reports: Vec<PeerId>,
+    queued: BTreeSet<Batch>,
+    in_flight: HashMap<Batch, PeerId>,
+    downloaded: BTreeMap<u64, (Batch, PeerId, Vec<Block>)>,
+    processed_height: u64,
+    last_hash: Hash,
+    target_height: u64,
+    /// Lowest height not yet queued
+    next_height: u64,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl SyncManager {
+    pub fn new(network_id: NetworkId, batch_size: u64) -> Self {
+        SyncManager {
+            network_id,
+            batch_size: batch_size.clamp(1, MAX_BLOCKS_PER_REQUEST),
+            peers: HashMap::new(),
+            peer_failures: HashMap::new(),
+            bad_peers: HashSet::new(),
+            pending_reports: Vec::new(),
+            queued: BTreeSet::new(),
+            in_flight: HashMap::new(),
+            downloaded: BTreeMap::new(),
+            processed_height: 0,
+            last_hash: [0u8; 32],
+            target_height: 0,
+            next_height: 1,
+            retries: 0,
+            backoff: Duration::ZERO,
+        }
+    }
+
+    /// Register a peer's status. Returns false if the peer is on another
+    /// network, was previously found to serve bad data, or claims a head
+    /// beyond `current_slot`: every block is at a later slot than its
+    /// parent, so no honest chain can be higher than the wall-clock slot.
+    pub fn add_peer(&mut self, peer_id: PeerId, status: StatusMessage, current_slot: Slot) -> bool {
+        if status.network_id != self.network_id || self.bad_peers.contains(&peer_id) {
+            return false;
+        }
+        if status.head_height > current_slot {
+            warn!("Peer {} claims head height {} beyond slot {}", peer_id, status.head_height, current_slot);
+            return false;
+        }
+
+        self.target_height = self.target_height.max(status.head_height);
+        self.peers.insert(peer_id, status);
+        true
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+
+        let orphaned: Vec<Batch> = self.in_flight
+            .iter()
+            .filter(|(_, peer)| *peer == peer_id)
+            .map(|(batch, _)| *batch)
+            .collect();
+        for batch in orphaned {
+            self.in_flight.remove(&batch);
+            self.queued.insert(batch);
+        }
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn processed_height(&self) -> u64 {
+        self.processed_height
+    }
+
+    pub fn target_height(&self) -> u64 {
+        self.target_height
+    }
+
+    /// Sync from just above the local head up to the best known peer head
+    pub fn start(&mut self, local_height: u64, local_head: Hash) {
+        self.processed_height = local_height;
+        self.last_hash = local_head;
+        self.queued.clear();
+        self.in_flight.clear();
+        self.downloaded.clear();
+        self.next_height = local_height.saturating_add(1);
+        self.fill_window();
+
+        info!("Syncing from height {} to {}", local_height, self.target_height);
+    }
+
+    /// Queue the next batches up to the target, keeping at most
+    /// `SYNC_WINDOW_BATCHES` outstanding
+    fn fill_window(&mut self) {
+        while self.next_height <= self.target_height
+            && self.queued.len() + self.in_flight.len() + self.downloaded.len() < SYNC_WINDOW_BATCHES
+        {
+            let count = self.batch_size.min(self.target_height - self.next_height + 1);
+            self.queued.insert(Batch { start: self.next_height, count });
+            self.next_height += count;
+        }
+    }
+
+    /// Whether so many requests failed that the sync should give up
+    pub fn retries_exhausted(&self) -> bool {
+        self.retries >= MAX_SYNC_RETRIES
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.processed_height >= self.target_height
+            && self.next_height > self.target_height
+            && self.queued.is_empty()
+            && self.in_flight.is_empty()
+            && self.downloaded.is_empty()
+    }
+
+    /// Assign queued batches to idle peers, at most one batch per peer
+    pub fn next_requests(&mut self) -> Vec<(PeerId, Batch)> {
+        self.fill_window();
+        let busy: HashSet<PeerId> = self.in_flight.values().copied().collect();
+        let mut idle: Vec<(PeerId, u64)> = self.peers
+            .iter()
+            .filter(|(peer_id, _)| !busy.contains(peer_id))
+            .map(|(peer_id, status)| (*peer_id, status.head_height))
+            .collect();
+        // Prefer peers with the highest head
+        idle.sort_by_key(|(_, head)| std::cmp::Reverse(*head));
+
+        let mut requests = Vec::new();
+        let queued: Vec<Batch> = self.queued.iter().copied().collect();
+        for batch in queued {
+            let position = idle.iter().position(|(_, head)| *head >= batch.end() - 1);
+            if let Some(position) = position {
+                let (peer_id, _) = idle.remove(position);
+                self.queued.remove(&batch);
+                self.in_flight.insert(batch, peer_id);
+                requests.push((peer_id, batch));
+            }
+        }
+
+        requests
+    }
+
+    /// Record the outcome of a batch request
+    pub fn on_response(
+        &mut self,
+        peer_id: PeerId,
+        batch: Batch,
+        response: Result<SyncResponse, Box<dyn Error + Send + Sync>>,
+    ) {
+        if self.in_flight.get(&batch) != Some(&peer_id) {
+            debug!("Ignoring unexpected sync response from {}", peer_id);
+            return;
+        }
+        self.in_flight.remove(&batch);
+
+        match response {
+            Ok(SyncResponse::Blocks(blocks)) => match Self::check_batch(&batch, &blocks) {
+                Ok(()) => {
+                    let received = blocks.len() as u64;
+                    if received < batch.count {
+                        // Request the heights the peer did not serve
+                        self.queued.insert(Batch {
+                            start: batch.start + received,
+                            count: batch.count - received,
+                        });
+                    }
+                    let served = Batch { start: batch.start, count: received };
+                    self.downloaded.insert(batch.start, (served, peer_id, blocks));
+                }
+                Err(reason) => {
+                    self.penalize_peer(peer_id, &reason);
+                    self.queued.insert(batch);
+                }
+            },
+            Ok(SyncResponse::Error(reason)) => {
+                debug!("Peer {} could not serve batch {:?}: {}", peer_id, batch, reason);
+                self.record_failure(peer_id);
+                self.queued.insert(batch);
+            }
+            Ok(SyncResponse::RateLimited { retry_after_ms }) => {
+                debug!("Peer {} rate limited batch {:?}, retrying in {}ms", peer_id, batch, retry_after_ms);
+                self.backoff = self.backoff.max(Duration::from_millis(retry_after_ms).min(MAX_SYNC_BACKOFF));
+                self.record_failure(peer_id);
+                self.queued.insert(batch);
+            }
+            Ok(SyncResponse::Status(_)) => {
+                self.penalize_peer(peer_id, "status returned for a block request");
+                self.queued.insert(batch);
+            }
+            Err(e) => {
+                debug!("Sync request to {} failed: {}", peer_id, e);
+                self.record_failure(peer_id);
+                self.queued.insert(batch);
+            }
+        }
+    }
+
+    /// Import downloaded batches that extend the processed chain, returning
+    /// the blocks imported
+    pub fn process_ready(&mut self, consensus: &mut ConsensusEngine) -> Vec<Block> {
+        let mut imported = Vec::new();
+
+        while let Some((batch, peer_id, blocks)) = self.downloaded.remove(&(self.processed_height + 1)) {
+            if blocks[0].header.previous_hash != self.last_hash {
+                self.penalize_peer(peer_id, "batch does not extend the local chain");
+                self.queued.insert(batch);
+                break;
+            }
+
+            for (i, block) in blocks.iter().enumerate() {
+                if let Err(e) = consensus.process_block(block) {
+                    self.penalize_peer(peer_id, &format!("invalid block at height {}: {}", block.header.height, e));
+                    let remaining = Batch {
+                        start: block.header.height,
+                        count: batch.end() - block.header.height,
+                    };
+                    self.queued.insert(remaining);
+                    imported.extend_from_slice(&blocks[..i]);
+                    return imported;
+                }
+
+                self.processed_height = block.header.height;
+                self.last_hash = block.hash();
+            }
+            imported.extend(blocks);
+        }
+
+        imported
+    }
+
+    /// Peers that returned invalid data. They stay excluded from this sync
+    /// and are reported to the network once.
+    pub fn bad_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.bad_peers.iter()
+    }
+
+    /// Download blocks until caught up with the best peer. Blocks are
+    /// checked against `consensus`, which is usually a copy of the node's,
+    /// and sent to `imported` for the node to import.
+    pub async fn sync(
+        &mut self,
+        network: &SyncClient,
+        consensus: &mut ConsensusEngine,
+        imported: &mpsc::Sender<Block>,
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let mut count = 0;
+
+        while !self.is_complete() {
+            if self.retries_exhausted() {
+                return Err(format!("Giving up after {} failed requests", self.retries).into());
+            }
+            let requests = self.next_requests();
+            if requests.is_empty() {
+                return Err("No peers available to sync from".into());
+            }
+
+            let responses = futures::future::join_all(requests.into_iter().map(|(peer_id, batch)| async move {
+                (peer_id, batch, network.send_sync_request(peer_id, batch.request()).await)
+            }))
+            .await;
+
+            for (peer_id, batch, response) in responses {
+                self.on_response(peer_id, batch, response);
+            }
+
+            for block in self.process_ready(consensus) {
+                imported.send(block).await.map_err(|_| "Node stopped importing synced blocks")?;
+                count += 1;
+            }
+
+            let backoff = std::mem::take(&mut self.backoff);
+            if !backoff.is_zero() {
+                tokio::time::sleep(backoff).await;
+            }
+
+            for peer_id in std::mem::take(&mut self.pending_reports) {
+                if let Err(e) = network.report_peer(peer_id, PeerAction::BadSyncResponse).await {
+                    warn!("Failed to report sync peer {}: {}", peer_id, e);
+                }
+            }
+        }
+
+        info!("Sync complete at height {}", self.processed_height);
+        Ok(count)
+    }
+
+    fn check_batch(batch: &Batch, blocks: &[Block]) -> Result<(), String> {
+        if blocks.is_empty() || blocks.len() as u64 > batch.count {
+            return Err(format!("expected 1 to {} blocks, got {}", batch.count, blocks.len()));
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            if block.header.height != batch.start + i as u64 {
+                return Err(format!("unexpected block height {}", block.header.height));
+            }
+            if i > 0 && block.header.previous_hash != blocks[i - 1].hash() {
+                return Err(format!("block at height {} does not link to its parent", block.header.height));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_failure(&mut self, peer_id: PeerId) {
+        self.retries += 1;
+        let failures = self.peer_failures.entry(peer_id).or_insert(0);
+        *failures += 1;
+        if *failures >= MAX_PEER_FAILURES {
+            warn!("Dropping sync peer {} after {} failed requests", peer_id, failures);
+            self.remove_peer(&peer_id);
+        }
+    }
+
+    fn penalize_peer(&mut self, peer_id: PeerId, reason: &str) {
+        self.retries += 1;
+        warn!("Sync peer {} returned bad data: {}", peer_id, reason);
+        if self.bad_peers.insert(peer_id) {
+            self.pending_reports.push(peer_id);
+        }
+        self.remove_peer(&peer_id);
+    }
+}
+
+/// Chain status of the local node
+pub fn local_status(consensus: &ConsensusEngine, config: &NetworkConfig) -> StatusMessage {
+    let head_hash = consensus.get_head().unwrap_or([0u8; 32]);
+    let head_height = consensus.fork_choice
+        .get_block(&head_hash)
+        .map(|block| block.header.height)
+        .unwrap_or(0);
+
+    config.local_status(head_hash, head_height, consensus.fork_choice.finalized_checkpoint.clone())
+}
+
+/// Serve a sync request from local storage
+pub async fn handle_sync_request(
+    request: SyncRequest,
+    storage: &StorageService,
+    consensus: &ConsensusEngine,
+    config: &NetworkConfig,
+) -> SyncResponse {
+    if let Err(reason) = request.validate() {
+        return SyncResponse::Error(reason);
+    }
+
+    match request {
+        SyncRequest::Status(_) => SyncResponse::Status(local_status(consensus, config)),
+        SyncRequest::BlocksByRange { start, count } => {
+            let mut blocks = Vec::new();
+            for height in start..start.saturating_add(count) {
+                match storage.get_block_by_height(height).await {
+                    Ok(Some(block)) => blocks.push(block),
+                    Ok(None) => break,
+                    Err(e) => return SyncResponse::Error(e.to_string()),
+                }
+            }
+            SyncResponse::Blocks(blocks)
+        }
+        SyncRequest::BlocksByRoot { hashes } => {
+            let mut blocks = Vec::new();
+            for hash in &hashes {
+                match storage.get_block(hash).await {
+                    Ok(Some(block)) => blocks.push(block),
+                    Ok(None) => {}
+                    Err(e) => return SyncResponse::Error(e.to_string()),
+                }
+            }
+            SyncResponse::Blocks(blocks)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::test_utils::engine_with_validators;
+
+    fn setup() -> (ConsensusEngine, Vec<KeyPair>) {
+        let keypairs: Vec<KeyPair> = (0..3).map(|_| KeyPair::generate()).collect();
+        (engine_with_validators(&keypairs), keypairs)
+    }
+
+    fn build_chain(consensus: &ConsensusEngine, keypairs: &[KeyPair], length: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut previous_hash = [0u8; 32];
+        for height in 1..=length {
+            let proposer = consensus.get_proposer_for_slot(height).unwrap();
+            let keypair = keypairs.iter().find(|kp| kp.address == proposer).unwrap();
+            let mut block = Block::new(height, previous_hash, [0u8; 32], height, consensus.slot_to_epoch(height), proposer, Vec::new(), [0u8; 32], 1_000_000);
+            block.sign(&keypair.signing_key());
+            previous_hash = block.hash();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn status(head_height: u64) -> StatusMessage {
+        StatusMessage {
+            network_id: NetworkId::Devnet,
+            genesis_root: [0u8; 32],
+            fork_version: [0u8; 4],
+            head_hash: [0u8; 32],
+            head_height,
+            finalized_checkpoint: Checkpoint { epoch: 0, root: [0u8; 32] },
+        }
+    }
+
+    fn serve(chain: &[Block], batch: &Batch) -> SyncResponse {
+        let start = batch.start as usize - 1;
+        SyncResponse::Blocks(chain[start..start + batch.count as usize].to_vec())
+    }
+
+    #[test]
+    fn test_sync_across_peers_out_of_order() {
+        let (mut consensus, keypairs) = setup();
+        let chain = build_chain(&consensus, &keypairs, 10);
+
+        let mut manager = SyncManager::new(NetworkId::Devnet, 4);
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        assert!(manager.add_peer(peer_a, status(10), 10));
+        assert!(manager.add_peer(peer_b, status(10), 10));
+        manager.start(0, [0u8; 32]);
+
+        let requests = manager.next_requests();
+        assert_eq!(requests.len(), 2);
+        assert_ne!(requests[0].0, requests[1].0);
+
+        // Deliver the later batch first; nothing can be imported yet
+        for (peer_id, batch) in requests.iter().rev() {
+            manager.on_response(*peer_id, *batch, Ok(serve(&chain, batch)));
+            if batch.start != 1 {
+                assert!(manager.process_ready(&mut consensus).is_empty());
+            }
+        }
+        assert_eq!(manager.process_ready(&mut consensus).len(), 8);
+
+        let requests = manager.next_requests();
+        assert_eq!(requests.len(), 1);
+        let (peer_id, batch) = requests[0];
+        manager.on_response(peer_id, batch, Ok(serve(&chain, &batch)));
+        assert_eq!(manager.process_ready(&mut consensus).len(), 2);
+
+        assert!(manager.is_complete());
+        assert_eq!(consensus.get_head(), Some(chain[9].hash()));
+    }
+
+    #[test]
+    fn test_bad_peer_is_dropped_and_batch_retried() {
+        let (mut consensus, keypairs) = setup();
+        let chain = build_chain(&consensus, &keypairs, 4);
+
+        let mut manager = SyncManager::new(NetworkId::Devnet, 4);
+        let bad_peer = PeerId::random();
+        let good_peer = PeerId::random();
+        manager.add_peer(bad_peer, status(4), 4);
+        manager.start(0, [0u8; 32]);
+
+        let (peer_id, batch) = manager.next_requests()[0];
+        let mut tampered = chain.clone();
+        tampered[2].header.gas_used = 1;
+        manager.on_response(peer_id, batch, Ok(SyncResponse::Blocks(tampered)));
+        assert_eq!(manager.peer_count(), 0);
+        assert!(manager.bad_peers().any(|peer| *peer == bad_peer));
+        assert!(!manager.add_peer(bad_peer, status(4), 4));
+
+        manager.add_peer(good_peer, status(4), 4);
+        let (peer_id, batch) = manager.next_requests()[0];
+        assert_eq!(peer_id, good_peer);
+        manager.on_response(peer_id, batch, Ok(serve(&chain, &batch)));
+        assert_eq!(manager.process_ready(&mut consensus).len(), 4);
+        assert!(manager.is_complete());
+    }
+
+    #[test]
+    fn test_rejects_peer_from_other_network() {
+        let mut manager = SyncManager::new(NetworkId::Devnet, 4);
+        let mut other = status(10);
+        other.network_id = NetworkId::Mainnet;
+        assert!(!manager.add_peer(PeerId::random(), other, 10));
+        assert_eq!(manager.target_height(), 0);
+    }
+
+    #[test]
+    fn test_rate_limited_batches_count_as_failures() {
+        let mut manager = SyncManager::new(NetworkId::Devnet, 4);
+        let peer = PeerId::random();
+        assert!(manager.add_peer(peer, status(4), 4));
+        manager.start(0, [0u8; 32]);
+
+        for _ in 0..MAX_PEER_FAILURES {
+            let (peer_id, batch) = manager.next_requests()[0];
+            manager.on_response(peer_id, batch, Ok(SyncResponse::RateLimited { retry_after_ms: 60_000 }));
+        }
+
+        // The peer is dropped from this sync but not reported as bad
+        assert_eq!(manager.peer_count(), 0);
+        assert_eq!(manager.bad_peers().count(), 0);
+        assert_eq!(manager.backoff, MAX_SYNC_BACKOFF);
+        assert!(manager.next_requests().is_empty());
+    }
+
+    #[test]
+    fn test_retries_are_capped_across_peers() {
+        let mut manager = SyncManager::new(NetworkId::Devnet, 4);
+        manager.start(0, [0u8; 32]);
+
+        while !manager.retries_exhausted() {
+            manager.add_peer(PeerId::random(), status(4), 4);
+            let (peer_id, batch) = manager.next_requests()[0];
+            manager.on_response(peer_id, batch, Ok(SyncResponse::Error("unavailable".to_string())));
+        }
+        assert_eq!(manager.retries, MAX_SYNC_RETRIES);
+        assert!(!manager.is_complete());
+    }
+
+    #[test]
+    fn test_rejects_head_beyond_current_slot() {
+        let mut manager = SyncManager::new(NetworkId::Devnet, 4);
+        assert!(!manager.add_peer(PeerId::random(), status(u64::MAX), 100));
+        assert_eq!(manager.target_height(), 0);
+
+        assert!(manager.add_peer(PeerId::random(), status(100), 100));
+        assert_eq!(manager.target_height(), 100);
+    }
+
+    #[test]
+    fn test_batches_are_queued_a_window_at_a_time() {
+        let mut manager = SyncManager::new(NetworkId::Devnet, 4);
+        let current_slot = 1_000_000;
+        assert!(manager.add_peer(PeerId::random(), status(current_slot), current_slot));
+        manager.start(0, [0u8; 32]);
+        assert_eq!(manager.queued.len(), SYNC_WINDOW_BATCHES);
+
+        // A lost batch frees a slot in the window for the next heights
+        let (peer_id, batch) = manager.next_requests()[0];
+        manager.remove_peer(&peer_id);
+        assert!(manager.queued.contains(&batch));
+        assert_eq!(manager.queued.len(), SYNC_WINDOW_BATCHES);
+        assert!(!manager.is_complete());
+    }
+}
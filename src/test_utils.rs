@@ -0,0 +1,30 @@
+// Helpers shared by unit tests
+
+use crate::consensus::ConsensusEngine;
+use crate::crypto::KeyPair;
+use crate::types::*;
+
+/// Stake given to every validator built by `engine_with_validators`
+pub const STAKE: Amount = 32_000_000_000;
+
+/// Consensus engine with four-slot epochs whose validator set holds `keys`,
+/// each staking `STAKE`
+pub fn engine_with_validators(keys: &[KeyPair]) -> ConsensusEngine {
+    let config = ConsensusConfig {
+        slots_per_epoch: 4,
+        ..ConsensusConfig::default()
+    };
+    let validators = keys
+        .iter()
+        .map(|key| {
+            let metadata = ValidatorMetadata {
+                name: key.address.to_string(),
+                website: None,
+                description: None,
+                contact: None,
+            };
+            Validator::new(key.address, key.public_key, STAKE, 0, 0, metadata)
+        })
+        .collect();
+    ConsensusEngine::new(config, validators).unwrap()
+}
@@ -0,0 +1,197 @@
+// Testnet module - generates the keystores, genesis file and node configs
+// of a local multi-node network
+
+use crate::config::NodeConfig;
+use crate::crypto::KeyPair;
+use crate::genesis::GenesisSpec;
+use crate::network::{self, IDENTITY_FILE};
+use crate::types::{Address, Amount, Hash, NetworkId};
+use crate::validator::keystore;
+use anyhow::{anyhow, Result};
+use libp2p::PeerId;
+use std::path::{Path, PathBuf};
+
+/// Genesis file inside a testnet directory
+pub const GENESIS_FILE: &str = "genesis.json";
+
+/// Config file inside each node directory
+pub const NODE_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone)]
+pub struct TestnetOptions {
+    pub validators: usize,
+    pub nodes: usize,
+    pub network_id: NetworkId,
+    /// P2P port of node 0; node `i` listens on `base_port + i`
+    pub base_port: u16,
+    /// API port of node 0
+    pub base_api_port: u16,
+    /// Metrics port of node 0
+    pub base_metrics_port: u16,
+    pub seconds_per_slot: u64,
+    /// Seconds from now until genesis, leaving time to start the nodes
+    pub genesis_delay: u64,
+    pub stake: Amount,
+    /// Genesis balance of every validator's account
+    pub balance: Amount,
+    /// Password to encrypt the validator keystores with. Without one the
+    /// keys are stored unencrypted, which is only fit for local dev
+    /// networks. It is not written to the node configs; the nodes read it
+    /// from `POS_VALIDATOR__KEYSTORE_PASSWORD`.
+    pub keystore_password: Option<String>,
+}
+
+impl Default for TestnetOptions {
+    fn default() -> Self {
+        Self {
+            validators: 4,
+            nodes: 3,
+            network_id: NetworkId::Devnet,
+            base_port: 9000,
+            base_api_port: 8080,
+            base_metrics_port: 9090,
+            seconds_per_slot: 6,
+            genesis_delay: 30,
+            stake: 32_000_000_000,
+            balance: 1_000_000_000_000,
+            keystore_password: None,
+        }
+    }
+}
+
+/// A generated testnet
+#[derive(Debug, Clone)]
+pub struct Testnet {
+    pub genesis_file: PathBuf,
+    pub genesis_hash: Hash,
+    pub nodes: Vec<TestnetNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestnetNode {
+    pub dir: PathBuf,
+    pub config_file: PathBuf,
+    pub config: NodeConfig,
+    /// Network identity saved in the node's data directory
+    pub peer_id: PeerId,
+    /// Validators whose keystores this node holds
+    pub validators: Vec<Address>,
+}
+
+impl TestnetOptions {
+    fn validate(&self) -> Result<()> {
+        if self.nodes == 0 || self.validators == 0 {
+            return Err(anyhow!("A testnet needs at least one node and one validator"));
+        }
+        for (name, base) in [("p2p", self.base_port), ("API", self.base_api_port), ("metrics", self.base_metrics_port)] {
+            if base as usize + self.nodes > u16::MAX as usize {
+                return Err(anyhow!("Not enough {} ports above {} for {} nodes", name, base, self.nodes));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Generate a testnet in `out_dir`: a shared genesis file, and for each
+/// node a directory with its config, keystores and data directory.
+/// Validators are assigned to nodes round-robin and every node bootstraps
+/// from all the others. Each node's network identity is generated up front
+/// so bootstrap addresses can name the expected peer.
+pub fn init(out_dir: &Path, options: &TestnetOptions) -> Result<Testnet> {
+    options.validate()?;
+    if out_dir.exists() && std::fs::read_dir(out_dir)?.next().is_some() {
+        return Err(anyhow!("{} is not empty", out_dir.display()));
+    }
+    std::fs::create_dir_all(out_dir)?;
+    // Configs refer to the genesis file and keystores by absolute path so
+    // nodes can be started from any directory
+    let out_dir = out_dir.canonicalize()?;
+
+    let genesis_time = chrono::Utc::now().timestamp() as u64 + options.genesis_delay;
+    let mut spec = GenesisSpec::new(options.network_id, genesis_time);
+    spec.set_consensus("seconds_per_slot", serde_json::json!(options.seconds_per_slot));
+
+    let mut nodes: Vec<TestnetNode> = (0..options.nodes)
+        .map(|index| {
+            let dir = out_dir.join(format!("node-{}", index));
+            let identity = libp2p::identity::Keypair::generate_ed25519();
+            network::write_identity(&dir.join("data").join("network").join(IDENTITY_FILE), &identity)
+                .map_err(|e| anyhow!("Failed to write network identity: {}", e))?;
+            Ok(TestnetNode {
+                config_file: dir.join(NODE_CONFIG_FILE),
+                dir,
+                config: NodeConfig::default(),
+                peer_id: identity.public().to_peer_id(),
+                validators: Vec::new(),
+            })
+        })
+        .collect::<Result<_>>()?;
+    let peer_ids: Vec<PeerId> = nodes.iter().map(|node| node.peer_id).collect();
+
+    for index in 0..options.validators {
+        let keypair = KeyPair::generate();
+        spec.add_validator(keypair.public_key, options.stake, &format!("validator-{}", index));
+        spec.add_account(keypair.address, options.balance);
+
+        let node = &mut nodes[index % options.nodes];
+        let keystore_dir = node.dir.join("keystores");
+        std::fs::create_dir_all(&keystore_dir)?;
+        keystore::write_keystore(&keystore_dir.join(keystore::keystore_file_name(&keypair.address)), &keypair, options.keystore_password.as_deref())
+            .map_err(|e| anyhow!("Failed to write keystore: {}", e))?;
+        node.validators.push(keypair.address);
+    }
+
+    let genesis_file = out_dir.join(GENESIS_FILE);
+    spec.save_to_file(&genesis_file)?;
+    let genesis_hash = spec.build()?.hash();
+
+    for (index, node) in nodes.iter_mut().enumerate() {
+        let config = &mut node.config;
+        config.network.network_id = options.network_id;
+        config.network.listen_address = "127.0.0.1".to_string();
+        config.network.port = options.base_port + index as u16;
+        config.network.enable_mdns = false;
+        config.network.bootstrap_peers = (0..options.nodes)
+            .filter(|other| *other != index)
+            .map(|other| format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", options.base_port + other as u16, peer_ids[other]).parse())
+            .collect::<Result<_, _>>()?;
+        config.network.genesis_file = Some(genesis_file.clone());
+        config.network.genesis_hash = Some(hex::encode(genesis_hash));
+        config.storage.data_dir = node.dir.join("data");
+        config.api.listen_address = format!("127.0.0.1:{}", options.base_api_port + index as u16);
+        config.metrics.listen_address = format!("127.0.0.1:{}", options.base_metrics_port + index as u16);
+        if !node.validators.is_empty() {
+            config.validator.enabled = true;
+            config.validator.keystore_dir = Some(node.dir.join("keystores"));
+        }
+
+        std::fs::create_dir_all(&node.dir)?;
+        config
+            .save_to_file(&node.config_file)
+            .map_err(|e| anyhow!("Failed to write {}: {}", node.config_file.display(), e))?;
+    }
+
+    Ok(Testnet {
+        genesis_file,
+        genesis_hash,
+        nodes,
+    })
+}
+
+/// Config files of the nodes in a testnet directory, in node order
+pub fn node_config_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut nodes: Vec<(usize, PathBuf)> = std::fs::read_dir(dir)
+        .map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            let index = path.file_name()?.to_str()?.strip_prefix("node-")?.parse().ok()?;
+            let config_file = path.join(NODE_CONFIG_FILE);
+            config_file.is_file().then_some((index, config_file))
+        })
+        .collect();
+    if nodes.is_empty() {
+        return Err(anyhow!("No node configs found in {}", dir.display()));
+    }
+    nodes.sort();
+    Ok(nodes.into_iter().map(|(_, config_file)| config_file).collect())
+}
@@ -1,5 +1,9 @@
-use super::{Address, Amount, Nonce};
+use super::{Address, Amount, Hash, Nonce, PublicKey};
+use crate::crypto::SparseMerkleTree;
+use crate::types::block::Block;
+use crate::types::transaction::{Transaction, TransactionPayload};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -9,6 +13,60 @@ pub struct Account {
     pub nonce: Nonce,
     pub code: Vec<u8>, // For smart contracts
     pub storage: HashMap<[u8; 32], [u8; 32]>, // Contract storage
+    #[serde(default)]
+    pub kind: AccountKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccountKind {
+    /// Controlled by the single key the address was derived from
+    #[default]
+    Standard,
+    /// Controlled by k-of-n signers
+    Multisig(MultisigPolicy),
+}
+
+/// Maximum number of signers on a multisig account
+pub const MAX_MULTISIG_SIGNERS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigPolicy {
+    pub signers: Vec<PublicKey>,
+    pub threshold: usize,
+}
+
+impl MultisigPolicy {
+    pub fn new(signers: Vec<PublicKey>, threshold: usize) -> Result<Self, String> {
+        if signers.is_empty() || signers.len() > MAX_MULTISIG_SIGNERS {
+            return Err(format!("Signer set must contain 1 to {} keys", MAX_MULTISIG_SIGNERS));
+        }
+
+        if threshold == 0 || threshold > signers.len() {
+            return Err("Threshold must be between 1 and the number of signers".to_string());
+        }
+
+        for (i, signer) in signers.iter().enumerate() {
+            if signers[..i].contains(signer) {
+                return Err("Duplicate signer".to_string());
+            }
+        }
+
+        Ok(MultisigPolicy { signers, threshold })
+    }
+
+    /// Address of a new multisig account, derived from its initial policy
+    pub fn derive_address(&self) -> Address {
+        let mut signers = self.signers.clone();
+        signers.sort();
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"multisig");
+        hasher.update((self.threshold as u64).to_le_bytes());
+        for signer in &signers {
+            hasher.update(signer);
+        }
+        Address(hasher.finalize().into())
+    }
 }
 
 impl Account {
@@ -19,6 +77,21 @@ impl Account {
             nonce: 0,
             code: Vec::new(),
             storage: HashMap::new(),
+            kind: AccountKind::Standard,
+        }
+    }
+
+    pub fn new_multisig(address: Address, balance: Amount, policy: MultisigPolicy) -> Self {
+        Account {
+            kind: AccountKind::Multisig(policy),
+            ..Self::new(address, balance)
+        }
+    }
+
+    pub fn multisig_policy(&self) -> Option<&MultisigPolicy> {
+        match &self.kind {
+            AccountKind::Multisig(policy) => Some(policy),
+            AccountKind::Standard => None,
         }
     }
 
@@ -41,6 +114,32 @@ impl Account {
     pub fn credit(&mut self, amount: Amount) {
         self.balance += amount;
     }
+
+    /// Leaf value of this account in the state tree
+    pub fn state_hash(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(self.address.0);
+        hasher.update(self.balance.to_le_bytes());
+        hasher.update(self.nonce.to_le_bytes());
+        hasher.update(Sha256::digest(&self.code));
+
+        // Storage is a HashMap, so sort slots for a deterministic hash
+        let mut slots: Vec<_> = self.storage.iter().collect();
+        slots.sort();
+        for (slot, value) in slots {
+            hasher.update(slot);
+            hasher.update(value);
+        }
+
+        if let Some(policy) = self.multisig_policy() {
+            hasher.update((policy.threshold as u64).to_le_bytes());
+            for signer in &policy.signers {
+                hasher.update(signer);
+            }
+        }
+
+        hasher.finalize().into()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -82,6 +181,77 @@ impl AccountState {
         self.total_supply += initial_balance;
     }
 
+    pub fn create_multisig_account(&mut self, policy: MultisigPolicy, initial_balance: Amount) -> Address {
+        let address = policy.derive_address();
+        self.accounts.insert(address, Account::new_multisig(address, initial_balance, policy));
+        self.total_supply += initial_balance;
+        address
+    }
+
+    /// Check that a transaction is authorized by its sender. Multisig senders
+    /// need signatures from at least `threshold` members of their signer set.
+    /// Standard senders need `Transaction.signature` from the key behind
+    /// their address, so only the owner can transfer from the account or
+    /// turn it into a multisig account.
+    pub fn authorize_transaction(&self, transaction: &Transaction) -> Result<(), String> {
+        let sender = self.accounts.get(&transaction.from).ok_or("Sender account not found")?;
+
+        match &sender.kind {
+            AccountKind::Multisig(policy) => transaction.verify_multisig(&policy.signers, policy.threshold),
+            AccountKind::Standard => {
+                if transaction.multisig.is_some() {
+                    return Err("Multisig signatures on a standard account".to_string());
+                }
+                transaction.verify_sender()
+            }
+        }
+    }
+
+    /// Apply a transaction to the account state
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), String> {
+        self.authorize_transaction(transaction)?;
+
+        {
+            let sender = self.accounts.get(&transaction.from).ok_or("Sender account not found")?;
+            if sender.nonce != transaction.nonce {
+                return Err(format!("Invalid nonce: expected {}, got {}", sender.nonce, transaction.nonce));
+            }
+        }
+
+        match transaction.payload() {
+            Some(TransactionPayload::MultisigUpdate(update)) => {
+                if transaction.to != transaction.from {
+                    return Err("Multisig update must target the sending account".to_string());
+                }
+                let policy = MultisigPolicy::new(update.signers, update.threshold)?;
+                let account = self.accounts.get_mut(&transaction.from).ok_or("Sender account not found")?;
+                account.kind = AccountKind::Multisig(policy);
+            }
+            None => {
+                self.transfer(&transaction.from, &transaction.to, transaction.amount)?;
+            }
+        }
+
+        self.accounts
+            .get_mut(&transaction.from)
+            .ok_or("Sender account not found")?
+            .increment_nonce();
+        Ok(())
+    }
+
+    /// Apply every transaction of a block, leaving the state untouched if
+    /// any of them fails
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), String> {
+        let mut state = self.clone();
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            state
+                .apply_transaction(transaction)
+                .map_err(|e| format!("Transaction {} of block {}: {}", index, hex::encode(block.hash()), e))?;
+        }
+        *self = state;
+        Ok(())
+    }
+
     pub fn transfer(&mut self, from: &Address, to: &Address, amount: Amount) -> Result<(), String> {
         // Check if sender has sufficient balance
         {
@@ -97,10 +267,10 @@ impl AccountState {
         }
 
         // Perform transfer
-        let sender = self.accounts.get_mut(from).unwrap();
+        let sender = self.accounts.get_mut(from).ok_or("Sender account not found")?;
         sender.debit(amount)?;
 
-        let recipient = self.accounts.get_mut(to).unwrap();
+        let recipient = self.accounts.get_mut(to).ok_or("Recipient account not found")?;
         recipient.credit(amount);
 
         Ok(())
@@ -128,7 +298,7 @@ impl AccountState {
             unbonding_height: None,
         };
 
-        self.stakes.entry(delegator).or_insert_with(Vec::new).push(stake_info);
+        self.stakes.entry(delegator).or_default().push(stake_info);
 
         Ok(())
     }
@@ -174,6 +344,25 @@ impl AccountState {
             .unwrap_or(0)
     }
 
+    /// Build the account state tree, keyed by address, from scratch. A
+    /// running node keeps its tree in `StorageService`, which updates it as
+    /// accounts change.
+    pub fn state_tree(&self) -> SparseMerkleTree {
+        let entries: Vec<(Hash, Hash)> = self
+            .accounts
+            .values()
+            .map(|account| (account.address.0, account.state_hash()))
+            .collect();
+
+        let mut tree = SparseMerkleTree::new();
+        tree.update_batch(&entries);
+        tree
+    }
+
+    pub fn state_root(&self) -> Hash {
+        self.state_tree().root()
+    }
+
     pub fn get_validator_total_stake(&self, validator: &Address) -> Amount {
         self.stakes
             .values()
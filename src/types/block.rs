@@ -1,4 +1,4 @@
-use super::{Hash, Signature, Address, Slot, Epoch, PublicKey};
+use super::{Hash, Signature, Address, Slot, Epoch, PublicKey, VrfProof};
 use crate::types::transaction::Transaction;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -22,11 +22,15 @@ pub struct BlockHeader {
     pub proposer: Address,
     pub proposer_signature: Signature,
     pub randao_reveal: Hash,
+    /// Proof of slot leadership when VRF leader election is enabled
+    #[serde(default)]
+    pub vrf_proof: Option<VrfProof>,
     pub gas_limit: u64,
     pub gas_used: u64,
 }
 
 impl Block {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         height: u64,
         previous_hash: Hash,
@@ -52,6 +56,7 @@ impl Block {
             proposer,
             proposer_signature: Signature([0u8; 64]), // Will be set during signing
             randao_reveal,
+            vrf_proof: None,
             gas_limit,
             gas_used,
         };
@@ -117,6 +122,9 @@ impl Block {
         hasher.update(self.header.epoch.to_le_bytes());
         hasher.update(self.header.proposer.0);
         hasher.update(self.header.randao_reveal);
+        if let Some(vrf_proof) = &self.header.vrf_proof {
+            hasher.update(vrf_proof.0);
+        }
         hasher.update(self.header.gas_limit.to_le_bytes());
         hasher.update(self.header.gas_used.to_le_bytes());
 
@@ -168,6 +176,7 @@ impl Default for Block {
                 proposer: Address([0u8; 32]),
                 proposer_signature: Signature([0u8; 64]),
                 randao_reveal: [0u8; 32],
+                vrf_proof: None,
                 gas_limit: 1_000_000,
                 gas_used: 0,
             },
@@ -1,5 +1,6 @@
 use super::{Hash, Signature, Slot, Epoch, PublicKey};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Attestation {
@@ -13,6 +14,39 @@ pub struct Attestation {
     pub signature: Signature,
 }
 
+impl Attestation {
+    /// Root signed by the attesting validator: every field except the
+    /// signature
+    pub fn signing_root(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(b"BEACON_ATTESTER");
+        hasher.update(self.slot.to_le_bytes());
+        hasher.update(self.beacon_block_root);
+        hasher.update(self.source_epoch.to_le_bytes());
+        hasher.update(self.source_root);
+        hasher.update(self.target_epoch.to_le_bytes());
+        hasher.update(self.target_root);
+        hasher.update(self.validator_index.to_le_bytes());
+        hasher.finalize().into()
+    }
+
+    /// The vote, without the attesting validator
+    pub fn data(&self) -> AttestationData {
+        AttestationData {
+            slot: self.slot,
+            beacon_block_root: self.beacon_block_root,
+            source: Checkpoint {
+                epoch: self.source_epoch,
+                root: self.source_root,
+            },
+            target: Checkpoint {
+                epoch: self.target_epoch,
+                root: self.target_root,
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AttestationData {
     pub slot: Slot,
@@ -21,6 +55,21 @@ pub struct AttestationData {
     pub target: Checkpoint,
 }
 
+impl AttestationData {
+    /// Root signed over an aggregate's vote
+    pub fn signing_root(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(b"AGGREGATE_ATTESTATION");
+        hasher.update(self.slot.to_le_bytes());
+        hasher.update(self.beacon_block_root);
+        hasher.update(self.source.epoch.to_le_bytes());
+        hasher.update(self.source.root);
+        hasher.update(self.target.epoch.to_le_bytes());
+        hasher.update(self.target.root);
+        hasher.finalize().into()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Checkpoint {
     pub epoch: Epoch,
@@ -61,6 +110,94 @@ pub struct IndexedAttestation {
     pub signature: Signature,
 }
 
+/// An aggregated attestation published by a selected aggregator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregateAndProof {
+    pub aggregator_index: u64,
+    pub aggregate: IndexedAttestation,
+    pub selection_proof: Signature,
+    pub signature: Signature,
+}
+
+impl AggregateAndProof {
+    /// Root signed by the aggregator: the aggregate and the selection proof
+    pub fn signing_root(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(b"AGGREGATE_AND_PROOF");
+        hasher.update(self.aggregator_index.to_le_bytes());
+        for index in &self.aggregate.attesting_indices {
+            hasher.update(index.to_le_bytes());
+        }
+        hasher.update(self.aggregate.data.signing_root());
+        hasher.update(self.aggregate.signature.0);
+        hasher.update(self.selection_proof.0);
+        hasher.finalize().into()
+    }
+}
+
+/// A validator's signed request to leave the active set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoluntaryExit {
+    pub epoch: Epoch,
+    pub validator_index: u64,
+    pub signature: Signature,
+}
+
+impl BlockHeaderCore {
+    /// Root signed by the proposer of a header offered as slashing evidence
+    pub fn signing_root(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(b"BEACON_PROPOSER");
+        hasher.update(self.slot.to_le_bytes());
+        hasher.update(self.proposer_index.to_le_bytes());
+        hasher.update(self.parent_root);
+        hasher.update(self.state_root);
+        hasher.update(self.body_root);
+        hasher.finalize().into()
+    }
+}
+
+impl VoluntaryExit {
+    /// Root signed by the exiting validator
+    pub fn signing_root(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(b"VOLUNTARY_EXIT");
+        hasher.update(self.epoch.to_le_bytes());
+        hasher.update(self.validator_index.to_le_bytes());
+        hasher.finalize().into()
+    }
+}
+
+impl ProposerSlashing {
+    /// Two different headers signed by the same proposer for the same slot
+    pub fn is_slashable(&self) -> bool {
+        let header_1 = &self.signed_header_1.header;
+        let header_2 = &self.signed_header_2.header;
+        header_1.slot == header_2.slot
+            && header_1.proposer_index == header_2.proposer_index
+            && header_1 != header_2
+    }
+}
+
+impl AttesterSlashing {
+    /// Attestations sharing a validator that form a double vote or a
+    /// surround vote
+    pub fn is_slashable(&self) -> bool {
+        let data_1 = &self.attestation_1.data;
+        let data_2 = &self.attestation_2.data;
+
+        let double_vote = data_1 != data_2 && data_1.target.epoch == data_2.target.epoch;
+        let surround_vote = data_1.source.epoch < data_2.source.epoch
+            && data_2.target.epoch < data_1.target.epoch;
+        let overlapping = self.attestation_1
+            .attesting_indices
+            .iter()
+            .any(|index| self.attestation_2.attesting_indices.contains(index));
+
+        (double_vote || surround_vote) && overlapping
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CommitteeAssignment {
     pub slot: Slot,
@@ -125,8 +262,23 @@ pub struct PendingAttestation {
     pub proposer_index: u64,
 }
 
+/// How block proposers are chosen for each slot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeaderElection {
+    /// Single stake-weighted proposer, computable by anyone in advance
+    #[default]
+    Deterministic,
+    /// Private election: a validator is eligible when its VRF output over the
+    /// epoch seed and slot falls below a stake-weighted threshold.
+    /// `active_slot_coefficient` is the expected number of eligible proposers
+    /// per slot, in basis points (10000 = 1.0).
+    Vrf { active_slot_coefficient: u16 },
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConsensusConfig {
+    #[serde(default = "default_seconds_per_slot")]
+    pub seconds_per_slot: u64,
     pub slots_per_epoch: u64,
     pub min_genesis_delay: u64,
     pub genesis_delay: u64,
@@ -153,11 +305,18 @@ pub struct ConsensusConfig {
     pub min_slashing_penalty_quotient_bellatrix: u64,
     pub proportional_slashing_multiplier_bellatrix: u64,
     pub inactivity_penalty_quotient_bellatrix: u64,
+    #[serde(default)]
+    pub leader_election: LeaderElection,
+}
+
+fn default_seconds_per_slot() -> u64 {
+    12
 }
 
 impl Default for ConsensusConfig {
     fn default() -> Self {
         ConsensusConfig {
+            seconds_per_slot: default_seconds_per_slot(),
             slots_per_epoch: 32,
             min_genesis_delay: 86400, // 1 day
             genesis_delay: 604800, // 1 week
@@ -184,6 +343,7 @@ impl Default for ConsensusConfig {
             min_slashing_penalty_quotient_bellatrix: 32,
             proportional_slashing_multiplier_bellatrix: 3,
             inactivity_penalty_quotient_bellatrix: 16_777_216,
+            leader_election: LeaderElection::Deterministic,
         }
     }
 }
\ No newline at end of file
@@ -44,6 +44,36 @@ impl<'de> serde::Deserialize<'de> for Signature {
         Ok(Signature(array))
     }
 }
+
+/// ECVRF-EDWARDS25519-SHA512-TAI proof: Gamma (32) || c (16) || s (32)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VrfProof(pub [u8; 80]);
+
+impl serde::Serialize for VrfProof {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&hex::encode(self.0))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for VrfProof {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let bytes = hex::decode(s).map_err(serde::de::Error::custom)?;
+        if bytes.len() != 80 {
+            return Err(serde::de::Error::custom("Invalid VRF proof length"));
+        }
+        let mut array = [0u8; 80];
+        array.copy_from_slice(&bytes);
+        Ok(VrfProof(array))
+    }
+}
+
 pub type Amount = u64;
 pub type Nonce = u64;
 pub type Slot = u64;
@@ -76,15 +106,23 @@ impl From<&str> for Address {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NetworkId {
+    #[serde(alias = "mainnet")]
     Mainnet = 1,
+    #[serde(alias = "testnet")]
     Testnet = 2,
+    #[default]
+    #[serde(alias = "devnet")]
     Devnet = 3,
 }
 
-impl Default for NetworkId {
-    fn default() -> Self {
-        NetworkId::Devnet
+impl NetworkId {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NetworkId::Mainnet => "mainnet",
+            NetworkId::Testnet => "testnet",
+            NetworkId::Devnet => "devnet",
+        }
     }
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use super::{Hash, Signature, Address, Amount, Nonce, PublicKey};
 use crate::types::validator::ValidatorMetadata;
+use crate::crypto::MultiSignature;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -14,7 +15,13 @@ pub struct Transaction {
     pub nonce: Nonce,
     pub data: Vec<u8>,
     pub timestamp: DateTime<Utc>,
+    /// Key of a standard sender, which `from` is derived from; set by `sign`
+    #[serde(default)]
+    pub public_key: PublicKey,
     pub signature: Signature,
+    /// Signatures from a multisig sender's signer set
+    #[serde(default)]
+    pub multisig: Option<MultiSignature>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -26,9 +33,16 @@ pub enum TransactionType {
     Undelegate,
     ValidatorRegistration,
     ValidatorUpdate,
+    MultisigUpdate,
     Contract,
 }
 
+/// Structured payload carried in `Transaction.data`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionPayload {
+    MultisigUpdate(MultisigUpdateTransaction),
+}
+
 impl Transaction {
     pub fn new(
         from: Address,
@@ -48,7 +62,35 @@ impl Transaction {
             nonce,
             data,
             timestamp: Utc::now(),
+            public_key: [0u8; 32],
             signature: Signature([0u8; 64]),
+            multisig: None,
+        }
+    }
+
+    /// Transaction that replaces the signer set and threshold of `account`.
+    /// It must be approved by the account's current signers.
+    pub fn multisig_update(
+        account: Address,
+        signers: Vec<PublicKey>,
+        threshold: usize,
+        gas_limit: u64,
+        gas_price: u64,
+        nonce: Nonce,
+    ) -> Self {
+        let payload = TransactionPayload::MultisigUpdate(MultisigUpdateTransaction { signers, threshold });
+        let data = serde_json::to_vec(&payload).expect("Failed to serialize payload");
+        Self::new(account, account, 0, gas_limit, gas_price, nonce, data)
+    }
+
+    pub fn payload(&self) -> Option<TransactionPayload> {
+        serde_json::from_slice(&self.data).ok()
+    }
+
+    pub fn transaction_type(&self) -> TransactionType {
+        match self.payload() {
+            Some(TransactionPayload::MultisigUpdate(_)) => TransactionType::MultisigUpdate,
+            None => TransactionType::Transfer,
         }
     }
 
@@ -63,9 +105,31 @@ impl Transaction {
         use ed25519_dalek::Signer;
         let hash = self.hash_for_signature();
         let signature = private_key.sign(&hash);
+        self.public_key = private_key.verifying_key().to_bytes();
         self.signature = Signature(signature.to_bytes());
     }
 
+    /// Add one signer's signature for a transaction from a multisig account
+    pub fn add_multisig_signature(&mut self, private_key: &ed25519_dalek::SigningKey) -> Result<(), String> {
+        use ed25519_dalek::Signer;
+        let hash = self.hash_for_signature();
+        let signature = Signature(private_key.sign(&hash).to_bytes());
+        let signer = private_key.verifying_key().to_bytes();
+
+        self.multisig
+            .get_or_insert_with(|| MultiSignature::new(0))
+            .add_signature(signature, signer)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Check the carried multisig against a declared signer set and threshold
+    pub fn verify_multisig(&self, signers: &[PublicKey], threshold: usize) -> Result<(), String> {
+        let multisig = self.multisig.as_ref().ok_or("Missing multisig signatures")?;
+        multisig
+            .verify_with_signers(&self.hash_for_signature(), signers, threshold)
+            .map_err(|e| e.to_string())
+    }
+
     pub fn verify_signature(&self, public_key: &PublicKey) -> Result<(), ed25519_dalek::SignatureError> {
         use ed25519_dalek::Verifier;
         let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(public_key)?;
@@ -74,6 +138,15 @@ impl Transaction {
         verifying_key.verify(&hash, &signature)
     }
 
+    /// Check that a standard sender signed the transaction with the key
+    /// its address is derived from
+    pub fn verify_sender(&self) -> Result<(), String> {
+        if Address::from(self.public_key) != self.from {
+            return Err("Public key does not match the sender address".to_string());
+        }
+        self.verify_signature(&self.public_key).map_err(|_| "Invalid transaction signature".to_string())
+    }
+
     pub fn is_valid(&self) -> bool {
         // Basic validation
         if self.amount == 0 && self.data.is_empty() {
@@ -94,12 +167,14 @@ impl Transaction {
         true
     }
 
-    pub fn fee(&self) -> u64 {
-        self.gas_limit * self.gas_price
+    /// Maximum fee, or None if `gas_limit * gas_price` overflows
+    pub fn fee(&self) -> Option<u64> {
+        self.gas_limit.checked_mul(self.gas_price)
     }
 
-    pub fn total_cost(&self) -> u64 {
-        self.amount + self.fee()
+    /// Amount plus maximum fee, or None on overflow
+    pub fn total_cost(&self) -> Option<u64> {
+        self.fee()?.checked_add(self.amount)
     }
 
     fn hash_for_signature(&self) -> Hash {
@@ -126,6 +201,12 @@ pub struct StakeTransaction {
     pub delegator: Option<Address>, // None for self-stake
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigUpdateTransaction {
+    pub signers: Vec<PublicKey>,
+    pub threshold: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ValidatorRegistrationTransaction {
     pub validator_key: PublicKey,
@@ -33,7 +33,7 @@ pub struct ValidatorMetadata {
     pub contact: Option<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ValidatorPerformance {
     pub blocks_proposed: u64,
     pub blocks_missed: u64,
@@ -122,22 +122,13 @@ impl Validator {
     }
 }
 
-impl Default for ValidatorPerformance {
-    fn default() -> Self {
-        ValidatorPerformance {
-            blocks_proposed: 0,
-            blocks_missed: 0,
-            attestations_made: 0,
-            attestations_missed: 0,
-            slash_count: 0,
-            last_slash_epoch: None,
-        }
-    }
-}
-
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ValidatorSet {
     pub validators: HashMap<Address, Validator>,
+    /// Addresses in registration order. A validator's index is its position
+    /// here; it never changes and is not reused after the validator leaves.
+    #[serde(default)]
+    pub registry: Vec<Address>,
     pub total_stake: Amount,
     pub min_stake: Amount,
     pub max_validators: usize,
@@ -148,6 +139,7 @@ impl ValidatorSet {
     pub fn new(min_stake: Amount, max_validators: usize, epoch: Epoch) -> Self {
         ValidatorSet {
             validators: HashMap::new(),
+            registry: Vec::new(),
             total_stake: 0,
             min_stake,
             max_validators,
@@ -165,6 +157,9 @@ impl ValidatorSet {
         }
 
         self.total_stake += validator.total_stake();
+        if !self.registry.contains(&validator.address) {
+            self.registry.push(validator.address);
+        }
         self.validators.insert(validator.address, validator);
         Ok(())
     }
@@ -179,10 +174,47 @@ impl ValidatorSet {
         }
     }
 
+    /// Eligible validators ordered by address, so that every node derives
+    /// the same proposer and committees from the same set
     pub fn get_active_validators(&self) -> Vec<&Validator> {
-        self.validators
+        let mut active: Vec<&Validator> = self.validators
             .values()
             .filter(|v| v.is_eligible(self.min_stake))
+            .collect();
+        active.sort_by_key(|v| v.address.0);
+        active
+    }
+
+    /// Registry index of a validator, which attestations, committees, exits
+    /// and slashings refer to it by
+    pub fn validator_index(&self, address: &Address) -> Option<u64> {
+        self.registry
+            .iter()
+            .position(|registered| registered == address)
+            .map(|index| index as u64)
+    }
+
+    /// Validator registered at `index`, unless it has since been removed
+    pub fn validator_by_index(&self, index: u64) -> Option<&Validator> {
+        let address = self.registry.get(usize::try_from(index).ok()?)?;
+        self.validators.get(address)
+    }
+
+    /// Registry indices of the eligible validators, in ascending order
+    pub fn active_indices(&self) -> Vec<u64> {
+        self.registry
+            .iter()
+            .enumerate()
+            .filter(|(_, address)| self.validators.get(*address).is_some_and(|v| v.is_eligible(self.min_stake)))
+            .map(|(index, _)| index as u64)
+            .collect()
+    }
+
+    /// Keys of the active validators with their registry index
+    pub fn indexed_keys(&self) -> Vec<(u64, Address, PublicKey)> {
+        self.active_indices()
+            .into_iter()
+            .filter_map(|index| self.validator_by_index(index).map(|v| (index, v.address, v.public_key)))
             .collect()
     }
 
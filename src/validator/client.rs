@@ -0,0 +1,421 @@
+// Validator client - many validator keys managed in one process
+
+use super::{keystore, AttesterDuty, DutyScheduler, ProposerDuty, ValidatorService};
+use crate::consensus::ConsensusEngine;
+use crate::crypto::KeyPair;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tokio::sync::{mpsc, oneshot};
+
+/// Lifecycle of a managed key, derived from the validator set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    /// Not (yet) in the active validator set
+    Pending,
+    Active,
+    /// Leaving or removed from the validator set after exiting
+    Exiting,
+    /// Slashed or jailed; the key no longer performs duties
+    Slashed,
+}
+
+impl KeyStatus {
+    /// Status of `address` in `validator_set`. Exiting and slashed keys keep
+    /// their status once they leave the set.
+    fn from_validator_set(address: &Address, validator_set: &ValidatorSet, previous: KeyStatus) -> Self {
+        let Some(validator) = validator_set.validators.get(address) else {
+            return match previous {
+                KeyStatus::Exiting | KeyStatus::Slashed => previous,
+                KeyStatus::Pending | KeyStatus::Active => KeyStatus::Pending,
+            };
+        };
+        if validator.performance.slash_count > 0 || matches!(validator.status, ValidatorStatus::Jailed) {
+            return KeyStatus::Slashed;
+        }
+        match validator.status {
+            ValidatorStatus::Exiting | ValidatorStatus::Exited => KeyStatus::Exiting,
+            _ if validator.is_eligible(validator_set.min_stake) => KeyStatus::Active,
+            _ => KeyStatus::Pending,
+        }
+    }
+}
+
+/// Summary of a managed key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyInfo {
+    pub address: Address,
+    pub public_key: String,
+    pub status: KeyStatus,
+    /// Position in the active set while active
+    pub validator_index: Option<u64>,
+    pub keystore: Option<PathBuf>,
+}
+
+/// One key and its duties
+#[derive(Debug)]
+pub struct ManagedValidator {
+    pub service: ValidatorService,
+    pub duties: DutyScheduler,
+    pub status: KeyStatus,
+    pub keystore: Option<PathBuf>,
+    address: Address,
+    public_key: PublicKey,
+}
+
+impl ManagedValidator {
+    fn info(&self, validator_set: &ValidatorSet) -> KeyInfo {
+        KeyInfo {
+            address: self.address,
+            public_key: hex::encode(self.public_key),
+            status: self.status,
+            validator_index: self.service.validator_index(validator_set),
+            keystore: self.keystore.clone(),
+        }
+    }
+}
+
+/// Runs duties for every key in a keystore directory. Keys are added and
+/// removed at runtime through an `AdminHandle`.
+#[derive(Debug, Default)]
+pub struct ValidatorClient {
+    keystore_dir: Option<PathBuf>,
+    /// Password keystores are encrypted with, if any
+    keystore_password: Option<String>,
+    validators: HashMap<Address, ManagedValidator>,
+}
+
+impl ValidatorClient {
+    /// Client without keys. Keys added at runtime are written to
+    /// `keystore_dir` when one is given, encrypted with `keystore_password`.
+    pub fn new(keystore_dir: Option<PathBuf>, keystore_password: Option<String>) -> Self {
+        Self {
+            keystore_dir,
+            keystore_password,
+            validators: HashMap::new(),
+        }
+    }
+
+    /// Client with every keystore in `dir` loaded
+    pub fn load(dir: &Path, keystore_password: Option<String>) -> Result<Self, Box<dyn Error>> {
+        let mut client = Self::new(Some(dir.to_path_buf()), keystore_password);
+        for (path, keypair) in keystore::load_keystore_dir(dir, client.keystore_password.as_deref())? {
+            client.insert(keypair, Some(path))?;
+        }
+        Ok(client)
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn validators(&self) -> impl Iterator<Item = &ManagedValidator> {
+        self.validators.values()
+    }
+
+    pub fn get(&self, address: &Address) -> Option<&ManagedValidator> {
+        self.validators.get(address)
+    }
+
+    /// Managed keys, ordered by address
+    pub fn keys(&self, validator_set: &ValidatorSet) -> Vec<KeyInfo> {
+        let mut keys: Vec<KeyInfo> = self.validators.values().map(|validator| validator.info(validator_set)).collect();
+        keys.sort_by_key(|key| key.address.0);
+        keys
+    }
+
+    /// Start managing `keypair`, loaded from `keystore` if given
+    pub fn insert(&mut self, keypair: KeyPair, keystore: Option<PathBuf>) -> Result<Address, Box<dyn Error>> {
+        if self.validators.contains_key(&keypair.address) {
+            return Err(format!("Validator {} is already managed", keypair.address).into());
+        }
+
+        let mut service = ValidatorService::new();
+        service.load_keypair(keypair.private_key)?;
+        service.start_validating()?;
+        self.validators.insert(keypair.address, ManagedValidator {
+            service,
+            duties: DutyScheduler::new(),
+            status: KeyStatus::Pending,
+            keystore,
+            address: keypair.address,
+            public_key: keypair.public_key,
+        });
+        Ok(keypair.address)
+    }
+
+    /// Add a key at runtime, writing its keystore to the keystore directory
+    pub fn add_key(&mut self, keypair: KeyPair) -> Result<Address, Box<dyn Error>> {
+        if self.validators.contains_key(&keypair.address) {
+            return Err(format!("Validator {} is already managed", keypair.address).into());
+        }
+        let keystore = match &self.keystore_dir {
+            Some(dir) => {
+                let path = dir.join(keystore::keystore_file_name(&keypair.address));
+                keystore::write_keystore(&path, &keypair, self.keystore_password.as_deref())?;
+                Some(path)
+            }
+            None => None,
+        };
+        self.insert(keypair, keystore)
+    }
+
+    /// Stop managing a key. Its keystore is renamed with a `.removed`
+    /// suffix so it is not loaded again but the key is not lost.
+    pub fn remove_key(&mut self, address: &Address) -> Result<ManagedValidator, Box<dyn Error>> {
+        let mut validator = self.validators
+            .remove(address)
+            .ok_or_else(|| format!("Validator {} is not managed", address))?;
+        validator.service.stop_validating();
+
+        if let Some(path) = &validator.keystore {
+            let mut removed = path.clone().into_os_string();
+            removed.push(".removed");
+            std::fs::rename(path, &removed)?;
+        }
+        Ok(validator)
+    }
+
+    /// Refresh every key's status from the validator set
+    pub fn update_statuses(&mut self, validator_set: &ValidatorSet) {
+        for (address, validator) in &mut self.validators {
+            validator.status = KeyStatus::from_validator_set(address, validator_set, validator.status);
+        }
+    }
+
+    /// Refresh statuses and compute duties for `epoch` and the next one for
+    /// every active key. Returns the committee assignments of all keys.
+    pub fn update_duties(
+        &mut self,
+        epoch: Epoch,
+        engine: &ConsensusEngine,
+    ) -> Result<Vec<CommitteeAssignment>, Box<dyn Error>> {
+        self.update_statuses(&engine.validator_set);
+
+        let mut assignments = Vec::new();
+        for validator in self.validators.values_mut() {
+            if validator.status == KeyStatus::Active {
+                assignments.extend(validator.duties.update(epoch, &validator.service, engine)?);
+            } else {
+                validator.duties = DutyScheduler::new();
+            }
+        }
+        Ok(assignments)
+    }
+
+    /// Whether duties have been computed for `epoch`
+    pub fn has_duties(&self, epoch: Epoch) -> bool {
+        self.validators
+            .values()
+            .filter(|validator| validator.status == KeyStatus::Active)
+            .all(|validator| validator.duties.epoch_duties(epoch).is_some())
+    }
+
+    /// Keys proposing at `slot`, ordered by address. Under VRF election
+    /// several keys may be eligible for one slot.
+    pub fn proposer_duties(&self, slot: Slot) -> Vec<(Address, ProposerDuty)> {
+        let mut duties: Vec<(Address, ProposerDuty)> = self.validators
+            .values()
+            .filter_map(|validator| Some((validator.address, validator.duties.proposer_duty(slot)?.clone())))
+            .collect();
+        duties.sort_by_key(|(address, _)| address.0);
+        duties
+    }
+
+    /// Keys attesting at `slot`, ordered by address
+    pub fn attester_duties(&self, slot: Slot) -> Vec<(Address, AttesterDuty)> {
+        let mut duties: Vec<(Address, AttesterDuty)> = self.validators
+            .values()
+            .filter_map(|validator| Some((validator.address, validator.duties.attester_duty(slot)?.clone())))
+            .collect();
+        duties.sort_by_key(|(address, _)| address.0);
+        duties
+    }
+
+    /// Signer for a managed key
+    pub fn service(&self, address: &Address) -> Option<&ValidatorService> {
+        self.validators.get(address).map(|validator| &validator.service)
+    }
+
+    /// Offer an attestation to every key aggregating its committee
+    pub fn observe_attestation(&mut self, attestation: &Attestation) {
+        for validator in self.validators.values_mut() {
+            validator.duties.observe_attestation(attestation);
+        }
+    }
+
+    /// Aggregates for `slot` from every key selected as aggregator
+    pub fn aggregate(&mut self, slot: Slot) -> Vec<Result<AggregateAndProof, Box<dyn Error>>> {
+        self.validators
+            .values_mut()
+            .filter_map(|validator| validator.duties.aggregate(slot, &validator.service))
+            .collect()
+    }
+
+    /// Answer an admin command. Added keys get their duties for the
+    /// engine's current epoch straight away.
+    pub fn handle_admin_command(&mut self, command: AdminCommand, engine: &ConsensusEngine) {
+        match command {
+            AdminCommand::ListKeys { response } => {
+                let _ = response.send(self.keys(&engine.validator_set));
+            }
+            AdminCommand::AddKey { private_key, response } => {
+                let result = KeyPair::from_hex(&private_key)
+                    .map_err(|e| e.to_string())
+                    .and_then(|keypair| self.add_key(keypair).map_err(|e| e.to_string()))
+                    .and_then(|address| {
+                        let validator = self.validators.get_mut(&address).expect("key was just added");
+                        validator.status = KeyStatus::from_validator_set(&address, &engine.validator_set, validator.status);
+                        if validator.status == KeyStatus::Active {
+                            validator.duties
+                                .update(engine.current_epoch, &validator.service, engine)
+                                .map_err(|e| e.to_string())?;
+                        }
+                        Ok(validator.info(&engine.validator_set))
+                    });
+                let _ = response.send(result);
+            }
+            AdminCommand::RemoveKey { address, response } => {
+                let result = self.remove_key(&address)
+                    .map(|validator| validator.info(&engine.validator_set))
+                    .map_err(|e| e.to_string());
+                let _ = response.send(result);
+            }
+        }
+    }
+}
+
+/// Requests to change the managed keys of a running validator client
+#[derive(Debug)]
+pub enum AdminCommand {
+    ListKeys {
+        response: oneshot::Sender<Vec<KeyInfo>>,
+    },
+    AddKey {
+        /// Hex-encoded private key
+        private_key: String,
+        response: oneshot::Sender<Result<KeyInfo, String>>,
+    },
+    RemoveKey {
+        address: Address,
+        response: oneshot::Sender<Result<KeyInfo, String>>,
+    },
+}
+
+/// Handle for managing a running node's validator keys
+#[derive(Debug, Clone)]
+pub struct AdminHandle {
+    command_sender: mpsc::UnboundedSender<AdminCommand>,
+}
+
+impl AdminHandle {
+    /// Handle and the receiver its commands arrive on
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<AdminCommand>) {
+        let (command_sender, command_receiver) = mpsc::unbounded_channel();
+        (Self { command_sender }, command_receiver)
+    }
+
+    pub async fn list_keys(&self) -> Result<Vec<KeyInfo>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(AdminCommand::ListKeys { response: tx })?;
+        Ok(rx.await?)
+    }
+
+    pub async fn add_key(&self, private_key: String) -> Result<KeyInfo, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(AdminCommand::AddKey {
+            private_key,
+            response: tx,
+        })?;
+        Ok(rx.await??)
+    }
+
+    pub async fn remove_key(&self, address: Address) -> Result<KeyInfo, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_sender.send(AdminCommand::RemoveKey {
+            address,
+            response: tx,
+        })?;
+        Ok(rx.await??)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::engine_with_validators;
+
+    #[test]
+    fn test_client_loads_keystores_and_tracks_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys: Vec<KeyPair> = (0..3).map(|_| KeyPair::generate()).collect();
+        for key in &keys {
+            keystore::write_keystore(&dir.path().join(keystore::keystore_file_name(&key.address)), key, None).unwrap();
+        }
+        // Only the first two keys are registered validators
+        let mut engine = engine_with_validators(&keys[..2]);
+
+        let mut client = ValidatorClient::load(dir.path(), None).unwrap();
+        assert_eq!(client.len(), 3);
+        client.update_duties(0, &engine).unwrap();
+        assert_eq!(client.get(&keys[0].address).unwrap().status, KeyStatus::Active);
+        assert_eq!(client.get(&keys[2].address).unwrap().status, KeyStatus::Pending);
+
+        // Duties cover both active keys: one proposer per slot between them
+        let proposals: usize = (0..4).map(|slot| client.proposer_duties(slot).len()).sum();
+        assert_eq!(proposals, 4);
+        assert_eq!(client.attester_duties(1).len(), 2);
+
+        engine.validator_set.validators.get_mut(&keys[0].address).unwrap().slash(1, 0);
+        engine.validator_set.validators.get_mut(&keys[1].address).unwrap().status = ValidatorStatus::Exiting;
+        client.update_duties(1, &engine).unwrap();
+        assert_eq!(client.get(&keys[0].address).unwrap().status, KeyStatus::Slashed);
+        assert_eq!(client.get(&keys[1].address).unwrap().status, KeyStatus::Exiting);
+        assert!(client.attester_duties(5).is_empty());
+
+        // Exited keys stay exiting once they leave the set
+        engine.validator_set.remove_validator(&keys[1].address).unwrap();
+        client.update_statuses(&engine.validator_set);
+        assert_eq!(client.get(&keys[1].address).unwrap().status, KeyStatus::Exiting);
+    }
+
+    #[tokio::test]
+    async fn test_admin_handle_adds_and_removes_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = KeyPair::generate();
+        let engine = engine_with_validators(std::slice::from_ref(&key));
+        let mut client = ValidatorClient::new(Some(dir.path().to_path_buf()), None);
+        let (admin, mut commands) = AdminHandle::channel();
+
+        let (private_key, address) = (key.to_hex(), key.address);
+        let task = tokio::spawn(async move {
+            let added = admin.add_key(private_key.clone()).await.unwrap();
+            assert!(admin.add_key(private_key).await.is_err());
+            let keys = admin.list_keys().await.unwrap();
+            let removed = admin.remove_key(address).await.unwrap();
+            (added, keys, removed)
+        });
+        while let Some(command) = commands.recv().await {
+            client.handle_admin_command(command, &engine);
+        }
+        let (added, keys, removed) = task.await.unwrap();
+
+        assert_eq!(added.address, key.address);
+        assert_eq!(added.status, KeyStatus::Active);
+        assert_eq!(added.validator_index, Some(0));
+        assert_eq!(keys, vec![added.clone()]);
+        assert_eq!(removed.address, key.address);
+        assert!(client.is_empty());
+
+        // The keystore is kept under a new name and not loaded again
+        assert!(ValidatorClient::load(dir.path(), None).unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,266 @@
+// Validator duties - proposer and committee assignments per epoch
+
+use super::ValidatorService;
+use crate::consensus::ConsensusEngine;
+use crate::crypto::Hasher;
+use crate::types::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Expected number of aggregators selected from each committee
+pub const TARGET_AGGREGATORS_PER_COMMITTEE: u64 = 16;
+
+/// Message signed as an aggregator selection proof for `slot`
+pub fn selection_proof_root(slot: Slot) -> Hash {
+    Hasher::hash_with_domain(b"SELECTION_PROOF", &slot.to_le_bytes())
+}
+
+/// Whether `selection_proof` selects its signer as an aggregator for a
+/// committee of `committee_size` validators
+pub fn is_aggregator(committee_size: usize, selection_proof: &Signature) -> bool {
+    let modulo = (committee_size as u64 / TARGET_AGGREGATORS_PER_COMMITTEE).max(1);
+    let hash = Hasher::hash(&selection_proof.0);
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(value).is_multiple_of(modulo)
+}
+
+/// A slot this validator proposes at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProposerDuty {
+    pub slot: Slot,
+    /// Leadership proof for the block header under VRF leader election
+    pub vrf_proof: Option<VrfProof>,
+}
+
+/// A committee this validator attests in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttesterDuty {
+    pub validator_index: u64,
+    pub assignment: CommitteeAssignment,
+    pub selection_proof: Signature,
+    pub is_aggregator: bool,
+}
+
+/// Duties of one validator for one epoch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochDuties {
+    pub epoch: Epoch,
+    /// None while the validator is not in the active set
+    pub validator_index: Option<u64>,
+    pub proposals: Vec<ProposerDuty>,
+    pub attestations: Vec<AttesterDuty>,
+}
+
+impl EpochDuties {
+    /// Compute the duties of `validator` in `epoch` from the engine's
+    /// current validator set
+    pub fn compute(
+        epoch: Epoch,
+        validator: &ValidatorService,
+        engine: &ConsensusEngine,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut duties = EpochDuties {
+            epoch,
+            validator_index: validator.validator_index(&engine.validator_set),
+            proposals: Vec::new(),
+            attestations: Vec::new(),
+        };
+        let Some(validator_index) = duties.validator_index else {
+            return Ok(duties);
+        };
+
+        // Proposals build on the current head, whose chain fixes the seed
+        // of `epoch` once epoch - 2 has ended
+        let mix = engine.seed_mix(&engine.get_head().unwrap_or_default(), epoch);
+        let start_slot = engine.epoch_to_slot(epoch);
+        for slot in start_slot..start_slot + engine.config.slots_per_epoch {
+            match engine.config.leader_election {
+                LeaderElection::Deterministic => {
+                    if engine.get_proposer_for_slot(slot).ok() == validator.get_address() {
+                        duties.proposals.push(ProposerDuty { slot, vrf_proof: None });
+                    }
+                }
+                LeaderElection::Vrf { .. } => {
+                    let proof = validator.prove_slot_leadership(slot, &mix, &engine.proposer_selector, &engine.validator_set)?;
+                    if let Some(proof) = proof {
+                        duties.proposals.push(ProposerDuty { slot, vrf_proof: Some(proof) });
+                    }
+                }
+            }
+        }
+
+        for assignment in engine.proposer_selector.get_committee_assignments(epoch, validator_index, &engine.validator_set) {
+            let selection_proof = validator.sign_selection_proof(assignment.slot)?;
+            duties.attestations.push(AttesterDuty {
+                validator_index,
+                is_aggregator: is_aggregator(assignment.validators.len(), &selection_proof),
+                assignment,
+                selection_proof,
+            });
+        }
+
+        Ok(duties)
+    }
+}
+
+/// Tracks a validator's duties for the current and next epoch and collects
+/// attestations for the committees it aggregates
+#[derive(Debug, Default)]
+pub struct DutyScheduler {
+    epochs: BTreeMap<Epoch, EpochDuties>,
+    /// Attestations from committees this validator aggregates, by slot
+    aggregation_pool: HashMap<Slot, Vec<Attestation>>,
+}
+
+impl DutyScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recompute duties for `epoch` and the epoch after it from the current
+    /// validator set, dropping earlier epochs. Returns the committee
+    /// assignments of both epochs, for subnet subscriptions.
+    pub fn update(
+        &mut self,
+        epoch: Epoch,
+        validator: &ValidatorService,
+        engine: &ConsensusEngine,
+    ) -> Result<Vec<CommitteeAssignment>, Box<dyn std::error::Error>> {
+        for epoch in [epoch, epoch + 1] {
+            self.epochs.insert(epoch, EpochDuties::compute(epoch, validator, engine)?);
+        }
+        self.epochs.retain(|known, _| *known >= epoch);
+
+        let start_slot = engine.epoch_to_slot(epoch);
+        self.aggregation_pool.retain(|slot, _| *slot >= start_slot);
+
+        Ok(self.epochs
+            .values()
+            .flat_map(|duties| duties.attestations.iter().map(|duty| duty.assignment.clone()))
+            .collect())
+    }
+
+    pub fn epoch_duties(&self, epoch: Epoch) -> Option<&EpochDuties> {
+        self.epochs.get(&epoch)
+    }
+
+    pub fn proposer_duty(&self, slot: Slot) -> Option<&ProposerDuty> {
+        self.epochs
+            .values()
+            .flat_map(|duties| &duties.proposals)
+            .find(|duty| duty.slot == slot)
+    }
+
+    pub fn attester_duty(&self, slot: Slot) -> Option<&AttesterDuty> {
+        self.epochs
+            .values()
+            .flat_map(|duties| &duties.attestations)
+            .find(|duty| duty.assignment.slot == slot)
+    }
+
+    /// Keep a valid attestation for aggregation when this validator
+    /// aggregates the attester's committee
+    pub fn observe_attestation(&mut self, attestation: &Attestation) {
+        let Some(duty) = self.attester_duty(attestation.slot) else {
+            return;
+        };
+        if !duty.is_aggregator || !duty.assignment.validators.contains(&attestation.validator_index) {
+            return;
+        }
+
+        let pool = self.aggregation_pool.entry(attestation.slot).or_default();
+        if !pool.iter().any(|seen| seen.validator_index == attestation.validator_index) {
+            pool.push(attestation.clone());
+        }
+    }
+
+    /// Aggregate the attestations collected for `slot` that agree with this
+    /// validator's own vote. None when it is not an aggregator for the slot
+    /// or has not attested.
+    pub fn aggregate(
+        &mut self,
+        slot: Slot,
+        validator: &ValidatorService,
+    ) -> Option<Result<AggregateAndProof, Box<dyn std::error::Error>>> {
+        let duty = self.attester_duty(slot).filter(|duty| duty.is_aggregator)?.clone();
+        let pool = self.aggregation_pool.remove(&slot)?;
+        let data = pool
+            .iter()
+            .find(|attestation| attestation.validator_index == duty.validator_index)?
+            .data();
+
+        let attestations: Vec<Attestation> = pool.into_iter().filter(|attestation| attestation.data() == data).collect();
+        Some(validator.create_aggregate_and_proof(duty.validator_index, duty.selection_proof, &attestations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::engine_with_validators;
+    use crate::crypto::{CryptoProvider, KeyPair};
+
+    fn service(key: &KeyPair) -> ValidatorService {
+        let mut validator = ValidatorService::new();
+        validator.load_keypair(key.private_key).unwrap();
+        validator
+    }
+
+    #[test]
+    fn test_duties_cover_every_slot_once() {
+        let keys: Vec<KeyPair> = (0..4).map(|_| KeyPair::generate()).collect();
+        let engine = engine_with_validators(&keys);
+
+        let mut proposals = Vec::new();
+        for key in &keys {
+            let validator = service(key);
+            let mut scheduler = DutyScheduler::new();
+            let assignments = scheduler.update(1, &validator, &engine).unwrap();
+
+            // Every validator sits in every committee with so few validators
+            assert_eq!(assignments.len(), 8);
+            let duties = scheduler.epoch_duties(1).unwrap();
+            assert_eq!(duties.validator_index, validator.validator_index(&engine.validator_set));
+            proposals.extend(duties.proposals.iter().map(|duty| duty.slot));
+            assert!(scheduler.epoch_duties(2).is_some());
+
+            scheduler.update(2, &validator, &engine).unwrap();
+            assert!(scheduler.epoch_duties(1).is_none());
+        }
+
+        proposals.sort_unstable();
+        assert_eq!(proposals, vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_aggregator_signs_agreeing_attestations() {
+        let keys: Vec<KeyPair> = (0..4).map(|_| KeyPair::generate()).collect();
+        let engine = engine_with_validators(&keys);
+        let services: Vec<ValidatorService> = keys.iter().map(service).collect();
+
+        // Small committees make every member an aggregator
+        let aggregator = &services[0];
+        let mut scheduler = DutyScheduler::new();
+        scheduler.update(0, aggregator, &engine).unwrap();
+        let duty = scheduler.attester_duty(1).unwrap().clone();
+        assert!(duty.is_aggregator);
+
+        let justified = engine.fork_choice.justified_checkpoint.clone();
+        for (index, validator) in services.iter().enumerate() {
+            let validator_index = validator.validator_index(&engine.validator_set).unwrap();
+            // The last validator votes for a different head
+            let root = if index == 3 { [2u8; 32] } else { [1u8; 32] };
+            let attestation = validator.create_attestation(1, root, validator_index, &justified, 0).unwrap();
+            assert!(engine.validate_attestation(&attestation).is_ok());
+            scheduler.observe_attestation(&attestation);
+        }
+
+        let aggregate = scheduler.aggregate(1, aggregator).unwrap().unwrap();
+        assert_eq!(aggregate.aggregator_index, duty.validator_index);
+        assert_eq!(aggregate.aggregate.attesting_indices.len(), 3);
+        assert_eq!(aggregate.aggregate.data.beacon_block_root, [1u8; 32]);
+        CryptoProvider::verify(&keys[0].public_key, &aggregate.signing_root(), &aggregate.signature).unwrap();
+        CryptoProvider::verify(&keys[0].public_key, &selection_proof_root(1), &aggregate.selection_proof).unwrap();
+        assert!(scheduler.aggregate(1, aggregator).is_none());
+    }
+}
@@ -0,0 +1,151 @@
+// Validator keystores - JSON key files and keystore directories
+
+use crate::crypto::KeyPair;
+use crate::types::Address;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// PBKDF2-HMAC-SHA256 rounds deriving a keystore's encryption key from its
+/// password
+pub const KEYSTORE_KDF_ROUNDS: u32 = 262_144;
+
+const KDF: &str = "pbkdf2-hmac-sha256";
+const CIPHER: &str = "chacha20-poly1305";
+
+/// File name of the keystore for `address` inside a keystore directory
+pub fn keystore_file_name(address: &Address) -> String {
+    format!("{}.json", address)
+}
+
+/// Keystore file contents for `keypair`. With a password the private key is
+/// encrypted under a key derived from it; without one it is stored as hex.
+pub fn keystore_json(keypair: &KeyPair, password: Option<&str>) -> serde_json::Value {
+    let mut value = serde_json::json!({
+        "public_key": hex::encode(keypair.public_key),
+        "address": keypair.address.to_string(),
+        "created_at": chrono::Utc::now().to_rfc3339()
+    });
+    match password {
+        Some(password) => {
+            let mut salt = [0u8; 32];
+            let mut nonce = [0u8; 12];
+            rand::rngs::OsRng.fill_bytes(&mut salt);
+            rand::rngs::OsRng.fill_bytes(&mut nonce);
+            let cipher = keystore_cipher(password, &salt, KEYSTORE_KDF_ROUNDS);
+            let ciphertext = cipher
+                .encrypt(Nonce::from_slice(&nonce), keypair.private_key.as_slice())
+                .expect("encrypting a 32 byte key cannot fail");
+            value["crypto"] = serde_json::json!({
+                "kdf": KDF,
+                "rounds": KEYSTORE_KDF_ROUNDS,
+                "salt": hex::encode(salt),
+                "cipher": CIPHER,
+                "nonce": hex::encode(nonce),
+                "ciphertext": hex::encode(ciphertext)
+            });
+        }
+        None => value["private_key"] = serde_json::json!(hex::encode(keypair.private_key)),
+    }
+    value
+}
+
+fn keystore_cipher(password: &str, salt: &[u8], rounds: u32) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password.as_bytes(), salt, rounds, &mut key);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// Read a keystore. Encrypted keystores need `password`; a password given
+/// for an unencrypted keystore is an error, so a configured password is
+/// never silently ignored.
+pub fn read_keystore(path: &Path, password: Option<&str>) -> Result<KeyPair, Box<dyn std::error::Error>> {
+    let key_data: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let keypair = match (key_data.get("crypto"), password) {
+        (Some(crypto), Some(password)) => decrypt_private_key(crypto, password)?,
+        (Some(_), None) => return Err("Keystore is encrypted but no password is configured".into()),
+        (None, Some(_)) => return Err("Keystore is not encrypted but a keystore password is configured".into()),
+        (None, None) => {
+            let private_key = key_data["private_key"]
+                .as_str()
+                .ok_or("Invalid key file format")?;
+            KeyPair::from_hex(private_key)?
+        }
+    };
+
+    if let Some(address) = key_data["address"].as_str() {
+        if address != keypair.address.to_string() {
+            return Err("Keystore address does not match its key".into());
+        }
+    }
+    Ok(keypair)
+}
+
+fn decrypt_private_key(crypto: &serde_json::Value, password: &str) -> Result<KeyPair, Box<dyn std::error::Error>> {
+    if crypto["kdf"].as_str() != Some(KDF) || crypto["cipher"].as_str() != Some(CIPHER) {
+        return Err("Unsupported keystore encryption".into());
+    }
+    let field = |name: &str| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let value = crypto[name].as_str().ok_or_else(|| format!("Keystore is missing crypto.{}", name))?;
+        Ok(hex::decode(value)?)
+    };
+    let rounds = crypto["rounds"]
+        .as_u64()
+        .and_then(|rounds| u32::try_from(rounds).ok())
+        .ok_or("Keystore is missing crypto.rounds")?;
+    let nonce = field("nonce")?;
+    if nonce.len() != 12 {
+        return Err("Invalid keystore nonce".into());
+    }
+
+    let private_key = keystore_cipher(password, &field("salt")?, rounds)
+        .decrypt(Nonce::from_slice(&nonce), field("ciphertext")?.as_slice())
+        .map_err(|_| "Wrong keystore password")?;
+    let private_key: [u8; 32] = private_key.try_into().map_err(|_| "Invalid keystore key length")?;
+    Ok(KeyPair::from_private_key(private_key)?)
+}
+
+/// Write a keystore readable only by its owner. The file is written to a
+/// temporary name first and renamed, so it is never seen half-written.
+pub fn write_keystore(path: &Path, keypair: &KeyPair, password: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let content = serde_json::to_string_pretty(&keystore_json(keypair, password))?;
+    let temp = path.with_extension("json.tmp");
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp)?;
+    #[cfg(unix)]
+    {
+        // The mode only applies to new files
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Load every `.json` keystore in `dir`, ordered by file name
+pub fn load_keystore_dir(dir: &Path, password: Option<&str>) -> Result<Vec<(PathBuf, KeyPair)>, Box<dyn std::error::Error>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let keypair = read_keystore(&path, password).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok((path, keypair))
+        })
+        .collect()
+}
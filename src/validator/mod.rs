@@ -1,7 +1,15 @@
 // Validator module - validator operations and management
 
+pub mod client;
+pub mod duties;
+pub mod keystore;
+
+pub use client::*;
+pub use duties::*;
+
 use crate::types::*;
 use crate::crypto::*;
+use crate::consensus::ProposerSelector;
 
 pub struct ValidatorService {
     keypair: Option<KeyPair>,
@@ -51,23 +59,107 @@ impl ValidatorService {
         }
     }
 
-    pub fn create_attestation(&self, slot: Slot, beacon_block_root: Hash) -> Result<Attestation, Box<dyn std::error::Error>> {
-        let _keypair = self.keypair.as_ref().ok_or("No keypair available")?;
+    /// Check VRF slot leadership on a chain with randomness `mix`, returning
+    /// the proof to place in the block header when this validator may
+    /// propose
+    pub fn prove_slot_leadership(
+        &self,
+        slot: Slot,
+        mix: &Hash,
+        selector: &ProposerSelector,
+        validator_set: &ValidatorSet,
+    ) -> Result<Option<VrfProof>, Box<dyn std::error::Error>> {
+        let keypair = self.keypair.as_ref().ok_or("No keypair available")?;
+        Ok(selector.prove_leadership(slot, mix, keypair, validator_set)?)
+    }
+
+    /// Registry index of this validator while it is active, which
+    /// committees and attestations refer to
+    pub fn validator_index(&self, validator_set: &ValidatorSet) -> Option<u64> {
+        let address = self.get_address()?;
+        validator_set
+            .validators
+            .get(&address)
+            .filter(|validator| validator.is_eligible(validator_set.min_stake))?;
+        validator_set.validator_index(&address)
+    }
 
-        let attestation = Attestation {
+    /// Sign an attestation voting for `beacon_block_root` at `slot`, with
+    /// the justified checkpoint as its source
+    pub fn create_attestation(
+        &self,
+        slot: Slot,
+        beacon_block_root: Hash,
+        validator_index: u64,
+        source: &Checkpoint,
+        target_epoch: Epoch,
+    ) -> Result<Attestation, Box<dyn std::error::Error>> {
+        let keypair = self.keypair.as_ref().ok_or("No keypair available")?;
+
+        // Fork choice counts the target root as the vote, so it is the head
+        // rather than the epoch boundary block
+        let mut attestation = Attestation {
             slot,
             beacon_block_root,
-            source_epoch: 0, // TODO: Get from consensus state
-            source_root: [0u8; 32],
-            target_epoch: slot / 32, // Assuming 32 slots per epoch
+            source_epoch: source.epoch,
+            source_root: source.root,
+            target_epoch,
             target_root: beacon_block_root,
-            validator_index: 0, // TODO: Get validator index
-            signature: Signature([0u8; 64]), // Will be filled by signing
+            validator_index,
+            signature: Signature([0u8; 64]),
         };
-
-        // TODO: Sign the attestation
+        attestation.signature = CryptoProvider::sign(&keypair.private_key, &attestation.signing_root())?;
         Ok(attestation)
     }
+
+    /// Proof that this validator may aggregate for `slot`
+    pub fn sign_selection_proof(&self, slot: Slot) -> Result<Signature, Box<dyn std::error::Error>> {
+        let keypair = self.keypair.as_ref().ok_or("No keypair available")?;
+        Ok(CryptoProvider::sign(&keypair.private_key, &selection_proof_root(slot))?)
+    }
+
+    /// Aggregate attestations that share one vote into a signed
+    /// `AggregateAndProof`
+    pub fn create_aggregate_and_proof(
+        &self,
+        aggregator_index: u64,
+        selection_proof: Signature,
+        attestations: &[Attestation],
+    ) -> Result<AggregateAndProof, Box<dyn std::error::Error>> {
+        let keypair = self.keypair.as_ref().ok_or("No keypair available")?;
+        let data = attestations.first().ok_or("No attestations to aggregate")?.data();
+        if attestations.iter().any(|attestation| attestation.data() != data) {
+            return Err("Attestations vote for different data".into());
+        }
+
+        let mut attesting_indices: Vec<u64> = attestations.iter().map(|a| a.validator_index).collect();
+        attesting_indices.sort_unstable();
+        attesting_indices.dedup();
+
+        let signature = CryptoProvider::sign(&keypair.private_key, &data.signing_root())?;
+        let mut aggregate = AggregateAndProof {
+            aggregator_index,
+            aggregate: IndexedAttestation {
+                attesting_indices,
+                data,
+                signature,
+            },
+            selection_proof,
+            signature: Signature([0u8; 64]),
+        };
+        aggregate.signature = CryptoProvider::sign(&keypair.private_key, &aggregate.signing_root())?;
+        Ok(aggregate)
+    }
+}
+
+// The key is left out so that it never ends up in logs
+impl std::fmt::Debug for ValidatorService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValidatorService")
+            .field("address", &self.get_address())
+            .field("is_active", &self.is_active)
+            .finish()
+    }
 }
 
 impl Default for ValidatorService {